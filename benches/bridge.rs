@@ -0,0 +1,158 @@
+//
+// Measures messages per second over a loopback TCP bridge. The frame benches
+// write the same frames the way the TX thread used to, with a write for each
+// part of each message, and the way it does now, with a batch of frames going
+// out in one vectored write. The net bench sends through a whole bridge.
+//
+#![allow(unused_must_use)]
+
+extern crate test;
+extern crate water;
+
+use std::mem::transmute_copy;
+use std::io::{TcpListener, TcpStream, Listener, Acceptor};
+use std::io::timer::sleep;
+use std::time::duration::Duration;
+use std::thread::Thread;
+
+use test::Bencher;
+
+use water::get_time;
+use water::timespec::sub;
+use water::timespec::NSINSEC;
+use water::Net;
+use water::Message;
+use water::bridge::frame::Frame;
+use water::bridge::link::Link;
+use water::bridge::link::StreamLink;
+use water::bridge::link::readunit;
+use water::bridge::link::writeunitv;
+
+const COUNT: usize = 100000;
+const SIZE: usize = 64;
+const BATCH: usize = 64;
+
+pub struct BencherHack {
+    iterations: u64,
+    dur:        Duration,
+    bytes:      u64,
+}
+
+fn main() {
+    report("frames unbatched", bridge_frames_run("localhost:34237", false, COUNT));
+    report("frames batched", bridge_frames_run("localhost:34238", true, COUNT));
+    report("net", bridge_net_run("localhost:34239", COUNT));
+}
+
+fn report(name: &str, dur: Duration) {
+    let ms = dur.num_milliseconds() as u64;
+    println!("{}: {} messages in {}ms, {} messages/sec", name, COUNT, ms, COUNT as u64 * 1000 / (ms + 1));
+}
+
+fn hack(b: &mut Bencher, dur: Duration) {
+    let h: &mut BencherHack = unsafe { transmute_copy(&b) };
+    h.iterations = COUNT as u64;
+    h.dur = dur;
+    h.bytes = (COUNT * SIZE) as u64;
+}
+
+#[bench]
+fn bridge_framesunbatched(b: &mut Bencher) {
+    let dur = bridge_frames_run("localhost:34237", false, COUNT);
+    hack(b, dur);
+}
+
+#[bench]
+fn bridge_framesbatched(b: &mut Bencher) {
+    let dur = bridge_frames_run("localhost:34238", true, COUNT);
+    hack(b, dur);
+}
+
+#[bench]
+fn bridge_net(b: &mut Bencher) {
+    let dur = bridge_net_run("localhost:34239", COUNT);
+    hack(b, dur);
+}
+
+fn elapsed<F: FnOnce()>(f: F) -> Duration {
+    let start = get_time();
+    f();
+    let dur = sub(get_time(), start);
+    Duration::nanoseconds(dur.sec * NSINSEC + dur.nsec as i64)
+}
+
+fn connect(addr: &str) -> TcpStream {
+    loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => return stream,
+            Err(_) => sleep(Duration::milliseconds(10)),
+        }
+    }
+}
+
+fn bridge_frames_run(addr: &'static str, batched: bool, n: usize) -> Duration {
+    let mut acceptor = TcpListener::bind(addr).listen().unwrap();
+
+    let reader = Thread::scoped(move || {
+        let mut stream = acceptor.accept().unwrap();
+        for _ in range(0us, n) {
+            readunit(&mut stream, 1024 * 1024).unwrap();
+        }
+    });
+
+    let frame = Frame::from_message(Message::new_raw(SIZE)).unwrap();
+    let header = frame.encodeheader();
+    let parts: Vec<&[u8]> = vec![header.as_slice(), frame.payload.as_slice()];
+
+    let mut stream = connect(addr);
+
+    elapsed(move || {
+        if batched {
+            let mut link = StreamLink::new(stream);
+            let units: Vec<Vec<&[u8]>> = range(0us, BATCH).map(|_| parts.clone()).collect();
+            for _ in range(0us, n / BATCH) {
+                link.sendbatch(units.as_slice()).unwrap();
+            }
+            link.sendbatch(units.slice_to(n % BATCH)).unwrap();
+        } else {
+            for _ in range(0us, n) {
+                writeunitv(&mut stream, parts.as_slice()).unwrap();
+            }
+        }
+        reader.join();
+    })
+}
+
+fn bridge_net_run(addr: &str, n: usize) -> Duration {
+    let net1 = Net::new(100);
+    let net2 = Net::new(200);
+    let ep1 = net1.new_endpoint();
+    let ep2 = net2.new_endpoint();
+
+    let mut listener = net1.tcplisten(String::from_str(addr));
+    let mut connector = net2.tcpconnect(String::from_str(addr));
+
+    while listener.getnegcount() < 1 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    let dur = elapsed(|| {
+        let sender = Thread::scoped(move || {
+            for _ in range(0us, n) {
+                let mut msg = Message::new_raw(SIZE);
+                msg.dstsid = 200;
+                ep1.send(msg);
+            }
+        });
+
+        for _ in range(0us, n) {
+            ep2.recvorblockforever().unwrap();
+        }
+
+        sender.join();
+    });
+
+    listener.terminate();
+    connector.terminate();
+    dur
+}
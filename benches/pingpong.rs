@@ -125,13 +125,13 @@ fn pingpong_water_run(m: uint, n: uint) {
         let ta = Thread::spawn(move || {
             for _ in range(0, n) {
                 epa.sendsynctype(FooApple);
-                epa.recvorblockforever().ok();
+                epa.recvorblockforever().unwrap();
             }
         });
 
         let tb = Thread::spawn(move || {
             for _ in range(0, n) {
-                epb.recvorblockforever().ok();
+                epb.recvorblockforever().unwrap();
                 epb.sendsynctype(FooGrape { field: [0u8;BIGSIZE] });
             }
         });
@@ -0,0 +1,93 @@
+//
+// Measures how the cost of sending a message scales with the number of
+// endpoints on a net. A unicast or group send should stay roughly flat
+// as the net grows, while a broadcast has to touch every endpoint.
+//
+extern crate test;
+extern crate water;
+
+use test::Bencher;
+
+use water::Net;
+use water::Endpoint;
+use water::Message;
+use water::ID;
+
+const GROUP: ID = 0x3000;
+
+#[bench]
+fn routing_unicast10(b: &mut Bencher) { routing_unicast_run(b, 10); }
+#[bench]
+fn routing_unicast100(b: &mut Bencher) { routing_unicast_run(b, 100); }
+#[bench]
+fn routing_unicast1000(b: &mut Bencher) { routing_unicast_run(b, 1000); }
+#[bench]
+fn routing_unicast10000(b: &mut Bencher) { routing_unicast_run(b, 10000); }
+
+#[bench]
+fn routing_group10(b: &mut Bencher) { routing_group_run(b, 10); }
+#[bench]
+fn routing_group100(b: &mut Bencher) { routing_group_run(b, 100); }
+#[bench]
+fn routing_group1000(b: &mut Bencher) { routing_group_run(b, 1000); }
+#[bench]
+fn routing_group10000(b: &mut Bencher) { routing_group_run(b, 10000); }
+
+#[bench]
+fn routing_broadcast10(b: &mut Bencher) { routing_broadcast_run(b, 10); }
+#[bench]
+fn routing_broadcast100(b: &mut Bencher) { routing_broadcast_run(b, 100); }
+#[bench]
+fn routing_broadcast1000(b: &mut Bencher) { routing_broadcast_run(b, 1000); }
+
+fn makenet(count: usize) -> (Net, Vec<Endpoint>) {
+    let net = Net::new(100);
+    let mut eps: Vec<Endpoint> = Vec::new();
+    for _ in range(0us, count) {
+        eps.push(net.new_endpoint());
+    }
+    (net, eps)
+}
+
+fn routing_unicast_run(b: &mut Bencher, count: usize) {
+    let (net, eps) = makenet(count);
+    let sender = net.new_endpoint();
+    let dst = eps[count / 2].clone();
+    let eid = dst.geteid();
+
+    b.iter(|| {
+        let mut msg = Message::new_raw(8);
+        msg.dstsid = 1;
+        msg.dsteid = eid;
+        sender.send(msg);
+        dst.recv().unwrap();
+    });
+}
+
+fn routing_group_run(b: &mut Bencher, count: usize) {
+    let (net, eps) = makenet(count);
+    let sender = net.new_endpoint();
+    let dst = eps[count / 2].clone();
+    dst.setgid(GROUP);
+
+    b.iter(|| {
+        let mut msg = Message::new_raw(8);
+        msg.dstsid = 1;
+        msg.dstgid = GROUP;
+        sender.send(msg);
+        dst.recv().unwrap();
+    });
+}
+
+fn routing_broadcast_run(b: &mut Bencher, count: usize) {
+    let (net, eps) = makenet(count);
+    let sender = net.new_endpoint();
+
+    b.iter(|| {
+        let msg = Message::new_raw(8);
+        sender.send(msg);
+        for ep in eps.iter() {
+            ep.recv().unwrap();
+        }
+    });
+}
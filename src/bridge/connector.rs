@@ -0,0 +1,552 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::intrinsics::transmute;
+
+use std::io::IoError;
+use std::result::Result;
+use std::vec::Vec;
+use std::thread::Thread;
+use std::sync::mpsc::channel;
+use std::io::timer::sleep;
+use std::time::duration::Duration;
+use std::rand::random;
+use std::cmp::min;
+
+use time::Timespec;
+
+use net::ID;
+use net::UNUSED_ID;
+use error::IoResult;
+use error::WaterError;
+use endpoint::Endpoint;
+use message::Message;
+use rawmessage::RawMessage;
+use net::Net;
+use bridge::runlink;
+use bridge::TerminateMessage;
+use bridge::BridgeOwner;
+use bridge::LinkCounters;
+use bridge::HeartbeatPolicy;
+use bridge::SourcePolicy;
+use bridge::CompressionStats;
+use bridge::event::publish;
+use bridge::event::BridgeEvent;
+use bridge::event::BridgeEventKind;
+use bridge::handshake::exchangeauth;
+use bridge::frame::DEFAULT_MAXFRAME;
+use bridge::link::Link;
+use bridge::Transport;
+
+/// How long a connector waits between attempts when links can not be
+/// established, and when it gives up.
+///
+/// The delay starts at `initialdelay` and doubles after each pass through the
+/// addresses that fails, up to `maxdelay`. Each delay is shortened by a random
+/// part of up to `jitter` (0.0 to 1.0) of itself so that many connectors do not
+/// retry at the same moment.
+#[derive(Clone, Copy, Debug)]
+pub struct ReconnectPolicy {
+    pub initialdelay:   Duration,
+    pub maxdelay:       Duration,
+    pub jitter:         f64,
+    /// Give up after this many attempts in a row fail, or never if `None`.
+    pub maxattempts:    Option<u64>,
+}
+
+impl ReconnectPolicy {
+    /// Start at 100ms, wait at most 30 seconds, with 20% jitter, and never give up.
+    pub fn new() -> ReconnectPolicy {
+        ReconnectPolicy {
+            initialdelay:   Duration::milliseconds(100),
+            maxdelay:       Duration::seconds(30),
+            jitter:         0.2,
+            maxattempts:    Option::None,
+        }
+    }
+
+    /// Get the delay to use after the number of failed passes.
+    pub fn getdelay(&self, passes: u64) -> Duration {
+        let shift = min(passes, 32) as u32;
+        let ms = self.initialdelay.num_milliseconds().checked_mul(1i64 << shift).unwrap_or(::std::i64::MAX);
+        let ms = min(ms, self.maxdelay.num_milliseconds());
+        let jitter = if self.jitter > 0.0 { self.jitter * random::<f64>() } else { 0.0 };
+        Duration::milliseconds(ms - (ms as f64 * jitter) as i64)
+    }
+}
+
+/// What a connector is doing.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ConnectorState {
+    /// Trying to establish a link.
+    Connecting,
+    /// A link is established.
+    Connected,
+    /// Waiting before the next attempt.
+    BackingOff,
+    /// The maximum attempts were made and no more will be.
+    GivenUp,
+    /// The connector was terminated.
+    Terminated,
+}
+
+struct Internal {
+    net:            Net,
+    transports:     Vec<Box<Transport>>,
+    /// The index of the transport in use or to be tried next.
+    current:        usize,
+    policy:         ReconnectPolicy,
+    state:          ConnectorState,
+    attempts:       u64,
+    ep:             Option<Endpoint>,
+    terminate:      bool,
+    gid:            ID,
+    pub connected:  bool,
+    handshakefailures: u64,
+    lasterror:      Option<WaterError>,
+    protoerrors:    u64,
+    heartbeat:      Option<HeartbeatPolicy>,
+    /// The counters of the current link.
+    counters:       Option<Arc<LinkCounters>>,
+    timeouts:       u64,
+    sourcepolicy:   SourcePolicy,
+    spoofed:        u64,
+    psk:            Option<Vec<u8>>,
+    authfailures:   u64,
+    compression:    Option<usize>,
+    maxframe:       u64,
+}
+
+/// This is a connector which tries to maintain a link using a transport to a
+/// `BridgeListener`. If the link is lost it is established again.
+///
+/// It can be given fallback transports which are tried in turn when a link can
+/// not be established. How long it waits between attempts and when it gives up
+/// is set with a `ReconnectPolicy`.
+///
+/// You will normally create one with `Net::tcpconnect` or `Net::unixconnect`.
+pub struct BridgeConnector {
+    i:              Arc<Mutex<Internal>>,
+}
+
+impl Clone for BridgeConnector {
+    fn clone(&self) -> BridgeConnector {
+        BridgeConnector {
+            i:      self.i.clone(),
+        }
+    }
+}
+
+impl BridgeConnector {
+    pub fn terminate(&mut self) {
+        // Set termination flag to catch connection loop.
+        let mut i = self.i.lock().unwrap();
+        i.terminate = true;
+        i.state = ConnectorState::Terminated;
+        if i.ep.is_none() {
+            // Early exit. It seems the main thread has not had
+            // time to set these values so it still has not spawned
+            // and RX or TX, hopefully, so lets just tell it to exit.
+            return;
+        }
+        // Send a message to catch active TX thread which
+        // will make the RX thread terminate.
+        i.ep.as_mut().unwrap().give(&Message::new_clone(TerminateMessage));
+
+        // We can wait for it to terminate inside this method
+        // because it a lock was acquired and the thread may
+        // need to check `terminate` so it will try to lock it.
+        // This is a side-effect of using Mutex<T> instead of
+        // per method locking like with Net, Endpoint, and friends.
+        // -- kmcg3413@gmail.com
+    }
+
+    /// Get the address connected to, or to be tried next. It is a String in the
+    /// format used by the transport, such as "<host/ip>:<port>" for TCP.
+    pub fn getaddr(&self) -> String {
+        let i = self.i.lock().unwrap();
+        i.transports[i.current].getaddr()
+    }
+
+    /// Add a transport to try when the others can not establish a link.
+    pub fn addfallback(&mut self, transport: Box<Transport>) {
+        self.i.lock().unwrap().transports.push(transport);
+    }
+
+    /// Set the reconnect policy. It is used from the next attempt on.
+    pub fn setpolicy(&mut self, policy: ReconnectPolicy) {
+        self.i.lock().unwrap().policy = policy;
+    }
+
+    pub fn getpolicy(&self) -> ReconnectPolicy {
+        self.i.lock().unwrap().policy
+    }
+
+    /// Get what the connector is doing.
+    pub fn getstate(&self) -> ConnectorState {
+        self.i.lock().unwrap().state
+    }
+
+    fn setstate(&mut self, state: ConnectorState) {
+        let mut i = self.i.lock().unwrap();
+        // Terminated is final.
+        if i.state != ConnectorState::Terminated {
+            i.state = state;
+        }
+    }
+
+    /// Get the number of attempts in a row that failed to establish a link.
+    pub fn getattempts(&self) -> u64 {
+        self.i.lock().unwrap().attempts
+    }
+
+    pub fn connected(&self) -> bool {
+        self.i.lock().unwrap().connected
+    }
+
+    pub fn setconnected(&mut self, connected: bool) {
+        self.i.lock().unwrap().connected = connected;
+        self.setstate(if connected { ConnectorState::Connected } else { ConnectorState::Connecting });
+    }
+
+    /// _(internal)_ Record a link that failed to negotiate.
+    pub fn handshakefailed(&mut self, err: WaterError) {
+        let mut i = self.i.lock().unwrap();
+        i.handshakefailures += 1;
+        if err == WaterError::AuthFailed {
+            i.authfailures += 1;
+        }
+        i.lasterror = Option::Some(err);
+    }
+
+    /// Get the number of connections that were dropped because the handshake
+    /// failed, such as a peer with a different protocol version.
+    pub fn gethandshakefailures(&self) -> u64 {
+        self.i.lock().unwrap().handshakefailures
+    }
+
+    /// Get the number of links torn down because the remote sent a frame that
+    /// was malformed or larger than the maximum frame size.
+    pub fn getprotoerrors(&self) -> u64 {
+        self.i.lock().unwrap().protoerrors
+    }
+
+    /// Set the largest frame in bytes that will be accepted from the remote. A
+    /// larger frame tears down the link. This only applies to links that are
+    /// established after it is set.
+    pub fn setmaxframe(&mut self, maxframe: u64) {
+        self.i.lock().unwrap().maxframe = maxframe;
+    }
+
+    /// Get the largest frame in bytes that will be accepted from the remote.
+    pub fn getmaxframe(&self) -> u64 {
+        self.i.lock().unwrap().maxframe
+    }
+
+    /// Set the heartbeat policy, or `None` to not send heartbeats and never time
+    /// out a silent link. This only applies to links that are established
+    /// after it is set.
+    pub fn setheartbeat(&mut self, heartbeat: Option<HeartbeatPolicy>) {
+        self.i.lock().unwrap().heartbeat = heartbeat;
+    }
+
+    pub fn getheartbeat(&self) -> Option<HeartbeatPolicy> {
+        self.i.lock().unwrap().heartbeat
+    }
+
+    /// Get the number of links torn down because nothing was heard from the
+    /// remote for the heartbeat timeout.
+    pub fn gettimeouts(&self) -> u64 {
+        self.i.lock().unwrap().timeouts
+    }
+
+    /// Compress the payload of frames that are at least this many bytes, or
+    /// `None` to not compress. It is only done if the remote can decompress.
+    /// This only applies to links that are established after it is set.
+    pub fn setcompression(&mut self, threshold: Option<usize>) {
+        self.i.lock().unwrap().compression = threshold;
+    }
+
+    pub fn getcompression(&self) -> Option<usize> {
+        self.i.lock().unwrap().compression
+    }
+
+    /// Set the key that both sides have to prove they know before a link is
+    /// used, or `None` to not authenticate. The remote has to use the same.
+    /// This only applies to links that are established after it is set.
+    pub fn setpsk(&mut self, psk: Option<Vec<u8>>) {
+        self.i.lock().unwrap().psk = psk;
+    }
+
+    /// Get the number of handshakes that failed because the remote did not
+    /// prove it knows the key, or did not want to authenticate at all.
+    pub fn getauthfailures(&self) -> u64 {
+        self.i.lock().unwrap().authfailures
+    }
+
+    /// Set what is done with a message from the remote that claims to be from a
+    /// net the remote can not speak for. This only applies to links that are
+    /// established after it is set.
+    pub fn setsourcepolicy(&mut self, sourcepolicy: SourcePolicy) {
+        self.i.lock().unwrap().sourcepolicy = sourcepolicy;
+    }
+
+    pub fn getsourcepolicy(&self) -> SourcePolicy {
+        self.i.lock().unwrap().sourcepolicy
+    }
+
+    /// Get the number of messages received that claimed to be from a net the
+    /// remote can not speak for, whether they were rejected or rewritten.
+    pub fn getspoofed(&self) -> u64 {
+        self.i.lock().unwrap().spoofed
+    }
+
+    /// Get how well compression did on the current link.
+    pub fn getcompressionstats(&self) -> Option<CompressionStats> {
+        let i = self.i.lock().unwrap();
+        match i.counters {
+            Some(ref counters) if i.connected => Option::Some(counters.getcompression()),
+            _ => Option::None,
+        }
+    }
+
+    /// Get the round trip time last measured by a heartbeat on the current link.
+    pub fn getrtt(&self) -> Option<Duration> {
+        let i = self.i.lock().unwrap();
+        match i.counters {
+            Some(ref counters) if i.connected => counters.getrtt(),
+            _ => Option::None,
+        }
+    }
+
+    /// Get the last error that caused a connection to be dropped or torn down.
+    pub fn getlasterror(&self) -> Option<WaterError> {
+        self.i.lock().unwrap().lasterror.clone()
+    }
+
+    /// _(internal)_ Record a failed attempt and move on to the next transport. Once
+    /// every transport has failed, wait as the policy says. Return false if the
+    /// connector gave up or was terminated.
+    fn attemptfailed(&mut self) -> bool {
+        let mut i = self.i.lock().unwrap();
+        let mut event = BridgeEvent::new(BridgeEventKind::Reconnecting, i.transports[i.current].getaddr());
+        i.attempts += 1;
+        i.current = (i.current + 1) % i.transports.len();
+        event.attempts = i.attempts;
+        event.error = i.lasterror.clone();
+
+        match i.policy.maxattempts {
+            Some(max) if i.attempts >= max => {
+                if i.state != ConnectorState::Terminated {
+                    i.state = ConnectorState::GivenUp;
+                }
+                drop(i);
+                event.kind = BridgeEventKind::GivenUp;
+                self.publish(event);
+                return false;
+            },
+            _ => { },
+        }
+
+        // Go straight on to a fallback until the whole list has been tried.
+        if i.current != 0 {
+            return !i.terminate;
+        }
+
+        let passes = i.attempts / i.transports.len() as u64;
+        let delay = i.policy.getdelay(if passes > 0 { passes - 1 } else { 0 });
+        drop(i);
+
+        event.delay = delay;
+        self.publish(event);
+
+        self.setstate(ConnectorState::BackingOff);
+
+        // Sleep in slices so that a terminate is not held up by a long delay.
+        let mut left = delay.num_milliseconds();
+        while left > 0 {
+            if self.i.lock().unwrap().terminate {
+                return false;
+            }
+            let slice = min(left, 50);
+            sleep(Duration::milliseconds(slice));
+            left -= slice;
+        }
+
+        self.setstate(ConnectorState::Connecting);
+        !self.i.lock().unwrap().terminate
+    }
+
+    fn publish(&self, event: BridgeEvent) {
+        let net = self.i.lock().unwrap().net.clone();
+        publish(&net, event);
+    }
+
+    pub fn thread(mut bridge: BridgeConnector) {
+        // This thread will be short-lived but to prevent us from
+        // blocking the calling thread. It should be easier to add
+        // in blocking if that is desired.
+        loop {
+            let result = {
+                let i = bridge.i.lock().unwrap();
+                i.transports[i.current].connect()
+            };
+
+            if bridge.i.lock().unwrap().terminate {
+                return;
+            }
+
+            let mut link: Box<Link> = match result {
+                Ok(link) => link,
+                Err(e) => {
+                    bridge.i.lock().unwrap().lasterror = Option::Some(e);
+                    if !bridge.attemptfailed() {
+                        return;
+                    }
+                    continue;
+                }
+            };
+
+            let sid = bridge.i.lock().unwrap().net.getserveraddr();
+            let psk = bridge.i.lock().unwrap().psk.clone();
+            let remote = match exchangeauth(&mut *link, sid, psk.as_ref().map(|psk| psk.as_slice())) {
+                Ok(remote) => remote,
+                Err(e) => {
+                    link.shutdown();
+                    let mut event = BridgeEvent::new(BridgeEventKind::HandshakeFailed, bridge.getaddr());
+                    event.error = Option::Some(e.clone());
+                    bridge.handshakefailed(e);
+                    bridge.publish(event);
+                    // The peer is not going to change its mind right away, so
+                    // this backs off like any other failure.
+                    if !bridge.attemptfailed() {
+                        return;
+                    }
+                    continue;
+                }
+            };
+
+            bridge.i.lock().unwrap().attempts = 0;
+
+            // The endpoint is used for both directions of the link. Its net ID
+            // is the remote net ID which is used to catch messages directed to
+            // go only onto the remote net, or for broadcast messages.
+            let mut lock = bridge.i.lock().unwrap();
+            let mut ep = Endpoint::new(remote.sid, lock.net.get_neweid(), lock.net.clone());
+            drop(lock);
+            // Get unique group ID for control messages.
+            ep.setgid(bridge.i.lock().unwrap().net.get_neweid()); 
+            bridge.i.lock().unwrap().net.add_endpoint(ep.clone());
+
+            if bridge.i.lock().unwrap().terminate {
+                return;
+            }
+
+            let counters = Arc::new(LinkCounters::new());
+            bridge.i.lock().unwrap().counters = Option::Some(counters.clone());
+            let (closedtx, closedrx) = channel::<()>();
+            runlink(bridge.clone(), ep.clone(), link, remote.features, counters, Box::new(closedtx));
+
+            // Set endpoint into bridge.
+            bridge.i.lock().unwrap().ep = Option::Some(ep);
+            bridge.setconnected(true);
+
+            let mut event = BridgeEvent::new(BridgeEventKind::Connected, bridge.getaddr());
+            event.sid = remote.sid;
+            bridge.publish(event.clone());
+
+            // Wait for the link to be closed.. then try connection
+            // again until we are requested to terminate.
+            closedrx.recv().ok();
+
+            bridge.setconnected(false);
+
+            event.kind = BridgeEventKind::Disconnected;
+            bridge.publish(event);
+
+            // The TX may have terminated the RX and we will make it
+            // here. We need to check the terminate flag to see if 
+            // we also need to exit.
+            if bridge.i.lock().unwrap().terminate {
+                return;
+            }
+        }
+    }
+
+    /// Create a connector that establishes links using the transport. This is how
+    /// a new transport is given a connector.
+    pub fn new(net: &Net, transport: Box<Transport>) -> BridgeConnector {
+        BridgeConnector::new_failover(net, vec![transport], ReconnectPolicy::new())
+    }
+
+    /// Create a connector that tries each of the transports in turn until a
+    /// link is established, waiting between attempts as the policy says.
+    pub fn new_failover(net: &Net, transports: Vec<Box<Transport>>, policy: ReconnectPolicy) -> BridgeConnector {
+        if transports.len() == 0 {
+            panic!("a connector needs at least one transport");
+        }
+
+        let n = BridgeConnector { i: Arc::new(Mutex::new(Internal {
+            net:        net.clone(),
+            terminate:  false,
+            ep:         Option::None,
+            transports: transports,
+            current:    0,
+            policy:     policy,
+            state:      ConnectorState::Connecting,
+            attempts:   0,
+            gid:        UNUSED_ID,
+            connected:  false,
+            handshakefailures: 0,
+            lasterror:  Option::None,
+            protoerrors: 0,
+            heartbeat:  Option::Some(HeartbeatPolicy::new()),
+            counters:   Option::None,
+            timeouts:   0,
+            sourcepolicy: SourcePolicy::Reject,
+            spoofed:    0,
+            psk:        Option::None,
+            authfailures: 0,
+            compression: Option::None,
+            maxframe:   DEFAULT_MAXFRAME,
+        }))};
+
+        let nclone = n.clone();
+        Thread::spawn(move || { BridgeConnector::thread(nclone)});
+
+        n
+    }
+}
+
+impl BridgeOwner for BridgeConnector {
+    fn getmaxframe(&self) -> u64 {
+        self.i.lock().unwrap().maxframe
+    }
+
+    fn protoerror(&mut self, err: WaterError) {
+        let mut i = self.i.lock().unwrap();
+        i.protoerrors += 1;
+        i.lasterror = Option::Some(err);
+    }
+
+    fn getheartbeat(&self) -> Option<HeartbeatPolicy> {
+        self.i.lock().unwrap().heartbeat
+    }
+
+    fn timedout(&mut self) {
+        let mut i = self.i.lock().unwrap();
+        i.timeouts += 1;
+        i.lasterror = Option::Some(WaterError::TimedOut);
+    }
+
+    fn getsourcepolicy(&self) -> SourcePolicy {
+        self.i.lock().unwrap().sourcepolicy
+    }
+
+    fn spoofed(&mut self) {
+        self.i.lock().unwrap().spoofed += 1;
+    }
+
+    fn getcompression(&self) -> Option<usize> {
+        self.i.lock().unwrap().compression
+    }
+}
@@ -0,0 +1,80 @@
+//! The bridges publish an event onto their local net each time a link comes up,
+//! goes down, or can not be established. They are sent as clone messages to the
+//! group `BRIDGE_EVENT_GID` on the local net only, so an endpoint that wants
+//! them just joins the group and blocks on it instead of polling the bridge.
+//!
+//!     use water::Net;
+//!     use water::BridgeEvent;
+//!     use water::BRIDGE_EVENT_GID;
+//!
+//!     let net = Net::new(100);
+//!     let events = net.new_endpoint();
+//!     events.joingroup(BRIDGE_EVENT_GID);
+//!
+//!     // let event = events.recvorblockforever().unwrap().typeunwrap::<BridgeEvent>();
+
+use std::time::duration::Duration;
+
+use net::ID;
+use net::UNUSED_ID;
+use net::Net;
+use message::Message;
+use error::WaterError;
+
+/// The group the events are sent to. It is below the IDs that a net gives out
+/// so it can not collide with them.
+pub const BRIDGE_EVENT_GID: ID = 0x100;
+
+/// What happened.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BridgeEventKind {
+    /// A link was negotiated with the remote net `sid`.
+    Connected,
+    /// The link with the remote net `sid` was lost or closed.
+    Disconnected,
+    /// A connection was made but the handshake failed with `error`.
+    HandshakeFailed,
+    /// A connector failed to establish a link and will try again after `delay`.
+    Reconnecting,
+    /// A connector made the most attempts its policy allows and stopped.
+    GivenUp,
+}
+
+/// A change in the links of a bridge.
+#[derive(Clone)]
+pub struct BridgeEvent {
+    pub kind:           BridgeEventKind,
+    /// The address of the remote in the format the transport uses. It may be
+    /// empty if the transport can not tell.
+    pub addr:           String,
+    /// The net ID of the remote, or `UNUSED_ID` if it is not known.
+    pub sid:            ID,
+    /// The failed attempts in a row so far. Only for `Reconnecting` and `GivenUp`.
+    pub attempts:       u64,
+    /// How long until the next attempt. Only for `Reconnecting`.
+    pub delay:          Duration,
+    /// Why it happened, if known.
+    pub error:          Option<WaterError>,
+}
+
+impl BridgeEvent {
+    pub fn new(kind: BridgeEventKind, addr: String) -> BridgeEvent {
+        BridgeEvent {
+            kind:       kind,
+            addr:       addr,
+            sid:        UNUSED_ID,
+            attempts:   0,
+            delay:      Duration::zero(),
+            error:      Option::None,
+        }
+    }
+}
+
+/// _(internal)_ Send the event to the event group on the local net.
+pub fn publish(net: &Net, event: BridgeEvent) {
+    let mut msg = Message::new_clone(event);
+    msg.dstsid = 1;
+    msg.dsteid = 0;
+    msg.dstgid = BRIDGE_EVENT_GID;
+    net.sendas(msg, net.getserveraddr(), 0);
+}
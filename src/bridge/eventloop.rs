@@ -0,0 +1,587 @@
+//! The event loop runs every stream link of a net, such as TCP and Unix domain
+//! sockets, on one thread instead of an RX and TX thread for each. It waits with
+//! epoll on the sockets of the links and on an eventfd, which the endpoint of a
+//! link signals when it is given a message to send.
+//!
+//! The frames are read and written exactly as the RX and TX threads do, so the
+//! remote can not tell the difference. A link that can not be polled, such as
+//! TLS, UDP, or shared memory, is still given its own threads by `runlink`.
+//!
+//! _Only Linux is supported, like the rest of `sys`._
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::collections::HashMap;
+use std::thread::Thread;
+use std::mem::replace;
+use std::cmp::min;
+use std::cmp::max;
+
+use libc::c_int;
+use libc::c_void;
+use libc::size_t;
+
+use net::ID;
+use sys;
+use sys::Fd;
+use endpoint::Endpoint;
+use error::IoResult;
+use error::WaterError;
+use selector::Waker;
+use selector::SelectToken;
+use bridge::Batch;
+use bridge::BridgeOwner;
+use bridge::LinkClosed;
+use bridge::LinkCounters;
+use bridge::HeartbeatPolicy;
+use bridge::SourcePolicy;
+use bridge::BATCH_BYTES;
+use bridge::handshake;
+use bridge::link::Link;
+use bridge::nowus;
+use bridge::rxunit;
+use bridge::txmessage;
+use bridge::keepalive;
+
+/// The epoll data of the eventfd. Every link has a key above it.
+const WAKE_KEY: u64 = 0;
+/// The most events taken from epoll at once.
+const MAX_EVENTS: usize = 256;
+/// The most bytes read from one link before the others get a turn.
+const READ_BYTES: usize = 256 * 1024;
+/// The most batches taken from the endpoint of one link before the others get
+/// a turn.
+const DRAIN_BATCHES: usize = 16;
+/// Messages are left on the endpoint of a link while this many bytes are
+/// still waiting to be written to it, so a slow remote backs up into the
+/// limits of the endpoint instead of into memory here.
+const OUT_BYTES: usize = 4 * BATCH_BYTES;
+
+struct Shared {
+    epfd:           Fd,
+    wakefd:         Fd,
+    /// Links given to the loop that it has not taken yet.
+    added:          Mutex<Vec<Conn>>,
+    /// The keys of links whose endpoints have been given messages.
+    ready:          Mutex<Vec<u64>>,
+    terminate:      AtomicBool,
+}
+
+impl Shared {
+    fn wake(&self) {
+        let one = 1u64;
+        unsafe { sys::write(self.wakefd.fd, &one as *const u64 as *const c_void, 8) };
+    }
+}
+
+/// Signals the loop when the endpoint of a link is given a message. It only
+/// does so once until the loop has taken the messages, however many arrive.
+struct ConnWaker {
+    shared:         Arc<Shared>,
+    key:            u64,
+    flagged:        Arc<AtomicBool>,
+}
+
+impl Waker for ConnWaker {
+    fn wake(&self) {
+        if !self.flagged.swap(true, Ordering::SeqCst) {
+            self.shared.ready.lock().unwrap().push(self.key);
+            self.shared.wake();
+        }
+    }
+}
+
+/// A link run by the event loop, with everything its RX and TX threads would
+/// otherwise have kept.
+///
+/// Once registered the socket is non-blocking, so every read and write goes
+/// through `inbuf` and `outbuf` here. The link itself, and any duplicate of it
+/// such as the one kept by the listener, is only used to `shutdown` it and to
+/// `getpeer`.
+pub struct Conn {
+    owner:          Box<BridgeOwner>,
+    ep:             Endpoint,
+    link:           Box<Link>,
+    fd:             c_int,
+    epfd:           c_int,
+    key:            u64,
+    features:       u32,
+    counters:       Arc<LinkCounters>,
+    maxframe:       u64,
+    sourcepolicy:   SourcePolicy,
+    policy:         Option<HeartbeatPolicy>,
+    compression:    Option<usize>,
+    lastping:       u64,
+    neighbor:       ID,
+    token:          Option<Arc<SelectToken>>,
+    flagged:        Arc<AtomicBool>,
+    /// Bytes read that do not make a whole unit yet.
+    inbuf:          Vec<u8>,
+    /// Bytes waiting to be written.
+    outbuf:         Vec<u8>,
+    /// Set while the socket is polled for writing.
+    writing:        bool,
+    /// Set when messages were left on the endpoint because too much is
+    /// waiting to be written.
+    stalled:        bool,
+    /// Set when messages were left on the endpoint to give others a turn.
+    more:           bool,
+    closed:         Box<LinkClosed>,
+}
+
+impl Conn {
+    /// Take the settings from the owner like the RX and TX threads do.
+    pub fn new(
+        owner: Box<BridgeOwner>, ep: Endpoint, link: Box<Link>, features: u32, counters: Arc<LinkCounters>,
+        closed: Box<LinkClosed>
+    ) -> Conn {
+        let policy = if features & handshake::FEATURE_HEARTBEAT != 0 { owner.getheartbeat() } else { None };
+        let compression = if features & handshake::FEATURE_COMPRESSION != 0 { owner.getcompression() } else { None };
+        let neighbor = ep.getsid();
+
+        Conn {
+            maxframe:       owner.getmaxframe(),
+            sourcepolicy:   owner.getsourcepolicy(),
+            owner:          owner,
+            ep:             ep,
+            fd:             link.getfd().unwrap(),
+            link:           link,
+            epfd:           -1,
+            key:            WAKE_KEY,
+            features:       features,
+            counters:       counters,
+            policy:         policy,
+            compression:    compression,
+            lastping:       nowus(),
+            neighbor:       neighbor,
+            token:          None,
+            flagged:        Arc::new(AtomicBool::new(false)),
+            inbuf:          Vec::new(),
+            outbuf:         Vec::new(),
+            writing:        false,
+            stalled:        false,
+            more:           false,
+            closed:         closed,
+        }
+    }
+
+    /// Start polling the socket and the endpoint.
+    fn register(&mut self, shared: &Arc<Shared>, key: u64) -> IoResult<()> {
+        self.key = key;
+        self.epfd = shared.epfd.fd;
+
+        // A blocking read or write through the link would now fail, which the
+        // link asserts on from here on.
+        self.link.setpolled();
+        try!(sys::setnonblocking(self.fd));
+        try!(self.poll(sys::EPOLL_CTL_ADD, false));
+
+        let token = Arc::new(SelectToken::with_waker(Box::new(ConnWaker {
+            shared:     shared.clone(),
+            key:        key,
+            flagged:    self.flagged.clone(),
+        })));
+        self.ep.addselecttoken(token.clone());
+        self.token = Some(token);
+
+        // This also has every link, including this one, advertise its routes.
+        self.ep.getnet().addneighbor(self.neighbor);
+        Ok(())
+    }
+
+    fn poll(&mut self, op: c_int, writing: bool) -> IoResult<()> {
+        let mut event = sys::epoll_event {
+            events:     sys::EPOLLIN | sys::EPOLLRDHUP | if writing { sys::EPOLLOUT } else { 0 },
+            data:       self.key,
+        };
+        if unsafe { sys::epoll_ctl(self.epfd, op, self.fd, &mut event) } < 0 {
+            return Err(sys::lasterror());
+        }
+        self.writing = writing;
+        Ok(())
+    }
+
+    /// Read what the socket has and handle every whole unit. Returns false if
+    /// the link has to be closed.
+    fn readable(&mut self) -> bool {
+        let mut buf = [0u8; 65536];
+        let mut total = 0us;
+        let mut eof = false;
+
+        while total < READ_BYTES {
+            let got = unsafe { sys::read(self.fd, buf.as_mut_ptr() as *mut c_void, buf.len() as size_t) };
+
+            // The units that came before the remote closed are still handled.
+            if got == 0 {
+                eof = true;
+                break;
+            }
+
+            if got < 0 {
+                match sys::errno() {
+                    sys::EINTR => continue,
+                    sys::EAGAIN => break,
+                    _ => return false,
+                }
+            }
+
+            self.inbuf.push_all(buf.slice_to(got as usize));
+            total += got as usize;
+        }
+
+        let mut start = 0us;
+
+        while self.inbuf.len() - start >= 8 {
+            let size = self.inbuf.slice(start, start + 8).iter().fold(0u64, |size, byte| (size << 8) | *byte as u64);
+
+            // Refuse before waiting for the rest of it.
+            if size > self.maxframe {
+                let err = WaterError::Protocol(format!("unit size {} exceeds maximum {}", size, self.maxframe));
+                self.owner.protoerror(err);
+                return false;
+            }
+
+            let end = start + 8 + size as usize;
+            if self.inbuf.len() < end {
+                break;
+            }
+
+            let result = rxunit(
+                &mut *self.owner, &mut self.ep, self.inbuf.slice(start + 8, end), self.maxframe,
+                self.sourcepolicy, &*self.counters
+            );
+
+            match result {
+                Ok(_) => { },
+                Err(e) => {
+                    match e {
+                        WaterError::Protocol(_) => self.owner.protoerror(e),
+                        _ => { },
+                    }
+                    return false;
+                },
+            }
+
+            start = end;
+        }
+
+        if start > 0 {
+            self.inbuf = self.inbuf.slice_from(start).to_vec();
+        }
+
+        !eof
+    }
+
+    /// Frame what is waiting on the endpoint and write it. Returns false if the
+    /// link has to be closed.
+    fn drain(&mut self) -> bool {
+        for _ in range(0us, DRAIN_BATCHES) {
+            if self.outbuf.len() >= OUT_BYTES {
+                self.stalled = true;
+                return true;
+            }
+
+            let mut batch = Batch::new();
+            let mut open = true;
+
+            while !batch.isfull() {
+                match self.ep.recv() {
+                    Ok(msg) => {
+                        if !txmessage(&mut self.ep, self.features, self.compression, &*self.counters, msg, &mut batch) {
+                            open = false;
+                            break;
+                        }
+                    },
+                    Err(_) => break,
+                }
+            }
+
+            let full = batch.isfull();
+            batch.encode(&mut self.outbuf, &*self.counters);
+
+            // What was taken before a `TerminateMessage` still goes out if the
+            // socket takes it right away.
+            if !self.flush() || !open {
+                return false;
+            }
+
+            if !full {
+                return true;
+            }
+        }
+
+        self.more = true;
+        true
+    }
+
+    /// Write as much as the socket takes, and poll for writing if it did not
+    /// take everything. Returns false if the link has to be closed.
+    fn flush(&mut self) -> bool {
+        let mut done = 0us;
+
+        while done < self.outbuf.len() {
+            let put = unsafe {
+                sys::write(
+                    self.fd, self.outbuf.slice_from(done).as_ptr() as *const c_void,
+                    (self.outbuf.len() - done) as size_t
+                )
+            };
+
+            if put < 0 {
+                match sys::errno() {
+                    sys::EINTR => continue,
+                    sys::EAGAIN => break,
+                    _ => return false,
+                }
+            }
+
+            done += put as usize;
+        }
+
+        if done == self.outbuf.len() {
+            self.outbuf.clear();
+        } else if done > 0 {
+            self.outbuf = self.outbuf.slice_from(done).to_vec();
+        }
+
+        let writing = self.outbuf.len() > 0;
+        if writing != self.writing && self.poll(sys::EPOLL_CTL_MOD, writing).is_err() {
+            return false;
+        }
+
+        true
+    }
+
+    /// The socket can take more. Returns false if the link has to be closed.
+    fn writable(&mut self) -> bool {
+        if !self.flush() {
+            return false;
+        }
+
+        if self.stalled && self.outbuf.len() < OUT_BYTES {
+            self.stalled = false;
+            self.more = true;
+        }
+
+        true
+    }
+
+    /// Send a ping if one is due. Returns false if the remote has gone silent
+    /// and the link has to be closed.
+    fn tick(&mut self) -> bool {
+        let mut batch = Batch::new();
+
+        if !keepalive(&mut *self.owner, &*self.counters, self.policy, &mut self.lastping, &mut batch) {
+            return false;
+        }
+
+        batch.encode(&mut self.outbuf, &*self.counters);
+        self.flush()
+    }
+
+    /// Stop polling and close the link, then tell whoever is waiting on it.
+    fn close(mut self) {
+        let mut event = sys::epoll_event { events: 0, data: 0 };
+        unsafe { sys::epoll_ctl(self.epfd, sys::EPOLL_CTL_DEL, self.fd, &mut event) };
+
+        self.link.shutdown();
+
+        // Only a link that was registered is a neighbor.
+        match self.token.take() {
+            Some(token) => {
+                self.ep.removeselecttoken(&token);
+                self.ep.getnet().removeneighbor(self.neighbor);
+            },
+            None => { },
+        }
+
+        // The endpoint is gone from the net before anyone is told, as it is
+        // once the threads of a link have exited.
+        let mut closed = self.closed;
+        drop(self.ep);
+        closed.linkclosed();
+    }
+}
+
+/// Runs the stream links of a net. A net starts one the first time it is
+/// needed with `Net::geteventloop`, and it lasts until the net is dropped.
+pub struct EventLoop {
+    shared:         Arc<Shared>,
+}
+
+impl Drop for EventLoop {
+    fn drop(&mut self) {
+        self.shared.terminate.store(true, Ordering::SeqCst);
+        self.shared.wake();
+    }
+}
+
+impl EventLoop {
+    /// Create the epoll instance and the eventfd, and start the thread.
+    pub fn new() -> IoResult<EventLoop> {
+        let epfd = unsafe { sys::epoll_create1(sys::EPOLL_CLOEXEC) };
+        if epfd < 0 {
+            return Err(sys::lasterror());
+        }
+        let epfd = Fd { fd: epfd };
+
+        let wakefd = unsafe { sys::eventfd(0, sys::EFD_NONBLOCK | sys::EFD_CLOEXEC) };
+        if wakefd < 0 {
+            return Err(sys::lasterror());
+        }
+        let wakefd = Fd { fd: wakefd };
+
+        let mut event = sys::epoll_event { events: sys::EPOLLIN, data: WAKE_KEY };
+        if unsafe { sys::epoll_ctl(epfd.fd, sys::EPOLL_CTL_ADD, wakefd.fd, &mut event) } < 0 {
+            return Err(sys::lasterror());
+        }
+
+        let shared = Arc::new(Shared {
+            epfd:       epfd,
+            wakefd:     wakefd,
+            added:      Mutex::new(Vec::new()),
+            ready:      Mutex::new(Vec::new()),
+            terminate:  AtomicBool::new(false),
+        });
+
+        let _shared = shared.clone();
+        Thread::spawn(move || { EventLoop::thread_loop(_shared) });
+
+        Ok(EventLoop {
+            shared:     shared,
+        })
+    }
+
+    /// Give the loop a link to run. `Conn::closed` is told once it is closed.
+    pub fn add(&self, conn: Conn) {
+        self.shared.added.lock().unwrap().push(conn);
+        self.shared.wake();
+    }
+
+    fn thread_loop(shared: Arc<Shared>) {
+        let mut conns: HashMap<u64, Conn> = HashMap::new();
+        let mut nextkey = WAKE_KEY + 1;
+        let mut events: Vec<sys::epoll_event> = range(0us, MAX_EVENTS).map(|_| {
+            sys::epoll_event { events: 0, data: 0 }
+        }).collect();
+        let mut lastcheck = nowus();
+        let mut again: Vec<u64> = Vec::new();
+
+        loop {
+            let added = replace(&mut *shared.added.lock().unwrap(), Vec::new());
+
+            for mut conn in added.into_iter() {
+                let key = nextkey;
+                nextkey += 1;
+                match conn.register(&shared, key) {
+                    Ok(_) => {
+                        // Anything given to the endpoint before now, such as
+                        // the routes to advertise, has to be sent.
+                        again.push(key);
+                        conns.insert(key, conn);
+                    },
+                    Err(_) => conn.close(),
+                }
+            }
+
+            // Nothing can give a link to the loop once the net is gone, and
+            // while a link is open its endpoint keeps the net around.
+            if conns.len() == 0 && shared.terminate.load(Ordering::SeqCst) {
+                return;
+            }
+
+            // Wake in time for the next heartbeat of any link.
+            let tickms = conns.values().filter_map(|conn| conn.policy).fold(-1i64, |ms, policy| {
+                let each = max(min(policy.interval, policy.timeout).num_milliseconds(), 1);
+                if ms < 0 { each } else { min(ms, each) }
+            });
+
+            let timeout = if again.len() > 0 { 0 } else { tickms as c_int };
+            let count = unsafe {
+                sys::epoll_wait(shared.epfd.fd, events.as_mut_ptr(), MAX_EVENTS as c_int, timeout)
+            };
+
+            let mut closing: Vec<u64> = Vec::new();
+
+            for ndx in range(0us, if count > 0 { count as usize } else { 0 }) {
+                let key = events[ndx].data;
+                let flags = events[ndx].events;
+
+                if key == WAKE_KEY {
+                    let mut value = 0u64;
+                    unsafe { sys::read(shared.wakefd.fd, &mut value as *mut u64 as *mut c_void, 8) };
+                    continue;
+                }
+
+                let conn = match conns.get_mut(&key) {
+                    Some(conn) => conn,
+                    None => continue,
+                };
+
+                if flags & (sys::EPOLLIN | sys::EPOLLRDHUP | sys::EPOLLHUP | sys::EPOLLERR) != 0 && !conn.readable() {
+                    closing.push(key);
+                    continue;
+                }
+
+                if flags & sys::EPOLLOUT != 0 && !conn.writable() {
+                    closing.push(key);
+                }
+            }
+
+            // Take the messages given to the endpoints.
+            let mut ready = replace(&mut *shared.ready.lock().unwrap(), Vec::new());
+            ready.push_all(again.as_slice());
+            again.clear();
+
+            for key in ready.iter() {
+                // A link that is being closed must not be written to again.
+                if closing.contains(key) {
+                    continue;
+                }
+
+                let conn = match conns.get_mut(key) {
+                    Some(conn) => conn,
+                    None => continue,
+                };
+
+                // Cleared first so that a message given while draining
+                // signals again.
+                conn.flagged.store(false, Ordering::SeqCst);
+                conn.more = false;
+
+                if conn.stalled {
+                    continue;
+                }
+
+                if !conn.drain() {
+                    closing.push(*key);
+                }
+            }
+
+            let now = nowus();
+            if tickms >= 0 && now > lastcheck && (now - lastcheck) as i64 >= tickms * 1000 {
+                lastcheck = now;
+                for (key, conn) in conns.iter_mut() {
+                    if !closing.contains(key) && !conn.tick() {
+                        closing.push(*key);
+                    }
+                }
+            }
+
+            for (key, conn) in conns.iter() {
+                if conn.more && !closing.contains(key) {
+                    again.push(*key);
+                }
+            }
+
+            for key in closing.iter() {
+                match conns.remove(key) {
+                    Some(conn) => conn.close(),
+                    None => { },
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,407 @@
+//! A frame carries one message across a link as a single unit. It starts with
+//! the frame type and the hop count each as a u8, then the source and
+//! destination fields, the correlation ID and the broadcast ID each as a u64,
+//! and then the payload.
+//!
+//! The frame type of a raw or serial message can have `FRAME_COMPRESSED` set,
+//! and then the payload is the size it has once decompressed as a u64 followed
+//! by the payload compressed with deflate.
+//!
+//! Nothing read from the link is trusted. A frame that is too small, larger
+//! than the maximum frame size, of an unknown type, or with a payload that does
+//! not make sense is returned as `WaterError::Protocol` and the link should be
+//! torn down since on a stream there is no way to find the start of the next
+//! frame.
+
+use std::io::BufReader;
+use std::io::MemWriter;
+
+use flate::deflate_bytes;
+
+use libc::c_int;
+use libc::c_void;
+use libc::size_t;
+
+use message::Message;
+use message::MessagePayload;
+use rawmessage::RawMessage;
+use serialmessage::SerialMessage;
+use error::IoResult;
+use error::WaterError;
+use net::ID;
+
+/// The frame type for a raw message.
+pub const FRAME_RAW: u8 = 1;
+/// The frame type for a heartbeat. The payload is the time it was sent in
+/// microseconds as a u64, and the remote answers with `FRAME_PONG`.
+pub const FRAME_PING: u8 = 2;
+/// The frame type for the answer to a heartbeat. The payload is the one from
+/// the `FRAME_PING` it answers.
+pub const FRAME_PONG: u8 = 3;
+/// The frame type for a serial message. The payload starts with the length of
+/// the type tag as a u16, then the tag, then the encoded type.
+pub const FRAME_SERIAL: u8 = 4;
+
+/// The frame type for a route advertisement. The payload is the number of
+/// routes as a u16 and then for each the net ID as a u64 and the hops to it as
+/// a u8.
+pub const FRAME_ROUTES: u8 = 5;
+
+/// Set in the frame type when the payload is compressed.
+pub const FRAME_COMPRESSED: u8 = 0x80;
+
+/// The size of the type and the fields that come before the payload.
+pub const HEADER_SIZE: u64 = 1 + 1 + 8 * 7;
+/// The maximum frame size used unless another is set on the bridge. It counts
+/// the whole unit.
+pub const DEFAULT_MAXFRAME: u64 = 1024 * 1024 * 16;
+
+/// Returned by miniz when the output does not fit or the input is corrupt.
+const TINFL_DECOMPRESS_MEM_TO_MEM_FAILED: size_t = !0;
+
+// The miniz library is linked by `flate`, which only offers inflating into a
+// buffer that grows with out a limit.
+extern {
+    fn tinfl_decompress_mem_to_mem(
+        out: *mut c_void, outlen: size_t, src: *const c_void, srclen: size_t, flags: c_int
+    ) -> size_t;
+}
+
+/// A single frame as it is on the link. The payload of a raw message is held
+/// as is so that it is only copied when it is written to the link.
+#[derive(Clone)]
+pub struct Frame {
+    pub msgtype:        u8,
+    /// The payload is compressed.
+    pub compressed:     bool,
+    /// The bridges the message crossed before this link.
+    pub hops:           u8,
+    pub srcsid:         ID,
+    pub srceid:         ID,
+    pub dstsid:         ID,
+    pub dsteid:         ID,
+    pub dstgid:         ID,
+    pub corid:          u64,
+    /// Identifies a broadcast so that a net only delivers it once.
+    pub msgid:          u64,
+    pub payload:        RawMessage,
+}
+
+impl Frame {
+    /// Create a frame from a message. Only raw and serial messages can be
+    /// framed, so `None` is returned for anything else.
+    pub fn from_message(msg: Message) -> Option<Frame> {
+        let hops = msg.hops;
+        let srcsid = msg.srcsid;
+        let srceid = msg.srceid;
+        let dstsid = msg.dstsid;
+        let dsteid = msg.dsteid;
+        let dstgid = msg.dstgid;
+        let corid = msg.corid;
+        let msgid = msg.msgid;
+
+        let (msgtype, payload) = match msg.payload {
+            MessagePayload::Raw(rmsg) => {
+                (FRAME_RAW, rmsg)
+            },
+            MessagePayload::Serial(smsg) => {
+                let tag = smsg.tag.as_bytes();
+                let mut payload = RawMessage::new(2 + tag.len() + smsg.payload.len());
+                payload.write_from_slice(0, &[(tag.len() >> 8) as u8, tag.len() as u8]);
+                payload.write_from_slice(2, tag);
+                payload.write_from_slice(2 + tag.len(), smsg.payload.as_slice());
+                (FRAME_SERIAL, payload)
+            },
+            _ => return None,
+        };
+
+        Some(Frame {
+            msgtype:    msgtype,
+            compressed: false,
+            hops:       hops,
+            srcsid:     srcsid,
+            srceid:     srceid,
+            dstsid:     dstsid,
+            dsteid:     dsteid,
+            dstgid:     dstgid,
+            corid:      corid,
+            msgid:      msgid,
+            payload:    payload,
+        })
+    }
+
+    /// Create a heartbeat frame of the type carrying the stamp.
+    pub fn heartbeat(msgtype: u8, stamp: u64) -> Frame {
+        let mut payload = RawMessage::new(8);
+        let bytes: Vec<u8> = range(0us, 8us).map(|n| (stamp >> (56 - n * 8)) as u8).collect();
+        payload.write_from_slice(0, bytes.as_slice());
+
+        Frame {
+            msgtype:    msgtype,
+            compressed: false,
+            hops:       0,
+            srcsid:     0,
+            srceid:     0,
+            dstsid:     0,
+            dsteid:     0,
+            dstgid:     0,
+            corid:      0,
+            msgid:      0,
+            payload:    payload,
+        }
+    }
+
+    /// Return true if this is a heartbeat frame instead of a message.
+    pub fn is_heartbeat(&self) -> bool {
+        self.msgtype == FRAME_PING || self.msgtype == FRAME_PONG
+    }
+
+    /// Get the stamp carried by a heartbeat frame.
+    pub fn getstamp(&self) -> IoResult<u64> {
+        let payload = self.payload.as_slice();
+
+        if payload.len() != 8 {
+            return Err(WaterError::Protocol(format!("heartbeat payload size {} is invalid", payload.len())));
+        }
+
+        Ok(payload.iter().fold(0u64, |stamp, byte| (stamp << 8) | *byte as u64))
+    }
+
+    /// Create a route advertisement frame carrying the net IDs and the hops to each.
+    pub fn routes(routes: &[(ID, u8)]) -> Frame {
+        let mut w = MemWriter::with_capacity(2 + routes.len() * 9);
+        // Writing to memory can not fail.
+        w.write_be_u16(routes.len() as u16);
+        for &(sid, hops) in routes.iter() {
+            w.write_be_u64(sid);
+            w.write_u8(hops);
+        }
+        let body = w.into_inner();
+
+        let mut frame = Frame::heartbeat(FRAME_ROUTES, 0);
+        frame.payload = RawMessage::new(body.len());
+        frame.payload.write_from_slice(0, body.as_slice());
+        frame
+    }
+
+    /// Get the routes carried by a route advertisement frame.
+    pub fn getroutes(&self) -> IoResult<Vec<(ID, u8)>> {
+        let payload = self.payload.as_slice();
+
+        if payload.len() < 2 {
+            return Err(WaterError::Protocol(String::from_str("route frame has no count")));
+        }
+
+        let count = ((payload[0] as usize) << 8) | payload[1] as usize;
+
+        if payload.len() != 2 + count * 9 {
+            return Err(WaterError::Protocol(format!("route frame count {} is invalid", count)));
+        }
+
+        let mut r = BufReader::new(payload.slice_from(2));
+        let mut routes: Vec<(ID, u8)> = Vec::with_capacity(count);
+        for _ in range(0us, count) {
+            let sid = try!(r.read_be_u64());
+            let hops = try!(r.read_u8());
+            routes.push((sid, hops));
+        }
+
+        Ok(routes)
+    }
+
+    /// Compress the payload. If it does not get any smaller it is left alone
+    /// and false is returned.
+    pub fn compress(&mut self) -> bool {
+        if self.compressed {
+            return true;
+        }
+
+        let body = match deflate_bytes(self.payload.as_slice()) {
+            Some(body) => body,
+            None => return false,
+        };
+
+        if 8 + body.len() >= self.payload.len() {
+            return false;
+        }
+
+        let size = self.payload.len() as u64;
+        let mut payload = RawMessage::new(8 + body.len());
+        let bytes: Vec<u8> = range(0us, 8us).map(|n| (size >> (56 - n * 8)) as u8).collect();
+        payload.write_from_slice(0, bytes.as_slice());
+        payload.write_from_slice(8, body.as_slice());
+
+        self.payload = payload;
+        self.compressed = true;
+        true
+    }
+
+    /// Decompress the payload. A payload that claims to be larger than `max`
+    /// once decompressed is refused before it is, and one that inflates to
+    /// more or less than it claims is refused as soon as that is known, so
+    /// never more than the claimed size is held.
+    pub fn decompress(&mut self, max: u64) -> IoResult<()> {
+        if !self.compressed {
+            return Ok(());
+        }
+
+        let payload = self.payload.as_slice();
+
+        if payload.len() < 8 {
+            return Err(WaterError::Protocol(String::from_str("compressed frame has no size")));
+        }
+
+        let size = payload.slice_to(8).iter().fold(0u64, |size, byte| (size << 8) | *byte as u64);
+
+        if size > max {
+            return Err(WaterError::Protocol(format!("decompressed size {} exceeds maximum {}", size, max)));
+        }
+
+        // The output buffer is exactly the claimed size so inflating stops
+        // there, instead of growing for as long as the remote wants.
+        let body = payload.slice_from(8);
+        let mut decompressed = RawMessage::new(size as usize);
+        let got = unsafe {
+            tinfl_decompress_mem_to_mem(
+                decompressed.as_mutslice().as_mut_ptr() as *mut c_void, size as size_t,
+                body.as_ptr() as *const c_void, body.len() as size_t, 0
+            )
+        };
+
+        if got == TINFL_DECOMPRESS_MEM_TO_MEM_FAILED {
+            return Err(WaterError::Protocol(format!("compressed frame is corrupt or larger than {}", size)));
+        }
+
+        if got as u64 != size {
+            return Err(WaterError::Protocol(format!("decompressed size {} is not {}", got, size)));
+        }
+
+        self.payload = decompressed;
+        self.compressed = false;
+        Ok(())
+    }
+
+    /// Turn the frame back into a message. The frame has to be decompressed
+    /// first.
+    pub fn into_message(self) -> IoResult<Message> {
+        if self.compressed {
+            return Err(WaterError::Protocol(String::from_str("frame is still compressed")));
+        }
+
+        let mut msg = match self.msgtype {
+            FRAME_RAW => {
+                Message::new_fromraw(self.payload)
+            },
+            FRAME_SERIAL => {
+                let payload = self.payload.as_slice();
+
+                if payload.len() < 2 {
+                    return Err(WaterError::Protocol(String::from_str("serial frame has no tag length")));
+                }
+
+                let taglen = ((payload[0] as usize) << 8) | payload[1] as usize;
+
+                if payload.len() < 2 + taglen {
+                    return Err(WaterError::Protocol(format!("serial frame tag length {} is invalid", taglen)));
+                }
+
+                let tag = match String::from_utf8(payload.slice(2, 2 + taglen).to_vec()) {
+                    Ok(tag) => tag,
+                    Err(_) => return Err(WaterError::Protocol(String::from_str("serial frame tag is invalid"))),
+                };
+
+                let body = payload.slice_from(2 + taglen);
+                let mut rmsg = RawMessage::new(body.len());
+                rmsg.write_from_slice(0, body);
+
+                let mut msg = Message::new_raw(0);
+                msg.payload = MessagePayload::Serial(SerialMessage::from_parts(tag, rmsg));
+                msg
+            },
+            _ => {
+                return Err(WaterError::Protocol(format!("frame type {} is unknown", self.msgtype)));
+            },
+        };
+
+        msg.hops = self.hops;
+        msg.srcsid = self.srcsid;
+        msg.srceid = self.srceid;
+        msg.dstsid = self.dstsid;
+        msg.dsteid = self.dsteid;
+        msg.dstgid = self.dstgid;
+        msg.corid = self.corid;
+        msg.msgid = self.msgid;
+        Ok(msg)
+    }
+
+    /// Encode everything before the payload. The unit is this followed by the
+    /// payload, which lets a link write the payload straight from the message.
+    pub fn encodeheader(&self) -> Vec<u8> {
+        let mut w = MemWriter::with_capacity(HEADER_SIZE as usize);
+        // Writing to memory can not fail.
+        w.write_u8(if self.compressed { self.msgtype | FRAME_COMPRESSED } else { self.msgtype });
+        w.write_u8(self.hops);
+        w.write_be_u64(self.srcsid);
+        w.write_be_u64(self.srceid);
+        w.write_be_u64(self.dstsid);
+        w.write_be_u64(self.dsteid);
+        w.write_be_u64(self.dstgid);
+        w.write_be_u64(self.corid);
+        w.write_be_u64(self.msgid);
+        w.into_inner()
+    }
+
+    /// Encode the frame as a unit.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut unit = self.encodeheader();
+        unit.push_all(self.payload.as_slice());
+        unit
+    }
+
+    /// Decode a frame from a unit.
+    pub fn decode(unit: &[u8]) -> IoResult<Frame> {
+        if (unit.len() as u64) < HEADER_SIZE {
+            return Err(WaterError::Protocol(format!("frame size {} is too small", unit.len())));
+        }
+
+        let mut r = BufReader::new(unit);
+
+        let msgtype = try!(r.read_u8());
+        let compressed = msgtype & FRAME_COMPRESSED != 0;
+        let msgtype = msgtype & !FRAME_COMPRESSED;
+
+        match msgtype {
+            FRAME_RAW | FRAME_SERIAL => { },
+            FRAME_PING | FRAME_PONG | FRAME_ROUTES if !compressed => { },
+            _ => return Err(WaterError::Protocol(format!("frame type {} is unknown", msgtype))),
+        }
+
+        let hops = try!(r.read_u8());
+
+        let srcsid = try!(r.read_be_u64());
+        let srceid = try!(r.read_be_u64());
+        let dstsid = try!(r.read_be_u64());
+        let dsteid = try!(r.read_be_u64());
+        let dstgid = try!(r.read_be_u64());
+        let corid = try!(r.read_be_u64());
+        let msgid = try!(r.read_be_u64());
+
+        let body = unit.slice_from(HEADER_SIZE as usize);
+        let mut payload = RawMessage::new(body.len());
+        payload.write_from_slice(0, body);
+
+        Ok(Frame {
+            msgtype:    msgtype,
+            compressed: compressed,
+            hops:       hops,
+            srcsid:     srcsid,
+            srceid:     srceid,
+            dstsid:     dstsid,
+            dsteid:     dsteid,
+            dstgid:     dstgid,
+            corid:      corid,
+            msgid:      msgid,
+            payload:    payload,
+        })
+    }
+}
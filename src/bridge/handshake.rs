@@ -0,0 +1,222 @@
+//! The handshake is the first unit exchanged in each direction when a link is
+//! established. Both sides write theirs and then read the other. It carries a
+//! magic number so that something which is not a water bridge is rejected
+//! right away, the protocol version, the net ID of the side that sent it, and
+//! the features that side supports. The link only uses the features that both
+//! sides support.
+//!
+//! A later version can append fields to the unit and still be read by this one.
+//!
+//! If a link has a shared key both sides then prove they know it before the
+//! link is used. Each sends a random nonce, and then the HMAC-SHA256 of the
+//! nonce of the other side, its own nonce and its own net ID. Since the nonce
+//! of the side checking an answer comes first, an answer can not be reflected
+//! back as its own. The key itself never crosses the link.
+
+use std::io::BufReader;
+use std::io::MemWriter;
+
+use openssl::crypto::hash::HashType;
+use openssl::crypto::hmac::HMAC;
+use openssl::crypto::rand::rand_bytes;
+
+use bridge::link::Link;
+
+use net::ID;
+use error::IoResult;
+use error::WaterError;
+
+/// Spells `WATR` and starts every handshake.
+pub const MAGIC: u32 = 0x57415452;
+/// The version of the bridge protocol implemented here. Version 2 added the hop
+/// count to frames and version 3 the broadcast ID.
+pub const VERSION: u16 = 3;
+
+/// Raw messages can be sent.
+pub const FEATURE_RAW: u32 = 1 << 0;
+/// Serial messages can be sent.
+pub const FEATURE_SERIAL: u32 = 1 << 1;
+/// Frames can be compressed.
+pub const FEATURE_COMPRESSION: u32 = 1 << 2;
+/// The link must be authenticated.
+pub const FEATURE_AUTH: u32 = 1 << 3;
+
+/// Heartbeat frames are answered.
+pub const FEATURE_HEARTBEAT: u32 = 1 << 4;
+/// Routes are advertised so that nets more than one bridge away can be reached.
+pub const FEATURE_ROUTING: u32 = 1 << 5;
+
+/// The features this implementation supports.
+pub const FEATURES: u32 = FEATURE_RAW | FEATURE_SERIAL | FEATURE_COMPRESSION | FEATURE_HEARTBEAT | FEATURE_ROUTING;
+
+/// The size of the fields known to this version.
+const UNIT_SIZE: u64 = 4 + 2 + 8 + 4;
+/// Anything larger is not a handshake.
+const UNIT_MAX: u64 = 1024;
+/// How long to wait for the remote handshake in milliseconds.
+const TIMEOUT_MS: u64 = 10000;
+/// The size of the nonce each side sends to authenticate.
+const NONCE_SIZE: usize = 32;
+
+/// The contents of a handshake unit.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Handshake {
+    pub version:        u16,
+    pub sid:            ID,
+    pub features:       u32,
+}
+
+impl Handshake {
+    /// Create the handshake for the local net.
+    pub fn new(sid: ID) -> Handshake {
+        Handshake {
+            version:    VERSION,
+            sid:        sid,
+            features:   FEATURES,
+        }
+    }
+
+    /// Encode the handshake as a unit.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = MemWriter::with_capacity(UNIT_SIZE as usize);
+        // Writing to memory can not fail.
+        w.write_be_u32(MAGIC);
+        w.write_be_u16(self.version);
+        w.write_be_u64(self.sid);
+        w.write_be_u32(self.features);
+        w.into_inner()
+    }
+
+    /// Decode a handshake from a unit. Any fields appended by a later version
+    /// are ignored.
+    pub fn decode(unit: &[u8]) -> IoResult<Handshake> {
+        if (unit.len() as u64) < UNIT_SIZE {
+            return Err(WaterError::Protocol(format!("handshake size {} is invalid", unit.len())));
+        }
+
+        let mut r = BufReader::new(unit);
+
+        let magic = try!(r.read_be_u32());
+        if magic != MAGIC {
+            return Err(WaterError::Protocol(format!("handshake magic {:x} is invalid", magic)));
+        }
+
+        let version = try!(r.read_be_u16());
+        let sid = try!(r.read_be_u64());
+        let features = try!(r.read_be_u32());
+
+        Ok(Handshake {
+            version:    version,
+            sid:        sid,
+            features:   features,
+        })
+    }
+
+    /// Check that the remote handshake is compatible with this one and return
+    /// the features both support.
+    pub fn negotiate(&self, remote: &Handshake) -> IoResult<u32> {
+        if remote.version != self.version {
+            return Err(WaterError::Protocol(format!(
+                "remote protocol version {} does not match {}", remote.version, self.version
+            )));
+        }
+
+        let features = self.features & remote.features;
+
+        if features & FEATURE_RAW == 0 {
+            return Err(WaterError::Protocol(String::from_str("remote does not support raw messages")));
+        }
+
+        // Authentication is not something we can skip just because the
+        // other side does not support it.
+        if (self.features | remote.features) & FEATURE_AUTH != 0 && features & FEATURE_AUTH == 0 {
+            return Err(WaterError::AuthFailed);
+        }
+
+        Ok(features)
+    }
+}
+
+/// Exchange handshakes over the link. Returns the remote handshake with its
+/// features replaced by the features negotiated for the link. The remote is
+/// refused if the link knows it may not use the net ID it sent.
+pub fn exchange(link: &mut Link, sid: ID) -> IoResult<Handshake> {
+    exchangeauth(link, sid, Option::None)
+}
+
+/// Exchange handshakes like `exchange`, and if there is a key then have both
+/// sides prove they know it. A remote that does not is `WaterError::AuthFailed`.
+pub fn exchangeauth(link: &mut Link, sid: ID, key: Option<&[u8]>) -> IoResult<Handshake> {
+    let mut local = Handshake::new(sid);
+    if key.is_some() {
+        local.features |= FEATURE_AUTH;
+    }
+
+    try!(link.establish());
+    try!(link.sendunit(local.encode().as_slice()));
+
+    let mut remote = try!(Handshake::decode(try!(recvsetup(link, UNIT_MAX)).as_slice()));
+    remote.features = try!(local.negotiate(&remote));
+
+    if !link.allows(remote.sid) {
+        return Err(WaterError::Protocol(format!("remote may not use net {}", remote.sid)));
+    }
+
+    // Messages are routed by net ID so the two sides can not share one.
+    if remote.sid == sid {
+        return Err(WaterError::Protocol(format!("remote uses the same net ID {}", sid)));
+    }
+
+    match key {
+        Some(key) => try!(authenticate(link, key, sid, remote.sid)),
+        None => { },
+    }
+
+    Ok(remote)
+}
+
+/// Receive a unit while the link is set up. Do not let a remote that never
+/// answers hold the link forever.
+fn recvsetup(link: &mut Link, max: u64) -> IoResult<Vec<u8>> {
+    link.settimeout(Some(TIMEOUT_MS));
+    let unit = link.recvunit(max);
+    link.settimeout(None);
+    unit
+}
+
+/// The answer proving the net knows the key.
+fn proof(key: &[u8], checker: &[u8], prover: &[u8], sid: ID) -> Vec<u8> {
+    let mut hmac = HMAC::new(HashType::SHA256, key);
+    hmac.update(checker);
+    hmac.update(prover);
+    hmac.update(range(0us, 8us).map(|n| (sid >> (56 - n * 8)) as u8).collect::<Vec<u8>>().as_slice());
+    hmac.finalize()
+}
+
+/// Have both sides prove that they know the key.
+fn authenticate(link: &mut Link, key: &[u8], local: ID, remote: ID) -> IoResult<()> {
+    let nonce = rand_bytes(NONCE_SIZE);
+    try!(link.sendunit(nonce.as_slice()));
+
+    let remotenonce = try!(recvsetup(link, NONCE_SIZE as u64));
+    if remotenonce.len() != NONCE_SIZE {
+        return Err(WaterError::Protocol(format!("nonce size {} is not {}", remotenonce.len(), NONCE_SIZE)));
+    }
+
+    try!(link.sendunit(proof(key, remotenonce.as_slice(), nonce.as_slice(), local).as_slice()));
+
+    let answer = try!(recvsetup(link, UNIT_MAX));
+    let expected = proof(key, nonce.as_slice(), remotenonce.as_slice(), remote);
+
+    // Look at every byte so the time taken does not tell how much was right.
+    let mut diff = if answer.len() == expected.len() { 0u8 } else { 1u8 };
+    for (a, b) in answer.iter().zip(expected.iter()) {
+        diff |= *a ^ *b;
+    }
+
+    if diff != 0 {
+        return Err(WaterError::AuthFailed);
+    }
+
+    Ok(())
+}
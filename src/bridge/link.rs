@@ -0,0 +1,309 @@
+//! A link is the connection between two nets as seen by the bridge. It moves
+//! units, which are whole byte chunks such as a handshake or a frame, and does
+//! not care what is inside them. A transport only has to provide a link to be
+//! used for a bridge.
+//!
+//! A stream link is used for transports that deliver a stream of bytes, such as
+//! TCP, and prefixes each unit with its length as a u64. A datagram link is used
+//! for transports that deliver whole datagrams and sends each unit as one.
+
+use std::io::IoErrorKind;
+use std::io::net::udp::UdpSocket;
+use std::io::net::ip::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use libc::c_int;
+
+use error::IoResult;
+use error::WaterError;
+use net::ID;
+
+/// The largest unit that fits into a single UDP datagram.
+pub const MAX_DATAGRAM: usize = 65507;
+
+/// The connection between two nets.
+pub trait Link: Send {
+    /// Send one unit.
+    fn sendunit(&mut self, unit: &[u8]) -> IoResult<()>;
+    /// Send one unit made of the parts one after another. A link should
+    /// override this if it can write the parts with out joining them first.
+    fn sendunitv(&mut self, parts: &[&[u8]]) -> IoResult<()> {
+        let mut unit: Vec<u8> = Vec::new();
+        for part in parts.iter() {
+            unit.push_all(*part);
+        }
+        self.sendunit(unit.as_slice())
+    }
+    /// Send the units one after another, each made of its parts. A link should
+    /// override this if it can send them with fewer writes.
+    fn sendbatch(&mut self, units: &[Vec<&[u8]>]) -> IoResult<()> {
+        for parts in units.iter() {
+            try!(self.sendunitv(parts.as_slice()));
+        }
+        Ok(())
+    }
+    /// Receive one unit. A unit larger than `max` is refused with
+    /// `WaterError::Protocol`. If the link was closed between units then
+    /// `WaterError::NetDisconnected` is returned.
+    fn recvunit(&mut self, max: u64) -> IoResult<Vec<u8>>;
+    /// Limit how long `recvunit` will block in milliseconds. `None` blocks forever.
+    fn settimeout(&mut self, ms: Option<u64>);
+    /// Close the link. Any thread blocked in `recvunit` on this link or on a
+    /// duplicate of it will return.
+    fn shutdown(&mut self);
+    /// Return another handle to the same link so that one thread can send while
+    /// another receives.
+    fn duplicate(&self) -> Box<Link>;
+    /// Get the address of the remote in the format the transport uses, or an
+    /// empty string if the transport can not tell.
+    fn getpeer(&self) -> String;
+    /// Called on the thread that does the handshake before it is done. A
+    /// transport with a handshake of its own, such as TLS, does it here.
+    fn establish(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+    /// Returns true if the remote proved that it may use the net ID. A link
+    /// that can not tell who the remote is allows any.
+    fn allows(&self, sid: ID) -> bool {
+        true
+    }
+    /// Get the descriptor of a link that is a plain stream of units prefixed
+    /// with their length, so that the event loop can read and write it itself.
+    /// Any other link returns `None` and is given its own threads.
+    fn getfd(&self) -> Option<c_int> {
+        None
+    }
+    /// Called when the event loop takes the descriptor and makes it
+    /// non-blocking. After that only `shutdown` and `getpeer` may be used on
+    /// the link or any duplicate of it, since anything else would fail with
+    /// `EAGAIN` instead of waiting.
+    fn setpolled(&mut self) { }
+}
+
+/// A byte stream that can carry a `StreamLink`.
+pub trait Stream: Reader + Writer + Clone + Send {
+    /// Close both directions of the stream.
+    fn closestream(&mut self);
+    /// Limit how long a read will block in milliseconds.
+    fn setreadtimeout(&mut self, ms: Option<u64>);
+    /// Get the address of the remote, or an empty string if it is not known.
+    fn peername(&mut self) -> String;
+    /// Get the descriptor of the stream if it can be polled.
+    fn getfd(&self) -> Option<c_int> {
+        None
+    }
+    /// Write the parts one after another. A stream should override this if it
+    /// can write them with out joining them first.
+    fn writev(&mut self, parts: &[&[u8]]) -> IoResult<()> {
+        let mut buf: Vec<u8> = Vec::with_capacity(parts.iter().fold(0us, |size, part| size + part.len()));
+        for part in parts.iter() {
+            buf.push_all(*part);
+        }
+        Ok(try!(self.write(buf.as_slice())))
+    }
+}
+
+/// Get the length prefix for a unit of the size.
+pub fn unitsize(size: usize) -> [u8; 8] {
+    let mut bytes = [0u8; 8];
+    for n in range(0us, 8us) {
+        bytes[n] = ((size as u64) >> (56 - n * 8)) as u8;
+    }
+    bytes
+}
+
+/// Read a unit that is prefixed with its length.
+pub fn readunit<R: Reader>(r: &mut R, max: u64) -> IoResult<Vec<u8>> {
+    let size = match r.read_be_u64() {
+        Ok(size) => size,
+        Err(ref e) if e.kind == IoErrorKind::EndOfFile => return Err(WaterError::NetDisconnected),
+        Err(e) => return Err(WaterError::BridgeFailure(format!("{}", e))),
+    };
+
+    // Refuse before anything is allocated for it.
+    if size > max {
+        return Err(WaterError::Protocol(format!("unit size {} exceeds maximum {}", size, max)));
+    }
+
+    Ok(try!(r.read_exact(size as usize)))
+}
+
+/// Write a unit prefixed with its length.
+pub fn writeunit<W: Writer>(w: &mut W, unit: &[u8]) -> IoResult<()> {
+    writeunitv(w, &[unit])
+}
+
+/// Write a unit made of the parts prefixed with its length.
+pub fn writeunitv<W: Writer>(w: &mut W, parts: &[&[u8]]) -> IoResult<()> {
+    let size = parts.iter().fold(0us, |size, part| size + part.len());
+    try!(w.write_be_u64(size as u64));
+    for part in parts.iter() {
+        try!(w.write(*part));
+    }
+    Ok(())
+}
+
+/// A link over a byte stream.
+pub struct StreamLink<S: Stream> {
+    stream:         S,
+    peer:           String,
+    /// Shared with every duplicate and set once the event loop has the stream.
+    polled:         Arc<AtomicBool>,
+}
+
+impl<S: Stream> StreamLink<S> {
+    pub fn new(mut stream: S) -> StreamLink<S> {
+        let peer = stream.peername();
+        StreamLink {
+            stream:     stream,
+            peer:       peer,
+            polled:     Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+impl<S: Stream + 'static> Link for StreamLink<S> {
+    fn sendunit(&mut self, unit: &[u8]) -> IoResult<()> {
+        self.sendunitv(&[unit])
+    }
+
+    fn sendunitv(&mut self, parts: &[&[u8]]) -> IoResult<()> {
+        self.sendbatch(&[parts.to_vec()])
+    }
+
+    fn sendbatch(&mut self, units: &[Vec<&[u8]>]) -> IoResult<()> {
+        debug_assert!(!self.polled.load(Ordering::Relaxed), "stream link written after it was given to the event loop");
+
+        let sizes: Vec<[u8; 8]> = units.iter().map(|parts| {
+            unitsize(parts.iter().fold(0us, |size, part| size + part.len()))
+        }).collect();
+
+        // The whole batch goes to the stream in one write.
+        let mut all: Vec<&[u8]> = Vec::new();
+        for (size, parts) in sizes.iter().zip(units.iter()) {
+            all.push(size.as_slice());
+            all.push_all(parts.as_slice());
+        }
+
+        self.stream.writev(all.as_slice())
+    }
+
+    fn recvunit(&mut self, max: u64) -> IoResult<Vec<u8>> {
+        debug_assert!(!self.polled.load(Ordering::Relaxed), "stream link read after it was given to the event loop");
+        readunit(&mut self.stream, max)
+    }
+
+    fn settimeout(&mut self, ms: Option<u64>) {
+        debug_assert!(!self.polled.load(Ordering::Relaxed), "stream link timeout set after it was given to the event loop");
+        self.stream.setreadtimeout(ms);
+    }
+
+    fn shutdown(&mut self) {
+        self.stream.closestream();
+    }
+
+    fn duplicate(&self) -> Box<Link> {
+        Box::new(StreamLink {
+            stream:     self.stream.clone(),
+            peer:       self.peer.clone(),
+            polled:     self.polled.clone(),
+        })
+    }
+
+    fn getpeer(&self) -> String {
+        self.peer.clone()
+    }
+
+    fn getfd(&self) -> Option<c_int> {
+        self.stream.getfd()
+    }
+
+    fn setpolled(&mut self) {
+        self.polled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A link over UDP to a single peer. Datagrams from anyone else are ignored.
+///
+/// _Nothing is retransmitted, so a datagram lost by the network loses the
+/// message it carried. Also a unit can be no larger than `MAX_DATAGRAM`._
+pub struct DatagramLink {
+    socket:         UdpSocket,
+    peer:           SocketAddr,
+    closed:         Arc<AtomicBool>,
+}
+
+impl DatagramLink {
+    /// Create a link that sends to and receives from `peer` on the socket.
+    pub fn new(socket: UdpSocket, peer: SocketAddr) -> DatagramLink {
+        DatagramLink {
+            socket:     socket,
+            peer:       peer,
+            closed:     Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+impl Link for DatagramLink {
+    fn sendunit(&mut self, unit: &[u8]) -> IoResult<()> {
+        if unit.len() > MAX_DATAGRAM {
+            return Err(WaterError::Protocol(format!("unit size {} exceeds maximum {}", unit.len(), MAX_DATAGRAM)));
+        }
+
+        Ok(try!(self.socket.send_to(unit, self.peer)))
+    }
+
+    fn recvunit(&mut self, max: u64) -> IoResult<Vec<u8>> {
+        let mut buf: Vec<u8> = Vec::with_capacity(MAX_DATAGRAM);
+        unsafe { buf.set_len(MAX_DATAGRAM) };
+
+        loop {
+            let (size, from) = try!(self.socket.recv_from(buf.as_mut_slice()));
+
+            if self.closed.load(Ordering::SeqCst) {
+                return Err(WaterError::NetDisconnected);
+            }
+
+            // A unit is never empty, and the empty datagram sent by `shutdown`
+            // could have come from a duplicate of this link.
+            if from != self.peer || size == 0 {
+                continue;
+            }
+
+            if size as u64 > max {
+                return Err(WaterError::Protocol(format!("unit size {} exceeds maximum {}", size, max)));
+            }
+
+            buf.truncate(size);
+            return Ok(buf);
+        }
+    }
+
+    fn settimeout(&mut self, ms: Option<u64>) {
+        self.socket.set_read_timeout(ms);
+    }
+
+    fn shutdown(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+        // There is no way to close a UDP socket under a blocked reader, so
+        // wake it with an empty datagram to itself.
+        match self.socket.socket_name() {
+            Ok(addr) => { self.socket.send_to(&[], addr); },
+            Err(_) => { },
+        }
+    }
+
+    fn duplicate(&self) -> Box<Link> {
+        Box::new(DatagramLink {
+            socket:     self.socket.clone(),
+            peer:       self.peer,
+            closed:     self.closed.clone(),
+        })
+    }
+
+    fn getpeer(&self) -> String {
+        format!("{}", self.peer)
+    }
+}
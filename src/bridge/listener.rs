@@ -0,0 +1,526 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::intrinsics::transmute;
+
+use std::io::IoError;
+use std::result::Result;
+use std::vec::Vec;
+use std::thread::Thread;
+use std::io::timer::sleep;
+use std::time::duration::Duration;
+use std::sync::atomic::Ordering;
+
+use time::Timespec;
+use time::get_time;
+
+use net::ID;
+use net::UNUSED_ID;
+use error::IoResult;
+use error::WaterError;
+use endpoint::Endpoint;
+use message::Message;
+use rawmessage::RawMessage;
+use net::Net;
+use bridge::runlink;
+use bridge::LinkClosed;
+use bridge::TerminateMessage;
+use bridge::BridgeOwner;
+use bridge::LinkCounters;
+use bridge::HeartbeatPolicy;
+use bridge::SourcePolicy;
+use bridge::CompressionStats;
+use bridge::event::publish;
+use bridge::event::BridgeEvent;
+use bridge::event::BridgeEventKind;
+use bridge::handshake::exchangeauth;
+use bridge::frame::DEFAULT_MAXFRAME;
+use bridge::link::Link;
+use bridge::Transport;
+use bridge::LinkAcceptor;
+
+/// A snapshot of a connection accepted by a listener.
+#[derive(Clone)]
+pub struct ConnectionInfo {
+    /// Identifies the connection to `BridgeListener::disconnect`.
+    pub id:             u64,
+    /// The address of the remote in the format the transport uses. It may be
+    /// empty if the transport can not tell.
+    pub addr:           String,
+    /// The net ID of the remote, or `UNUSED_ID` until the link is negotiated.
+    pub sid:            ID,
+    pub negotiated:     bool,
+    /// The bytes of the frames received, not counting framing.
+    pub bytesin:        u64,
+    /// The bytes of the frames sent, not counting framing.
+    pub bytesout:       u64,
+    pub msgsin:         u64,
+    pub msgsout:        u64,
+    /// The round trip time last measured by a heartbeat.
+    pub rtt:            Option<Duration>,
+    pub compression:    CompressionStats,
+    /// When the connection was accepted.
+    pub since:          Timespec,
+}
+
+struct Connection {
+    id:                 u64,
+    sid:                ID,
+    negotiated:         bool,
+    since:              Timespec,
+    /// Kept so the link can be closed from another thread.
+    link:               Box<Link>,
+    counters:           Arc<LinkCounters>,
+}
+
+/// Publishes the disconnect and removes the connection once its link is closed.
+struct Closed {
+    bridge:             BridgeListener,
+    id:                 u64,
+    event:              BridgeEvent,
+}
+
+impl LinkClosed for Closed {
+    fn linkclosed(&mut self) {
+        let mut event = self.event.clone();
+        event.kind = BridgeEventKind::Disconnected;
+        self.bridge.publish(event);
+        self.bridge.removeconnection(self.id);
+    }
+}
+
+pub struct Internal {
+    net:                Net,
+    transport:          Box<Transport>,
+    terminate:          bool,
+    /// Set while the accept thread is running.
+    accepting:          bool,
+    connections:        Vec<Connection>,
+    connid:             u64,
+    handshakefailures:  u64,
+    lasterror:          Option<WaterError>,
+    protoerrors:        u64,
+    heartbeat:          Option<HeartbeatPolicy>,
+    timeouts:           u64,
+    sourcepolicy:       SourcePolicy,
+    spoofed:            u64,
+    psk:                Option<Vec<u8>>,
+    authfailures:       u64,
+    compression:        Option<usize>,
+    maxframe:           u64,
+    acceptor:           Option<Box<LinkAcceptor>>,
+}
+
+/// This is a listener which handles accepting connections using a transport
+/// from a `BridgeConnector`. It automatically works with the connector to
+/// transport packets both directions providing a bridge between two nets.
+///
+/// You will normally create one with `Net::tcplisten` or `Net::unixlisten`.
+pub struct BridgeListener {
+    i:                  Arc<Mutex<Internal>>,
+}
+
+impl Clone for BridgeListener {
+    fn clone(&self) -> BridgeListener {
+        BridgeListener {
+            i:      self.i.clone(),
+        }
+    }
+}
+
+impl BridgeListener {
+    /// Stops accepting connections, closes every connection, and waits for
+    /// each to be gone. Once this returns nothing is left running for the
+    /// listener.
+    pub fn terminate(&mut self) {
+        {
+            let mut i = self.i.lock().unwrap();
+            i.terminate = true;
+            if i.acceptor.is_some() {
+                i.acceptor.as_mut().unwrap().closeaccept();
+            }
+            for conn in i.connections.iter_mut() {
+                conn.link.shutdown();
+            }
+        }
+
+        // The accept thread and closing connections take the lock as they
+        // exit, so it can not be held while waiting on them.
+        loop {
+            {
+                let i = self.i.lock().unwrap();
+                if !i.accepting && i.connections.len() == 0 {
+                    return;
+                }
+            }
+            sleep(Duration::milliseconds(10));
+        }
+    }
+
+    /// _(internal)_ This will set the acceptor.
+    pub fn setacceptor(&mut self, op: Option<Box<LinkAcceptor>>) {
+        self.i.lock().unwrap().acceptor = op;
+    }
+
+    /// This will determine if this listener has been slated for termination.
+    pub fn getterminate(&self) -> bool {
+        self.i.lock().unwrap().terminate
+    }
+
+    /// Get the address used to listen on. It is a String in the format used
+    /// by the transport, such as "<host/ip>:<port>" for TCP.
+    pub fn getaddr(&self) -> String {
+        self.i.lock().unwrap().transport.getaddr()
+    }
+
+    /// Get count of clients/connections. Just because a connection is active does
+    /// not mean that it has been negotiated therefore you should likely check `getnegcount`
+    /// instead. This is just left in for testing primarily.
+    pub fn getclientcount(&self) -> u64 {
+        self.i.lock().unwrap().connections.len() as u64
+    }
+
+    /// Get negotiated count. This represents the number of current successfully
+    /// negotiated links. It may be lower than `getclientcount()`.
+    pub fn getnegcount(&self) -> u64 {
+        self.i.lock().unwrap().connections.iter().filter(|conn| conn.negotiated).count() as u64
+    }
+
+    /// Get a snapshot of every current connection.
+    pub fn getconnections(&self) -> Vec<ConnectionInfo> {
+        self.i.lock().unwrap().connections.iter().map(|conn| {
+            ConnectionInfo {
+                id:         conn.id,
+                addr:       conn.link.getpeer(),
+                sid:        conn.sid,
+                negotiated: conn.negotiated,
+                bytesin:    conn.counters.bytesin.load(Ordering::Relaxed) as u64,
+                bytesout:   conn.counters.bytesout.load(Ordering::Relaxed) as u64,
+                msgsin:     conn.counters.msgsin.load(Ordering::Relaxed) as u64,
+                msgsout:    conn.counters.msgsout.load(Ordering::Relaxed) as u64,
+                rtt:        conn.counters.getrtt(),
+                compression: conn.counters.getcompression(),
+                since:      conn.since,
+            }
+        }).collect()
+    }
+
+    /// Close the connection with the ID from `getconnections`. It is gone from
+    /// the connections once its threads have exited. Returns false if there
+    /// is no such connection.
+    pub fn disconnect(&mut self, id: u64) -> bool {
+        let mut i = self.i.lock().unwrap();
+        for conn in i.connections.iter_mut() {
+            if conn.id == id {
+                conn.link.shutdown();
+                return true;
+            }
+        }
+        false
+    }
+
+    /// _(internal)_ Track a newly accepted link. Returns its connection ID, or
+    /// `None` if the listener is terminating and the link was closed instead.
+    fn addconnection(&mut self, link: &mut Box<Link>) -> Option<u64> {
+        let mut i = self.i.lock().unwrap();
+
+        // Checked under the lock so that `terminate` either sees this
+        // connection or it is never added.
+        if i.terminate {
+            link.shutdown();
+            return Option::None;
+        }
+
+        i.connid += 1;
+        let id = i.connid;
+        i.connections.push(Connection {
+            id:         id,
+            sid:        UNUSED_ID,
+            negotiated: false,
+            since:      get_time(),
+            link:       link.duplicate(),
+            counters:   Arc::new(LinkCounters::new()),
+        });
+        Option::Some(id)
+    }
+
+    /// _(internal)_ Mark the connection as negotiated and return its counters.
+    fn negotiated(&mut self, id: u64, sid: ID) -> Arc<LinkCounters> {
+        let mut i = self.i.lock().unwrap();
+        let conn = i.connections.iter_mut().find(|conn| conn.id == id).unwrap();
+        conn.sid = sid;
+        conn.negotiated = true;
+        conn.counters.clone()
+    }
+
+    fn removeconnection(&mut self, id: u64) {
+        self.i.lock().unwrap().connections.retain(|conn| conn.id != id);
+    }
+
+    /// _(internal)_ Record a link that failed to negotiate.
+    pub fn handshakefailed(&mut self, err: WaterError) {
+        let mut i = self.i.lock().unwrap();
+        i.handshakefailures += 1;
+        if err == WaterError::AuthFailed {
+            i.authfailures += 1;
+        }
+        i.lasterror = Option::Some(err);
+    }
+
+    /// Get the number of connections that were rejected because the handshake
+    /// failed, such as a peer with a different protocol version.
+    pub fn gethandshakefailures(&self) -> u64 {
+        self.i.lock().unwrap().handshakefailures
+    }
+
+    /// Get the number of links torn down because the remote sent a frame that
+    /// was malformed or larger than the maximum frame size.
+    pub fn getprotoerrors(&self) -> u64 {
+        self.i.lock().unwrap().protoerrors
+    }
+
+    /// Set the largest frame in bytes that will be accepted from the remote. A
+    /// larger frame tears down the link. This only applies to links that are
+    /// established after it is set.
+    pub fn setmaxframe(&mut self, maxframe: u64) {
+        self.i.lock().unwrap().maxframe = maxframe;
+    }
+
+    /// Get the largest frame in bytes that will be accepted from the remote.
+    pub fn getmaxframe(&self) -> u64 {
+        self.i.lock().unwrap().maxframe
+    }
+
+    /// Set the heartbeat policy, or `None` to not send heartbeats and never time
+    /// out a silent link. This only applies to links that are established
+    /// after it is set.
+    pub fn setheartbeat(&mut self, heartbeat: Option<HeartbeatPolicy>) {
+        self.i.lock().unwrap().heartbeat = heartbeat;
+    }
+
+    pub fn getheartbeat(&self) -> Option<HeartbeatPolicy> {
+        self.i.lock().unwrap().heartbeat
+    }
+
+    /// Get the number of links torn down because nothing was heard from the
+    /// remote for the heartbeat timeout.
+    pub fn gettimeouts(&self) -> u64 {
+        self.i.lock().unwrap().timeouts
+    }
+
+    /// Compress the payload of frames that are at least this many bytes, or
+    /// `None` to not compress. It is only done if the remote can decompress.
+    /// This only applies to links that are established after it is set.
+    pub fn setcompression(&mut self, threshold: Option<usize>) {
+        self.i.lock().unwrap().compression = threshold;
+    }
+
+    pub fn getcompression(&self) -> Option<usize> {
+        self.i.lock().unwrap().compression
+    }
+
+    /// Set the key that both sides have to prove they know before a link is
+    /// used, or `None` to not authenticate. The remote has to use the same.
+    /// This only applies to links that are established after it is set.
+    pub fn setpsk(&mut self, psk: Option<Vec<u8>>) {
+        self.i.lock().unwrap().psk = psk;
+    }
+
+    /// Get the number of handshakes that failed because the remote did not
+    /// prove it knows the key, or did not want to authenticate at all.
+    pub fn getauthfailures(&self) -> u64 {
+        self.i.lock().unwrap().authfailures
+    }
+
+    /// Set what is done with a message from the remote that claims to be from a
+    /// net the remote can not speak for. This only applies to links that are
+    /// established after it is set.
+    pub fn setsourcepolicy(&mut self, sourcepolicy: SourcePolicy) {
+        self.i.lock().unwrap().sourcepolicy = sourcepolicy;
+    }
+
+    pub fn getsourcepolicy(&self) -> SourcePolicy {
+        self.i.lock().unwrap().sourcepolicy
+    }
+
+    /// Get the number of messages received that claimed to be from a net the
+    /// remote can not speak for, whether they were rejected or rewritten.
+    pub fn getspoofed(&self) -> u64 {
+        self.i.lock().unwrap().spoofed
+    }
+
+    /// Get the last error that caused a connection to be rejected or torn down.
+    pub fn getlasterror(&self) -> Option<WaterError> {
+        self.i.lock().unwrap().lasterror.clone()
+    }
+
+    /// Negotiate the link and, if that succeeds, run the RX and TX threads for
+    /// it. This lasts as long as the connection and removes it at the end.
+    fn thread_connection(mut bridge: BridgeListener, mut link: Box<Link>, id: u64) {
+        let sid = bridge.i.lock().unwrap().net.getserveraddr();
+        let psk = bridge.i.lock().unwrap().psk.clone();
+
+        let remote = match exchangeauth(&mut *link, sid, psk.as_ref().map(|psk| psk.as_slice())) {
+            Ok(remote) => remote,
+            Err(e) => {
+                link.shutdown();
+                // Being closed by `terminate` is not the fault of the peer.
+                if !bridge.getterminate() {
+                    let mut event = BridgeEvent::new(BridgeEventKind::HandshakeFailed, link.getpeer());
+                    event.error = Option::Some(e.clone());
+                    bridge.handshakefailed(e);
+                    bridge.publish(event);
+                }
+                bridge.removeconnection(id);
+                return;
+            }
+        };
+
+        // A `terminate` during the handshake closed the link already, so it
+        // must not be added to the net or announced.
+        if bridge.getterminate() {
+            link.shutdown();
+            bridge.removeconnection(id);
+            return;
+        }
+
+        // The endpoint is used for both directions of the link. Its net ID is
+        // the remote net ID which is used to catch messages directed to go
+        // only onto the remote net, or for broadcast messages.
+        let mut event = BridgeEvent::new(BridgeEventKind::Connected, link.getpeer());
+        event.sid = remote.sid;
+
+        let net = bridge.i.lock().unwrap().net.clone();
+        let mut ep = Endpoint::new(remote.sid, net.get_neweid(), net.clone());
+        // Get unique group ID for control messages.
+        ep.setgid(net.get_neweid());
+        net.add_endpoint(ep.clone());
+        // Only counted as negotiated once messages can be routed to it.
+        let counters = bridge.negotiated(id, remote.sid);
+
+        // Messages for the remote are routed to the link from here on.
+        bridge.publish(event.clone());
+
+        // This thread is done with the link once it is running. A stream link
+        // is run by the event loop of the net, so a connection only has a
+        // thread of its own while it does the handshake.
+        let closed = Box::new(Closed { bridge: bridge.clone(), id: id, event: event });
+        runlink(bridge, ep, link, remote.features, counters, closed);
+    }
+
+    fn publish(&self, event: BridgeEvent) {
+        let net = self.i.lock().unwrap().net.clone();
+        publish(&net, event);
+    }
+
+    fn acceptloop(mut bridge: BridgeListener) {
+        let result = bridge.i.lock().unwrap().transport.listen();
+
+        let mut acceptor = match result {
+            Ok(acceptor) => acceptor,
+            Err(e) => {
+                bridge.i.lock().unwrap().lasterror = Option::Some(e);
+                return;
+            }
+        };
+
+        bridge.setacceptor(Option::Some(acceptor.duplicate()));
+        if bridge.getterminate() {
+            acceptor.closeaccept();
+            return;
+        }
+
+        loop {
+            match acceptor.accept() {
+                Err(e) => {
+                    // Once the acceptor is closed every accept fails.
+                    if bridge.getterminate() {
+                        return;
+                    }
+                    continue;
+                },
+                Ok(mut link) => {
+                    let id = match bridge.addconnection(&mut link) {
+                        Some(id) => id,
+                        None => return,
+                    };
+                    // The handshake is done on its own thread so that a slow
+                    // or silent peer can not hold up accepting others.
+                    let _bridge = bridge.clone();
+                    Thread::spawn(move || { BridgeListener::thread_connection(_bridge, link, id) });
+                }
+            }
+        }
+    }
+
+    pub fn thread_accept(bridge: BridgeListener) {
+        BridgeListener::acceptloop(bridge.clone());
+        bridge.i.lock().unwrap().accepting = false;
+    }
+
+    /// Create a listener that accepts links using the transport. This is how a new
+    /// transport is given a listener.
+    pub fn new(net: &Net, transport: Box<Transport>) -> BridgeListener {
+        let b = BridgeListener {
+            i: Arc::new(Mutex::new(Internal {
+                acceptor:       Option::None,
+                net:            net.clone(),
+                transport:      transport,
+                terminate:      false,
+                accepting:      true,
+                connections:    Vec::new(),
+                connid:         0,
+                handshakefailures: 0,
+                lasterror:      Option::None,
+                protoerrors:    0,
+                heartbeat:      Option::Some(HeartbeatPolicy::new()),
+                timeouts:       0,
+                sourcepolicy:   SourcePolicy::Reject,
+                spoofed:        0,
+                psk:            Option::None,
+                authfailures:   0,
+                compression:    Option::None,
+                maxframe:       DEFAULT_MAXFRAME,
+            })),
+        };
+
+        let bclone = b.clone();
+        Thread::spawn(move || { BridgeListener::thread_accept(bclone) });
+
+        b
+    }
+
+}
+
+impl BridgeOwner for BridgeListener {
+    fn getmaxframe(&self) -> u64 {
+        self.i.lock().unwrap().maxframe
+    }
+
+    fn protoerror(&mut self, err: WaterError) {
+        let mut i = self.i.lock().unwrap();
+        i.protoerrors += 1;
+        i.lasterror = Option::Some(err);
+    }
+
+    fn getheartbeat(&self) -> Option<HeartbeatPolicy> {
+        self.i.lock().unwrap().heartbeat
+    }
+
+    fn timedout(&mut self) {
+        let mut i = self.i.lock().unwrap();
+        i.timeouts += 1;
+        i.lasterror = Option::Some(WaterError::TimedOut);
+    }
+
+    fn getsourcepolicy(&self) -> SourcePolicy {
+        self.i.lock().unwrap().sourcepolicy
+    }
+
+    fn spoofed(&mut self) {
+        self.i.lock().unwrap().spoofed += 1;
+    }
+
+    fn getcompression(&self) -> Option<usize> {
+        self.i.lock().unwrap().compression
+    }
+}
@@ -0,0 +1,669 @@
+#![allow(unused_mut)]
+
+//! This implements the generic bridge components that are used for the
+//! different network transports. By having a generic implementation it
+//! prevent duplication of code and potential of bugs.
+//!
+//! A transport only has to implement `Transport` which provides a `Link` for
+//! each connection. The listener, connector, framing, handshake, and routing
+//! onto the net are all done here.
+//!
+//! _You will not normally use any of these facilities directly unless
+//! you are building a new bridge._
+
+use endpoint::Endpoint;
+use message::Message;
+use error::WaterError;
+use Duration;
+
+use error::IoResult;
+
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::sync::atomic::AtomicUint;
+use std::sync::atomic::Ordering;
+use std::cmp::min;
+use std::thread::Thread;
+
+use time::get_time;
+
+use bridge::frame::Frame;
+use bridge::frame::FRAME_PING;
+use bridge::frame::FRAME_PONG;
+use bridge::frame::FRAME_ROUTES;
+use net::MAX_HOPS;
+use net::UNUSED_ID;
+use bridge::link::Link;
+use bridge::link::unitsize;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+use bridge::eventloop::Conn;
+
+pub use bridge::listener::BridgeListener;
+pub use bridge::listener::ConnectionInfo;
+pub use bridge::event::BridgeEvent;
+pub use bridge::event::BridgeEventKind;
+pub use bridge::event::BRIDGE_EVENT_GID;
+pub use bridge::connector::BridgeConnector;
+pub use bridge::connector::ReconnectPolicy;
+pub use bridge::connector::ConnectorState;
+
+pub mod frame;
+pub mod handshake;
+pub mod link;
+pub mod listener;
+pub mod connector;
+pub mod event;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub mod eventloop;
+#[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+#[path = "noeventloop.rs"]
+pub mod eventloop;
+
+/// Given to the endpoint of a link to make its TX thread close the link.
+pub struct TerminateMessage;
+
+impl Copy for TerminateMessage { }
+
+impl Clone for TerminateMessage {
+    fn clone(&self) -> TerminateMessage { TerminateMessage }
+}
+
+/// Given to the endpoint of every link by the net when its routes change so
+/// that the TX thread advertises them again.
+pub struct RouteUpdateMessage;
+
+impl Copy for RouteUpdateMessage { }
+
+impl Clone for RouteUpdateMessage {
+    fn clone(&self) -> RouteUpdateMessage { RouteUpdateMessage }
+}
+
+/// Given to the endpoint of a link by its RX thread to have the TX thread
+/// answer a heartbeat.
+pub struct PongMessage {
+    pub stamp:          u64,
+}
+
+impl Copy for PongMessage { }
+
+impl Clone for PongMessage {
+    fn clone(&self) -> PongMessage { PongMessage { stamp: self.stamp } }
+}
+
+/// How often a link sends a heartbeat and how long it may go without hearing
+/// anything from the remote before it is torn down as dead. Heartbeats are
+/// only used if the remote supports them.
+#[derive(Clone, Copy, Debug)]
+pub struct HeartbeatPolicy {
+    pub interval:       Duration,
+    pub timeout:        Duration,
+}
+
+impl HeartbeatPolicy {
+    /// Send every 5 seconds and give up after 15 seconds of silence.
+    pub fn new() -> HeartbeatPolicy {
+        HeartbeatPolicy {
+            interval:   Duration::seconds(5),
+            timeout:    Duration::seconds(15),
+        }
+    }
+}
+
+/// What a link does with a message from the remote that claims to be from a net
+/// the remote can not speak for. It may speak for its own net and for the nets
+/// it advertised routes to. A unicast from them must also come over the
+/// shortest route, while a broadcast may come over any. It never speaks for
+/// the local net.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SourcePolicy {
+    /// Drop the message.
+    Reject,
+    /// Deliver the message as from the remote net and the unused endpoint ID.
+    Rewrite,
+    /// Deliver the message as it is. Only for a remote that is trusted.
+    Trust,
+}
+
+/// The most messages the TX thread takes from the endpoint for one write.
+pub const BATCH_MSGS: usize = 64;
+/// The TX thread stops taking messages for a write once this many bytes wait.
+pub const BATCH_BYTES: usize = 256 * 1024;
+
+/// The current time in microseconds, which is what heartbeats carry.
+pub fn nowus() -> u64 {
+    let now = get_time();
+    now.sec as u64 * 1000000 + now.nsec as u64 / 1000
+}
+
+/// How well compression did on a link. Only compressed frames are counted, and
+/// by their payload.
+#[derive(Clone, Copy, Debug)]
+pub struct CompressionStats {
+    /// The bytes received compressed.
+    pub compressedin:       u64,
+    /// The same bytes once decompressed.
+    pub uncompressedin:     u64,
+    /// The bytes sent compressed.
+    pub compressedout:      u64,
+    /// The same bytes before they were compressed.
+    pub uncompressedout:    u64,
+}
+
+/// The traffic over one link, updated by its RX and TX threads.
+pub struct LinkCounters {
+    pub bytesin:        AtomicUint,
+    pub bytesout:       AtomicUint,
+    pub msgsin:         AtomicUint,
+    pub msgsout:        AtomicUint,
+    /// When anything was last received, from `nowus`.
+    pub lastrecv:       AtomicUint,
+    /// The last round trip time in microseconds, or zero if not measured yet.
+    pub rtt:            AtomicUint,
+    pub compressedin:   AtomicUint,
+    pub uncompressedin: AtomicUint,
+    pub compressedout:  AtomicUint,
+    pub uncompressedout: AtomicUint,
+}
+
+impl LinkCounters {
+    pub fn new() -> LinkCounters {
+        LinkCounters {
+            bytesin:    AtomicUint::new(0),
+            bytesout:   AtomicUint::new(0),
+            msgsin:     AtomicUint::new(0),
+            msgsout:    AtomicUint::new(0),
+            lastrecv:   AtomicUint::new(nowus() as usize),
+            rtt:        AtomicUint::new(0),
+            compressedin: AtomicUint::new(0),
+            uncompressedin: AtomicUint::new(0),
+            compressedout: AtomicUint::new(0),
+            uncompressedout: AtomicUint::new(0),
+        }
+    }
+
+    /// Get the last round trip time measured by a heartbeat.
+    pub fn getrtt(&self) -> Option<Duration> {
+        match self.rtt.load(Ordering::Relaxed) {
+            0 => Option::None,
+            rtt => Option::Some(Duration::microseconds(rtt as i64)),
+        }
+    }
+
+    pub fn getcompression(&self) -> CompressionStats {
+        CompressionStats {
+            compressedin:       self.compressedin.load(Ordering::Relaxed) as u64,
+            uncompressedin:     self.uncompressedin.load(Ordering::Relaxed) as u64,
+            compressedout:      self.compressedout.load(Ordering::Relaxed) as u64,
+            uncompressedout:    self.uncompressedout.load(Ordering::Relaxed) as u64,
+        }
+    }
+}
+
+/// Implemented by a transport so that a `BridgeListener` and `BridgeConnector`
+/// can be built on it.
+pub trait Transport: Send {
+    /// Get the address in the format the transport uses.
+    fn getaddr(&self) -> String;
+    /// Start listening for links on the address.
+    fn listen(&self) -> IoResult<Box<LinkAcceptor>>;
+    /// Establish a link to the address.
+    fn connect(&self) -> IoResult<Box<Link>>;
+}
+
+/// Accepts links for a `BridgeListener`.
+pub trait LinkAcceptor: Send {
+    /// Block until a link is accepted.
+    fn accept(&mut self) -> IoResult<Box<Link>>;
+    /// Stop accepting. Any thread blocked in `accept` on this acceptor or on a
+    /// duplicate of it will return an error, as will every later `accept`.
+    fn closeaccept(&mut self);
+    /// Return another handle to the same acceptor.
+    fn duplicate(&self) -> Box<LinkAcceptor>;
+}
+
+/// Implemented by the object that owns the links of a bridge, such as a listener
+/// or connector, so that the RX and TX threads can report back to it.
+pub trait BridgeOwner: Send {
+    /// Get the maximum frame size accepted by the bridge.
+    fn getmaxframe(&self) -> u64;
+    /// Record a protocol error on the bridge.
+    fn protoerror(&mut self, err: WaterError);
+    /// Get the heartbeat policy for links, or `None` to not send heartbeats.
+    fn getheartbeat(&self) -> Option<HeartbeatPolicy>;
+    /// Record a link torn down because the remote went silent.
+    fn timedout(&mut self);
+    /// Get the policy for messages with a source the remote can not speak for.
+    fn getsourcepolicy(&self) -> SourcePolicy;
+    /// Record a message with a source the remote can not speak for.
+    fn spoofed(&mut self);
+    /// Get the smallest payload that is compressed, or `None` to not compress.
+    fn getcompression(&self) -> Option<usize>;
+}
+
+/// Told when a link given to `runlink` has closed and nothing is left running
+/// for it.
+pub trait LinkClosed: Send {
+    fn linkclosed(&mut self);
+}
+
+impl LinkClosed for Sender<()> {
+    fn linkclosed(&mut self) {
+        self.send(()).ok();
+    }
+}
+
+/// Run the link until it is closed and then tell `closed`. The handshake has
+/// already been exchanged, and the endpoint given the remote net ID and added
+/// to the net, before this is called. It returns right away.
+///
+/// A link that can be polled is given to the event loop of the net so that it
+/// does not need any threads of its own. Any other link is given an RX and TX
+/// thread.
+pub fn runlink<B: BridgeOwner + Clone>(
+    owner: B, ep: Endpoint, link: Box<Link>, features: u32, counters: Arc<LinkCounters>, closed: Box<LinkClosed>
+) {
+    let (owner, ep, link, counters, closed) = match runonloop(owner, ep, link, features, counters, closed) {
+        Some(parts) => parts,
+        None => return,
+    };
+
+    Thread::spawn(move || {
+        let mut closed = closed;
+        let _owner = owner.clone();
+        let _ep = ep.clone();
+        let _link = link.duplicate();
+        let _counters = counters.clone();
+        let rxthread = Thread::scoped(move || { thread_rx(_owner, _ep, _link, _counters) });
+        thread_tx(owner, ep, link, features, counters);
+        rxthread.join();
+        closed.linkclosed();
+    });
+}
+
+/// Give the link to the event loop of the net if it can be polled. Everything
+/// is handed back if it has to be run on threads instead.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn runonloop<B: BridgeOwner + Clone>(
+    owner: B, ep: Endpoint, link: Box<Link>, features: u32, counters: Arc<LinkCounters>, closed: Box<LinkClosed>
+) -> Option<(B, Endpoint, Box<Link>, Arc<LinkCounters>, Box<LinkClosed>)> {
+    if link.getfd().is_none() {
+        return Some((owner, ep, link, counters, closed));
+    }
+
+    match ep.getnet().geteventloop() {
+        Ok(eventloop) => {
+            eventloop.add(Conn::new(Box::new(owner), ep, link, features, counters, closed));
+            None
+        },
+        // Threads still work if the event loop could not be started.
+        Err(_) => Some((owner, ep, link, counters, closed)),
+    }
+}
+
+/// There is no event loop on this target, so every link is run on threads.
+#[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+fn runonloop<B: BridgeOwner + Clone>(
+    owner: B, ep: Endpoint, link: Box<Link>, _features: u32, counters: Arc<LinkCounters>, closed: Box<LinkClosed>
+) -> Option<(B, Endpoint, Box<Link>, Arc<LinkCounters>, Box<LinkClosed>)> {
+    Some((owner, ep, link, counters, closed))
+}
+
+/// The handshake has already been exchanged, and the endpoint has been given
+/// the remote net ID, before this is started.
+///
+/// When the link is closed or anything malformed is read this gives the TX
+/// thread a `TerminateMessage` so that it closes the link and both exit.
+pub fn thread_rx<B: BridgeOwner>(mut owner: B, mut ep: Endpoint, mut link: Box<Link>, counters: Arc<LinkCounters>) {
+    let maxframe = owner.getmaxframe();
+    let sourcepolicy = owner.getsourcepolicy();
+
+    loop {
+        let result = match link.recvunit(maxframe) {
+            Ok(unit) => rxunit(&mut owner, &mut ep, unit.as_slice(), maxframe, sourcepolicy, &*counters),
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(_) => { },
+            Err(e) => {
+                match e {
+                    WaterError::Protocol(_) => owner.protoerror(e),
+                    _ => { },
+                }
+
+                // There is no way to find the start of the next frame so the
+                // whole link has to go.
+                ep.give(&Message::new_clone(TerminateMessage));
+                return;
+            },
+        }
+    }
+}
+
+/// Handle one unit received from the link. An error means the link has to be
+/// closed.
+fn rxunit(
+    owner: &mut BridgeOwner, ep: &mut Endpoint, unit: &[u8], maxframe: u64, sourcepolicy: SourcePolicy,
+    counters: &LinkCounters
+) -> IoResult<()> {
+    let net = ep.getnet();
+    let peer = ep.getsid();
+
+    let mut frame = try!(Frame::decode(unit));
+    counters.lastrecv.store(nowus() as usize, Ordering::Relaxed);
+
+    if frame.msgtype == FRAME_ROUTES {
+        net.setadvert(peer, try!(frame.getroutes()));
+        return Ok(());
+    }
+
+    if frame.is_heartbeat() {
+        return heartbeat(ep, &frame, counters);
+    }
+
+    // Only raw and serial messages can cross, since sync and clone messages
+    // hold type instances that only make sense in this process.
+    counters.bytesin.fetch_add(unit.len(), Ordering::Relaxed);
+    try!(decompress(&mut frame, maxframe, counters));
+    let mut msg = try!(frame.into_message());
+    counters.msgsin.fetch_add(1, Ordering::Relaxed);
+
+    // In a mesh a broadcast reaches us over every path, and one from this net
+    // can come back to it, but it is only delivered and forwarded the first
+    // time. A copy that is not new is dropped before the source is checked so
+    // that ordinary traffic is never counted or rewritten.
+    let broadcast = msg.dstsid == 0;
+    if broadcast && (msg.srcsid == net.getserveraddr() || net.isseen(msg.srcsid, msg.msgid)) {
+        return Ok(());
+    }
+    let srcsid = msg.srcsid;
+
+    // Nothing from the link is trusted, including who it says the message
+    // is from.
+    if !net.speaksfor(peer, msg.srcsid, broadcast) {
+        match sourcepolicy {
+            SourcePolicy::Reject => {
+                owner.spoofed();
+                return Ok(());
+            },
+            SourcePolicy::Rewrite => {
+                owner.spoofed();
+                msg.srcsid = peer;
+                msg.srceid = UNUSED_ID;
+            },
+            SourcePolicy::Trust => { },
+        }
+    }
+
+    // Remembered as it was sent, so the same copy is dropped again above even
+    // if it was rewritten.
+    if broadcast && msg.msgid != 0 && !net.markseen(srcsid, msg.msgid) {
+        return Ok(());
+    }
+
+    // We need to place the message onto the net so that that it can be
+    // routed to its one or more destinations.
+    net.forward(msg, ep);
+    Ok(())
+}
+
+/// Decompress the frame if it is compressed and count it.
+fn decompress(frame: &mut Frame, max: u64, counters: &LinkCounters) -> IoResult<()> {
+    if !frame.compressed {
+        return Ok(());
+    }
+
+    let size = frame.payload.len();
+    try!(frame.decompress(max));
+    counters.compressedin.fetch_add(size, Ordering::Relaxed);
+    counters.uncompressedin.fetch_add(frame.payload.len(), Ordering::Relaxed);
+    Ok(())
+}
+
+/// Answer a ping through the TX thread, or take the round trip time from a pong.
+fn heartbeat(ep: &mut Endpoint, frame: &Frame, counters: &LinkCounters) -> IoResult<()> {
+    let stamp = try!(frame.getstamp());
+
+    if frame.msgtype == FRAME_PING {
+        // Only the TX thread writes to the link.
+        ep.give(&Message::new_clone(PongMessage { stamp: stamp }));
+    } else {
+        let now = nowus();
+        if stamp <= now {
+            // Zero means not measured, so round up.
+            counters.rtt.store(if now - stamp > 0 { (now - stamp) as usize } else { 1 }, Ordering::Relaxed);
+        }
+    }
+
+    Ok(())
+}
+
+/// The `features` are those negotiated by the handshake, and only the message
+/// types they allow are forwarded.
+///
+/// If the remote supports heartbeats and the owner has a policy for them, this
+/// also sends a ping each interval and tears the link down once nothing has
+/// been received from the remote for the timeout.
+///
+/// If the remote can decompress and the owner has a threshold, a payload at
+/// least that large is compressed when that makes it smaller.
+///
+/// Every message already waiting on the endpoint, up to `BATCH_MSGS` or
+/// `BATCH_BYTES`, is written to the link at once with `Link::sendbatch`.
+pub fn thread_tx<B: BridgeOwner>(mut owner: B, mut ep: Endpoint, mut link: Box<Link>, features: u32, counters: Arc<LinkCounters>) {
+    let policy = if features & handshake::FEATURE_HEARTBEAT != 0 { owner.getheartbeat() } else { Option::None };
+    let compression = if features & handshake::FEATURE_COMPRESSION != 0 { owner.getcompression() } else { Option::None };
+    let mut lastping = nowus();
+
+    // This also has every link, including this one, advertise its routes.
+    let net = ep.getnet();
+    let neighbor = ep.getsid();
+    net.addneighbor(neighbor);
+
+    tx(&mut owner, &mut ep, &mut *link, features, &*counters, policy, compression, &mut lastping);
+
+    net.removeneighbor(neighbor);
+}
+
+/// Frames waiting to be written to the link together.
+struct Batch {
+    /// Each frame with its encoded header.
+    frames:         Vec<(Vec<u8>, Frame)>,
+    bytes:          usize,
+    /// The frames carrying messages, which are what the counters count.
+    msgs:           usize,
+    msgbytes:       usize,
+}
+
+impl Batch {
+    fn new() -> Batch {
+        Batch {
+            frames:     Vec::new(),
+            bytes:      0,
+            msgs:       0,
+            msgbytes:   0,
+        }
+    }
+
+    fn push(&mut self, frame: Frame, ismsg: bool) {
+        let header = frame.encodeheader();
+        let size = header.len() + frame.payload.len();
+        self.bytes += size;
+        if ismsg {
+            self.msgs += 1;
+            self.msgbytes += size;
+        }
+        self.frames.push((header, frame));
+    }
+
+    fn isfull(&self) -> bool {
+        self.frames.len() >= BATCH_MSGS || self.bytes >= BATCH_BYTES
+    }
+
+    /// Write every frame to the link at once.
+    fn flush(&mut self, link: &mut Link, counters: &LinkCounters) -> IoResult<()> {
+        if self.frames.len() == 0 {
+            return Ok(());
+        }
+
+        {
+            let units: Vec<Vec<&[u8]>> = self.frames.iter().map(|&(ref header, ref frame)| {
+                vec![header.as_slice(), frame.payload.as_slice()]
+            }).collect();
+            try!(link.sendbatch(units.as_slice()));
+        }
+
+        self.sent(counters);
+        Ok(())
+    }
+
+    /// Append every frame to `out` as the units of a stream link would be
+    /// written, for the event loop to write.
+    fn encode(&mut self, out: &mut Vec<u8>, counters: &LinkCounters) {
+        for &(ref header, ref frame) in self.frames.iter() {
+            out.push_all(&unitsize(header.len() + frame.payload.len()));
+            out.push_all(header.as_slice());
+            out.push_all(frame.payload.as_slice());
+        }
+
+        self.sent(counters);
+    }
+
+    fn sent(&mut self, counters: &LinkCounters) {
+        counters.bytesout.fetch_add(self.msgbytes, Ordering::Relaxed);
+        counters.msgsout.fetch_add(self.msgs, Ordering::Relaxed);
+
+        self.frames.clear();
+        self.bytes = 0;
+        self.msgs = 0;
+        self.msgbytes = 0;
+    }
+}
+
+fn tx<B: BridgeOwner>(
+    owner: &mut B, ep: &mut Endpoint, link: &mut Link, features: u32, counters: &LinkCounters,
+    policy: Option<HeartbeatPolicy>, compression: Option<usize>, lastping: &mut u64
+) {
+    let mut batch = Batch::new();
+
+    loop {
+        let result = match policy {
+            Some(policy) => ep.recvorblock(min(policy.interval, policy.timeout)),
+            None => ep.recvorblock(Duration::seconds(900)),
+        };
+
+        if !keepalive(&mut *owner, counters, policy, lastping, &mut batch) {
+            // This also wakes the RX thread.
+            link.shutdown();
+            return;
+        }
+
+        // Take whatever else is already waiting so that it all goes out with
+        // one write instead of one for each message.
+        let mut next = result.ok();
+        while let Some(msg) = next {
+            if !txmessage(ep, features, compression, counters, msg, &mut batch) {
+                // This should cause the RX thread to terminate, but what was
+                // taken before it still goes out.
+                batch.flush(link, counters);
+                link.shutdown();
+                return;
+            }
+            next = if batch.isfull() { Option::None } else { ep.recv().ok() };
+        }
+
+        if batch.flush(link, counters).is_err() {
+            // The RX thread will see the link close and exit.
+            link.shutdown();
+            return;
+        }
+    }
+}
+
+/// Add a ping to the batch if one is due. Returns false if nothing has been
+/// heard from the remote for the timeout and the link has to be closed.
+fn keepalive(
+    owner: &mut BridgeOwner, counters: &LinkCounters, policy: Option<HeartbeatPolicy>, lastping: &mut u64,
+    batch: &mut Batch
+) -> bool {
+    let policy = match policy {
+        Some(policy) => policy,
+        None => return true,
+    };
+
+    let now = nowus();
+    let lastrecv = counters.lastrecv.load(Ordering::Relaxed) as u64;
+
+    if now > lastrecv && (now - lastrecv) as i64 > policy.timeout.num_microseconds().unwrap_or(0) {
+        // A half-open connection never errors, so this is the only way to
+        // find out.
+        owner.timedout();
+        return false;
+    }
+
+    if now > *lastping && (now - *lastping) as i64 >= policy.interval.num_microseconds().unwrap_or(0) {
+        *lastping = now;
+        batch.push(Frame::heartbeat(FRAME_PING, now), false);
+    }
+
+    true
+}
+
+/// Add the frame for the message to the batch, if it has one. Returns false if
+/// the link has to be closed.
+fn txmessage(
+    ep: &mut Endpoint, features: u32, compression: Option<usize>, counters: &LinkCounters,
+    msg: Message, batch: &mut Batch
+) -> bool {
+    // Check for termination message.
+    if msg.is_type::<TerminateMessage>() {
+        return false;
+    }
+
+    if msg.is_type::<RouteUpdateMessage>() {
+        if features & handshake::FEATURE_ROUTING != 0 {
+            batch.push(Frame::routes(ep.getnet().getadvert(ep.getsid()).as_slice()), false);
+        }
+        return true;
+    }
+
+    if msg.is_type::<PongMessage>() {
+        let stamp = msg.typeunwrap::<PongMessage>().stamp;
+        batch.push(Frame::heartbeat(FRAME_PONG, stamp), false);
+        return true;
+    }
+
+    if msg.is_serial() && features & handshake::FEATURE_SERIAL == 0 {
+        return true;
+    }
+
+    // It has gone around too many bridges and is likely in a loop.
+    if msg.hops >= MAX_HOPS {
+        return true;
+    }
+
+    // We only forward raw and serial messages. We do not support the
+    // ability to properly send sync and clone messages (both because they
+    // may contain pointers which we can not properly handle). And, the
+    // way they would be expected to work even if we could send them
+    // would not be able to work. A serial message is already encoded.
+    let mut frame = match Frame::from_message(msg) {
+        Some(frame) => frame,
+        None => return true,
+    };
+    frame.hops += 1;
+
+    match compression {
+        Some(threshold) if frame.payload.len() >= threshold => {
+            let size = frame.payload.len();
+            if frame.compress() {
+                counters.compressedout.fetch_add(frame.payload.len(), Ordering::Relaxed);
+                counters.uncompressedout.fetch_add(size, Ordering::Relaxed);
+            }
+        },
+        _ => { },
+    }
+
+    batch.push(frame, true);
+    true
+}
@@ -0,0 +1,16 @@
+//! Stands in for the event loop on targets other than Linux x86-64, where
+//! there is no `sys` to poll with. It can never be started, so `runlink` gives
+//! every link its own RX and TX threads.
+
+use error::IoResult;
+use error::WaterError;
+
+/// An event loop that can not be started on this target.
+pub struct EventLoop;
+
+impl EventLoop {
+    /// Always fails, which `Net::geteventloop` passes on.
+    pub fn new() -> IoResult<EventLoop> {
+        Err(WaterError::BridgeFailure(String::from_str("the event loop is only supported on Linux x86-64")))
+    }
+}
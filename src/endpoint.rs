@@ -1,4 +1,4 @@
-//! Here we implement the Endpoint and IoResult<T> which provide
+//! Here we implement the Endpoint which provides
 //! the core of the water library. These are to be some of the most used
 //! facilities when working with the water library. An endpoint forms
 //! the point of communication with other endpoints.
@@ -39,79 +39,49 @@ use timespec;
 use net::Net;
 use net::ID;
 use net::UNUSED_ID;
+use net::SendReport;
 use rawmessage::RawMessage;
 use message::Message;
 use message::MessagePayload;
- 
-/// This represents the exact failure code of the operation.
-pub enum IoErrorCode {
-    /// If the operation reaches the specified time to fail.
-    TimedOut,
-    /// There were no messages for the operation to succeed with.
-    NoMessages,
-}
-
-impl Copy for IoErrorCode { }
-
-/// This is returned by IoResult<T> when a error has occured. Some errors are normal
-/// and expected. You can read the `code` field to determine the exact error.
-pub struct IoError {
-    pub code:   IoErrorCode,
-}
-
-impl Copy for IoError { }
+use error::IoResult;
+use error::WaterError;
+use selector::Selector;
+use selector::SelectToken;
 
-/// Represents a successful return, or an I/O error. This deviates from the
-/// standard library IoResult and should not be confused. It deviates to
-/// provide a more specialized error value to indicate the exact problem.
-pub enum IoResult<T> {
-    Err(IoError),
-    Ok(T),
+use rustc_serialize::Encodable;
+ 
+/// The outcome of offering a message to a single endpoint.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Delivery {
+    /// The endpoint took the message.
+    Delivered,
+    /// The endpoint already has the limit of pending messages in its queue.
+    QueueFull,
+    /// The messages in the queue of the endpoint already use its limit of memory.
+    MemoryLimit,
+    /// The endpoint is not addressed by the message, or it has already been taken.
+    Filtered,
 }
 
-impl<T> IoResult<T> {
-    /// Will return the wrapped value consuming the IoResult in the process.
-    ///
-    /// _Will panic if not value._
-    pub fn ok(self) -> T {
-        match self {
-            IoResult::Ok(v) => v,
-            IoResult::Err(e) => panic!("not `IoResult::Ok`!"),
-        }
-    }
-
-    /// Will return the wrapped value consuming the IoResult in the process.
-    ///
-    /// _Will panic if not value._
-    pub fn unwrap(self) -> T {
-        self.ok()
-    }
-
-    /// Will return the wrapped error consuming the IoResult in the process.
-    ///
-    /// _Will panic if not error._
-    pub fn err(self) -> IoError {
-        match self {
-            IoResult::Ok(v) => panic!("not `IoResult::Err`!"),
-            IoResult::Err(e) => e,
-        }
-    }
-
-    /// Test if this contains a value and not an error. Will return `true` if contains a value.
-    pub fn is_ok(&self) -> bool {
-        match *self {
-            IoResult::Ok(_) => true,
-            IoResult::Err(_) => false,
-        }
-    }
-
-    /// Test if this contains a error and not a value. Will return `true` if contains a error.
-    pub fn is_err(&self) -> bool {
-        match *self {
-            IoResult::Ok(_) => false,
-            IoResult::Err(_) => true,
-        }
-    }
+/// A snapshot of the statistics of an endpoint.
+#[derive(Copy, Clone, Debug)]
+pub struct EndpointStats {
+    /// The number of messages taken into the queue.
+    pub msgsin:             usize,
+    /// The number of messages received out of the queue.
+    pub msgsout:            usize,
+    /// The number of messages currently in the queue.
+    pub pending:            usize,
+    /// The highest number of messages that have been in the queue at once.
+    pub peakpending:        usize,
+    /// The number of bytes used by the messages currently in the queue.
+    pub bytesqueued:        usize,
+    /// The number of messages rejected because of the pending limit.
+    pub droppedpending:     usize,
+    /// The number of messages rejected because of the memory limit.
+    pub droppedmemory:      usize,
+    /// The number of threads sleeping while waiting for a message.
+    pub sleepers:           usize,
 }
 
 struct SleepToken {
@@ -140,7 +110,7 @@ impl SleepToken {
 struct AddressData {
     eid:            ID,
     sid:            ID,
-    gid:            ID,
+    gids:           Vec<ID>,
 }
 
 struct Internal {
@@ -156,7 +126,16 @@ struct Internal {
     slpcnt:         AtomicUint,
     limitpending:   AtomicUint,
     limitmemory:    AtomicUint,
-    address:        Mutex<AddressData>
+    spacemutex:     Mutex<()>,
+    space:          Condvar,
+    spacewaiters:   AtomicUint,
+    msgsin:         AtomicUint,
+    msgsout:        AtomicUint,
+    peakpending:    AtomicUint,
+    droppedpending: AtomicUint,
+    droppedmemory:  AtomicUint,
+    address:        Mutex<AddressData>,
+    selectors:      Mutex<Vec<Arc<SelectToken>>>,
 }
 
 unsafe impl Send for Internal { }
@@ -249,6 +228,41 @@ impl Internal {
         *self.wakeupat.lock().unwrap() = Timespec { sec: 0x7fffffffffffffffi64, nsec: 0i32 };
     }
 
+    /// Check if the limits for pending count and memory would allow a message to be taken.
+    fn checklimits(&self) -> Delivery {
+        let limitpending = self.limitpending.load(Ordering::Relaxed);
+        if limitpending > 0 && self.messages.len() >= limitpending {
+            return Delivery::QueueFull;
+        }
+
+        let limitmemory = self.limitmemory.load(Ordering::Relaxed);
+        if limitmemory > 0 && self.memoryused.load(Ordering::SeqCst) >= limitmemory {
+            return Delivery::MemoryLimit;
+        }
+
+        Delivery::Delivered
+    }
+
+    /// Raise the peak pending count if the queue is now deeper than it.
+    fn updatepeak(&self) {
+        let depth = self.messages.len();
+        loop {
+            let peak = self.peakpending.load(Ordering::Relaxed);
+            if depth <= peak || self.peakpending.compare_and_swap(peak, depth, Ordering::Relaxed) == peak {
+                return;
+            }
+        }
+    }
+
+    /// Wake any senders waiting for space in the queue. The lock makes sure we do not
+    /// signal in between a sender checking the limits and sleeping.
+    fn signalspace(&self) {
+        if self.spacewaiters.load(Ordering::SeqCst) > 0 {
+            let lock = self.spacemutex.lock().unwrap();
+            self.space.notify_all();
+        }
+    }
+
     /// Takes one message from the queue and returns it. It also attempts to duplicate
     /// the message if that is supported to prevent giving access to shared buffers.
     fn recv(&self) -> IoResult<Message> {
@@ -260,25 +274,37 @@ impl Internal {
             let result = self.messages.get();
 
             if result.is_none() {
-                return IoResult::Err(IoError { code: IoErrorCode::NoMessages });
+                return Err(WaterError::NoMessages);
             }
 
             let msg = result.unwrap(); 
             let msg = msg.dup_ifok();
             let sz = msg.cap();
             self.memoryused.fetch_sub(sz, Ordering::SeqCst);
+            self.signalspace();
 
             match msg.payload {
-                MessagePayload::Raw(_) => { return IoResult::Ok(msg.dup()); },
+                MessagePayload::Raw(_) => {
+                    self.msgsout.fetch_add(1, Ordering::Relaxed);
+                    return Ok(msg.dup());
+                },
                 MessagePayload::Sync(_) => {
                     // To support first recv for sync message we need to try
                     // to take the message first. If we can not take the message
                     // we ignore it and throw it away.
                     if msg.get_syncref().takeasvalid() {
-                        return IoResult::Ok(msg);
+                        self.msgsout.fetch_add(1, Ordering::Relaxed);
+                        return Ok(msg);
                     }
                 },
-                MessagePayload::Clone(_) => { return IoResult::Ok(msg); },
+                MessagePayload::Clone(_) => {
+                    self.msgsout.fetch_add(1, Ordering::Relaxed);
+                    return Ok(msg);
+                },
+                MessagePayload::Serial(_) => {
+                    self.msgsout.fetch_add(1, Ordering::Relaxed);
+                    return Ok(msg);
+                },
             }
         }
     }
@@ -315,16 +341,25 @@ impl Endpoint {
                 wakeupat:       Mutex::new(Timespec { nsec: 0i32, sec: 0x7fffffffffffffffi64 }),
                 limitpending:   AtomicUint::new(0),
                 limitmemory:    AtomicUint::new(0),
+                spacemutex:     Mutex::new(()),
+                space:          Condvar::new(),
+                spacewaiters:   AtomicUint::new(0),
+                msgsin:         AtomicUint::new(0),
+                msgsout:        AtomicUint::new(0),
+                peakpending:    AtomicUint::new(0),
+                droppedpending: AtomicUint::new(0),
+                droppedmemory:  AtomicUint::new(0),
                 memoryused:     AtomicUint::new(0),
                 address:        Mutex::new(AddressData {
                     sid:        sid,
                     eid:        eid,
-                    gid:        UNUSED_ID,
+                    gids:       Vec::new(),
                 }),
                 net:            net,
                 refcnt:         AtomicUint::new(1),
                 slpcnt:         AtomicUint::new(0),
                 dropped:        AtomicBool::new(false),
+                selectors:      Mutex::new(Vec::new()),
             }),
         }
     }
@@ -342,50 +377,68 @@ impl Endpoint {
         self.i.net.getepcount()
     }
 
-    /// _(internal usage)_ Give the endpoint a message.
+    /// _(internal usage)_ Give the endpoint a message. Returns `true` if it was taken.
     pub fn give(&self, msg: &Message) -> bool {
+        self.offer(msg) == Delivery::Delivered
+    }
+
+    /// _(internal usage)_ Give the endpoint a message and return the outcome.
+    pub fn offer(&self, msg: &Message) -> Delivery {
         let addr = self.i.address.lock().unwrap();
         let myeid = addr.eid;
         let mysid = addr.sid;
-        let mygid = addr.gid;
+        let ingroup = msg.dstgid == 0 || addr.gids.contains(&msg.dstgid);
         drop(addr);
 
-
         // Do not send to the endpoint that it originated from.
         if !msg.canloop && msg.srceid == myeid && msg.srcsid == mysid {
-            return false;
+            return Delivery::Filtered;
         }
 
+        // If we are not part of the local net we are a type of bridge.
+        let isbridge = mysid != self.i.net.getserveraddr();
+
         if msg.dstsid != 0 {
             if msg.dstsid != 1 {
-                // It must be to a specific net and we are not it.
+                // It must be to a specific net and we are not it, nor the
+                // bridge the net is reached through.
                 if msg.dstsid != mysid {
-                    return false;
+                    if !isbridge || self.i.net.getnexthop(msg.dstsid) != Some(mysid) {
+                        return Delivery::Filtered;
+                    }
                 }
             } else {
-                // If its too the local net, but we are not part of
-                // the local net. We are likely a type of bridge then
+                // If its too the local net, but we are a bridge then
                 // let us ignore it.
-                if mysid !=  self.i.net.getserveraddr() {
-                    return false;
+                if isbridge {
+                    return Delivery::Filtered;
                 }
             }
         }
 
-        // If it is to a secific endpoint and we are not it.
-        if msg.dsteid != 0 && msg.dsteid != myeid {
-            return false;
+        // A bridge carries the message to the remote net where the endpoint
+        // and group are checked, therefore, only check them if we are local.
+        if !isbridge {
+            // If it is to a secific endpoint and we are not it.
+            if msg.dsteid != 0 && msg.dsteid != myeid {
+                return Delivery::Filtered;
+            }
+
+            // If it is to a specific group and we are not a member.
+            if !ingroup {
+                return Delivery::Filtered;
+            }
         }
 
         // Check limits for pending count and memory.
-        let limitpending = self.i.limitpending.load(Ordering::Relaxed);
-        if limitpending > 0 && self.i.messages.len() >= limitpending {
-            return false;
+        let limits = self.i.checklimits();
+        match limits {
+            Delivery::QueueFull => { self.i.droppedpending.fetch_add(1, Ordering::Relaxed); },
+            Delivery::MemoryLimit => { self.i.droppedmemory.fetch_add(1, Ordering::Relaxed); },
+            _ => { },
         }
-
-        let limitmemory = self.i.limitmemory.load(Ordering::Relaxed);
-        if limitmemory > 0 && self.i.memoryused.load(Ordering::SeqCst) >= limitmemory {
-            return false;
+        if limits != Delivery::Delivered {
+            return limits;
         }
 
         let cloned;
@@ -407,12 +460,63 @@ impl Endpoint {
         // that they are sleeping waiting to receive.
         {
             let recvlock = self.i.waitmutex.lock().unwrap();
-            self.i.messages.put(cloned);
+            // The memory must be accounted for before the message can be seen
+            // by a receiver, or the receiver could subtract it first and wrap
+            // the counter around.
             self.i.memoryused.fetch_add(msg.cap(), Ordering::SeqCst);
+            self.i.messages.put(cloned);
+            self.i.msgsin.fetch_add(1, Ordering::Relaxed);
+            self.i.updatepeak();
         }
         // Wake up any who are waiting to receive.
         self.wakeonewaiter();
-        true
+        // Wake up any selectors waiting on us.
+        for token in self.i.selectors.lock().unwrap().iter() {
+            token.signal();
+        }
+        Delivery::Delivered
+    }
+
+    /// _(internal usage)_ Wait until the limits of the endpoint would allow a message
+    /// to be taken or until the time specified has been reached. Returns `false` if
+    /// the time was reached.
+    pub fn waitforspace(&self, until: Option<Timespec>) -> bool {
+        self.i.spacewaiters.fetch_add(1, Ordering::SeqCst);
+
+        let mut lock = self.i.spacemutex.lock().unwrap();
+        let mut result = true;
+
+        while self.i.checklimits() != Delivery::Delivered {
+            match until {
+                None => {
+                    lock = self.i.space.wait(lock).unwrap();
+                },
+                Some(until) => {
+                    let now = get_time();
+                    if now >= until {
+                        result = false;
+                        break;
+                    }
+                    lock = self.i.space.wait_timeout(lock, until - now).unwrap().0;
+                }
+            }
+        }
+
+        drop(lock);
+        self.i.spacewaiters.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+
+    /// _(internal)_ Register the token of a selector so it is signaled
+    /// each time this endpoint is given a message.
+    pub fn addselecttoken(&self, token: Arc<SelectToken>) {
+        self.i.selectors.lock().unwrap().push(token);
+    }
+
+    /// _(internal)_ Unregister the token of a selector.
+    pub fn removeselecttoken(&self, token: &Arc<SelectToken>) {
+        let ptr = &**token as *const SelectToken;
+        self.i.selectors.lock().unwrap().retain(|t| &**t as *const SelectToken != ptr);
     }
 
     /// Return the number of sleeping threads. 
@@ -425,6 +529,22 @@ impl Endpoint {
         self.i.slpcnt.load(Ordering::Relaxed)
     }
 
+    /// Return a snapshot of the statistics for this endpoint. The values are read
+    /// individually, therefore, they may not be exactly consistent with each other
+    /// if messages are moving through the endpoint at the same time.
+    pub fn stats(&self) -> EndpointStats {
+        EndpointStats {
+            msgsin:             self.i.msgsin.load(Ordering::Relaxed),
+            msgsout:            self.i.msgsout.load(Ordering::Relaxed),
+            pending:            self.i.messages.len(),
+            peakpending:        self.i.peakpending.load(Ordering::Relaxed),
+            bytesqueued:        self.i.memoryused.load(Ordering::SeqCst),
+            droppedpending:     self.i.droppedpending.load(Ordering::Relaxed),
+            droppedmemory:      self.i.droppedmemory.load(Ordering::Relaxed),
+            sleepers:           self.i.slpcnt.load(Ordering::Relaxed),
+        }
+    }
+
     /// Return true if the endpoint has messages that recv will not fail on getting. Beware
     /// that is another threads call recv before you do that it may fail.
     pub fn hasmessages(&self) -> bool {
@@ -453,6 +573,11 @@ impl Endpoint {
         self.i.limitmemory.store(limit, Ordering::Relaxed);
     }
 
+    /// Get the net the endpoint is on.
+    pub fn getnet(&self) -> Net {
+        self.i.net.clone()
+    }
+
     /// Get the system/net identifier.
     pub fn getsid(&self) -> ID {
         self.i.address.lock().unwrap().sid
@@ -463,24 +588,63 @@ impl Endpoint {
         self.i.address.lock().unwrap().eid
     }
 
-    /// Get the group identifier (like the endpoint identifier).
+    /// Get the group identifier (like the endpoint identifier). If the endpoint
+    /// is a member of more than one group this is the first group joined, and if
+    /// it is a member of no group `UNUSED_ID` is returned.
     pub fn getgid(&self) -> ID {
-        self.i.address.lock().unwrap().gid
+        let addr = self.i.address.lock().unwrap();
+        if addr.gids.len() > 0 { addr.gids[0] } else { UNUSED_ID }
     }
 
-    /// Set the group identifier.
+    /// Set the group identifier. This replaces any group membership with
+    /// membership in only the specified group.
     pub fn setgid(&self, id: ID) {
-        self.i.address.lock().unwrap().gid = id;
+        let mut addr = self.i.address.lock().unwrap();
+        addr.gids.clear();
+        addr.gids.push(id);
+        drop(addr);
+        self.i.net.reindex(self);
+    }
+
+    /// Join a group. The endpoint will receive messages with a `dstgid` of
+    /// this group in addition to any other groups it is already a member of.
+    pub fn joingroup(&self, id: ID) {
+        let mut addr = self.i.address.lock().unwrap();
+        if !addr.gids.contains(&id) {
+            addr.gids.push(id);
+        }
+        drop(addr);
+        self.i.net.reindex(self);
+    }
+
+    /// Leave a group. Does nothing if the endpoint is not a member.
+    pub fn leavegroup(&self, id: ID) {
+        let mut addr = self.i.address.lock().unwrap();
+        addr.gids.retain(|gid| *gid != id);
+        drop(addr);
+        self.i.net.reindex(self);
+    }
+
+    /// Return `true` if the endpoint is a member of the group.
+    pub fn ingroup(&self, id: ID) -> bool {
+        self.i.address.lock().unwrap().gids.contains(&id)
+    }
+
+    /// Return all groups that the endpoint is a member of.
+    pub fn getgroups(&self) -> Vec<ID> {
+        self.i.address.lock().unwrap().gids.clone()
     }
 
     /// Set the system/net identifier.
     pub fn setsid(&self, id: ID) {
         self.i.address.lock().unwrap().sid = id;
+        self.i.net.reindex(self);
     }
 
     /// Set the endpoint identifier.
     pub fn seteid(&self, id: ID) {
         self.i.address.lock().unwrap().eid = id;
+        self.i.net.reindex(self);
     }
 
     /// Send a message, but leave from address fields alone.
@@ -494,11 +658,43 @@ impl Endpoint {
     ///
     /// _If sync type use `sendsync` or `sendsyncx`._
     pub fn send(&self, msg: Message) -> usize {
+        self.i.net.send(self.stampfrom(msg))
+    }
+
+    /// Send a message like `send` but return the outcome for each endpoint the message
+    /// was offered to. This lets you tell the difference between nobody listening and
+    /// everyone being full.
+    pub fn sendreport(&self, msg: Message) -> SendReport {
+        self.i.net.sendreport(self.stampfrom(msg))
+    }
+
+    /// Send a message like `send` but if any endpoint rejects it because of a pending or
+    /// memory limit then block until it has space or until the duration expires. If it
+    /// expires the report still tells who took the message, and every endpoint that
+    /// had no space is counted by `SendReport::rejected`.
+    ///
+    /// _A sync message only needs to be taken by one endpoint, therefore, the blocking
+    /// ends once any endpoint has taken it._
+    pub fn sendorblock(&self, msg: Message, duration: Duration) -> IoResult<SendReport> {
+        self.i.net.sendorblock(self.stampfrom(msg), duration)
+    }
+
+    /// Send a message like `sendorblock` but block forever waiting for space.
+    pub fn sendorblockforever(&self, msg: Message) -> IoResult<SendReport> {
+        self.i.net.sendorblockforever(self.stampfrom(msg))
+    }
+
+    /// Fill the from address fields of the message with our address.
+    fn stampfrom(&self, mut msg: Message) -> Message {
         let lock = self.i.address.lock().unwrap();
-        let sid = lock.sid;
-        let eid = lock.eid;
+        msg.srcsid = lock.sid;
+        msg.srceid = lock.eid;
         drop(lock);
-        self.i.net.sendas(msg, sid, eid)
+        // It is a new message from us even if it was received, so a broadcast
+        // has to be given a new ID and it has not crossed any bridges yet.
+        msg.msgid = 0;
+        msg.hops = 0;
+        msg
     }
 
     /// Easily sends a sync message by wrapping it into a
@@ -529,6 +725,20 @@ impl Endpoint {
         self.send(msg)
     }
 
+    /// Easily sends a serial message by wrapping it into a
+    /// message. Using this function is the same as doing:
+    /// 
+    /// `endpoint.send(Message::new_serial(tag, t))`
+    ///
+    /// Unlike the sync and clone helpers this sends to every
+    /// net including those across bridges.
+    pub fn sendserialtype<T: Encodable>(&self, tag: &str, t: T) -> usize {
+        let mut msg = Message::new_serial(tag, t);
+        msg.dstsid = 0; // every net
+        msg.dsteid = 0; // everyone
+        self.send(msg)
+    }
+
     /// Return a message or block forever until one is received.
     pub fn recvorblockforever(&self) -> IoResult<Message> {
         let mut lock = self.i.waitmutex.lock().unwrap();
@@ -539,12 +749,15 @@ impl Endpoint {
             return r;
         }
 
+        self.i.slpcnt.fetch_add(1, Ordering::Relaxed);
+
         loop {
             lock = self.i.wait.wait(lock).unwrap();
 
             let r = self.i.recv();
 
             if r.is_ok() {
+                self.i.slpcnt.fetch_sub(1, Ordering::Relaxed);
                 return r;
             }
         }
@@ -564,7 +777,7 @@ impl Endpoint {
     ///     if result.is_err() { 
     ///         println!("no message");
     ///     } else {
-    ///         println!("got message [{}]", result.ok().typeunwrap::<usize>());
+    ///         println!("got message [{}]", result.unwrap().typeunwrap::<usize>());
     ///     }
     /// 
     /// See `Message` for API dealing with messages.
@@ -577,6 +790,8 @@ impl Endpoint {
             return result;
         }
 
+        self.i.slpcnt.fetch_add(1, Ordering::Relaxed);
+
         loop {
             let result = self.i.wait.wait_timeout(lock, duration).unwrap();
             lock = result.0;
@@ -584,12 +799,14 @@ impl Endpoint {
 
             let result = self.i.recv();
             if result.is_ok() {
+                self.i.slpcnt.fetch_sub(1, Ordering::Relaxed);
                 return result;
             }
 
             // The wait timed out, therefore, let us exit.
             if !expired {
-                return IoResult::Err(IoError { code: IoErrorCode::TimedOut });             
+                self.i.slpcnt.fetch_sub(1, Ordering::Relaxed);
+                return Err(WaterError::TimedOut);             
             }            
         }
     }
@@ -606,7 +823,7 @@ impl Endpoint {
     ///     if result.is_err() { 
     ///         println!("no message");
     ///     } else {
-    ///         println!("got message [{}]", result.ok().typeunwrap::<usize>());
+    ///         println!("got message [{}]", result.unwrap().typeunwrap::<usize>());
     ///     }
     ///
     pub fn recv(&self) -> IoResult<Message> {
@@ -616,7 +833,9 @@ impl Endpoint {
 
 /// Will not return until it has a valid message or a critical errored occures.
 ///
-/// _This function can wait on multiple endpoints._
+/// _This function can wait on multiple endpoints. It builds a `Selector` for each call,
+/// therefore, if you wait on the same endpoints repeatedly consider using a `Selector`
+/// directly which will also service the endpoints fairly._
 ///
 ///     #![allow(unstable)]
 ///     // Needed by the commented out line further below.
@@ -634,15 +853,7 @@ impl Endpoint {
 ///     //water::recvorblockforever(&Vec::<Endpoint>::new());
 ///
 pub fn recvorblockforever(list: &Vec<Endpoint>) -> IoResult<Message> {
-    loop {
-        let result = recv(list);
-
-        if result.is_ok() {
-            return result;
-        }
-
-        Thread::yield_now();
-    }
+    Selector::from_list(list).recvorblockforever().map(|(_, msg)| msg)
 }
 
 /// Return immediantly if not message can be received.
@@ -670,12 +881,14 @@ pub fn recv(list: &Vec<Endpoint>) -> IoResult<Message> {
         }
     }
 
-    IoResult::Err(IoError { code: IoErrorCode::NoMessages })
+    Err(WaterError::NoMessages)
 }
 
 /// Wait the specified duration for a message or return if a message received.
 ///
-/// _This function can wait on multiple endpoints._
+/// _This function can wait on multiple endpoints. It builds a `Selector` for each call,
+/// therefore, if you wait on the same endpoints repeatedly consider using a `Selector`
+/// directly which will also service the endpoints fairly._
 ///
 ///     #![allow(unstable)]
 ///     use water;
@@ -692,19 +905,5 @@ pub fn recv(list: &Vec<Endpoint>) -> IoResult<Message> {
 ///     water::recvorblock(&Vec::<Endpoint>::new(), Duration::seconds(0));
 ///
 pub fn recvorblock(list: &Vec<Endpoint>, duration: Duration) -> IoResult<Message> {
-    let when: Timespec = get_time() + duration;
-
-    loop {
-        let result = recv(list);
-
-        if result.is_ok() {
-            return result;
-        }
-
-        if get_time() > when {
-            return IoResult::Err(IoError { code: IoErrorCode::TimedOut });
-        }
-
-        Thread::yield_now();
-    }
+    Selector::from_list(list).recvorblock(duration).map(|(_, msg)| msg)
 }
\ No newline at end of file
@@ -0,0 +1,65 @@
+//! Here we implement the error type returned by the operations of the water
+//! library. It implements the standard error traits so that it can be used
+//! with `try!` and converted into a `Box<Error>` with the rest of your errors.
+
+use std::error::Error;
+use std::error::FromError;
+use std::fmt;
+use std::io;
+
+/// Represents a successful return, or a water error. Since this is a standard
+/// `Result` all the normal methods and `try!` can be used on it.
+pub type IoResult<T> = Result<T, WaterError>;
+
+/// The name used for the error before `WaterError` existed.
+pub type IoError = WaterError;
+
+/// This represents the exact failure of the operation. Some errors are normal
+/// and expected such as `TimedOut` and `NoMessages`.
+#[derive(Clone, PartialEq, Debug)]
+pub enum WaterError {
+    /// If the operation reaches the specified time to fail.
+    TimedOut,
+    /// There were no messages for the operation to succeed with.
+    NoMessages,
+    /// The net or the endpoints needed to complete the operation are gone.
+    NetDisconnected,
+    /// The message did not contain the type that was expected.
+    WrongType,
+    /// A bridge was unable to perform the I/O it needed.
+    BridgeFailure(String),
+    /// A bridge received something from the remote side that does not follow the protocol.
+    Protocol(String),
+    /// The remote side of a bridge could not prove that it knows the shared key.
+    AuthFailed,
+}
+
+impl Error for WaterError {
+    fn description(&self) -> &str {
+        match *self {
+            WaterError::TimedOut => "timed out",
+            WaterError::NoMessages => "no messages",
+            WaterError::NetDisconnected => "net disconnected",
+            WaterError::WrongType => "wrong payload type",
+            WaterError::BridgeFailure(_) => "bridge failure",
+            WaterError::Protocol(_) => "protocol error",
+            WaterError::AuthFailed => "authentication failed",
+        }
+    }
+}
+
+impl fmt::Display for WaterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WaterError::BridgeFailure(ref detail) => write!(f, "bridge failure: {}", detail),
+            WaterError::Protocol(ref detail) => write!(f, "protocol error: {}", detail),
+            _ => write!(f, "{}", self.description()),
+        }
+    }
+}
+
+impl FromError<io::IoError> for WaterError {
+    fn from_error(err: io::IoError) -> WaterError {
+        WaterError::BridgeFailure(format!("{}", err))
+    }
+}
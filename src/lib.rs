@@ -40,25 +40,59 @@
 
 extern crate test;
 extern crate time;
+extern crate libc;
+extern crate flate;
+extern crate "rustc-serialize" as rustc_serialize;
+extern crate openssl;
 
 pub use net::Net;
 pub use endpoint::Endpoint;
+pub use endpoint::Delivery;
+pub use net::SendReport;
+pub use net::NetStats;
+pub use endpoint::EndpointStats;
 pub use rawmessage::RawMessage;
 pub use rawmessage::NoPointers;
 pub use message::MessagePayload;
 pub use message::Message;
-pub use endpoint::IoResult;
-pub use endpoint::IoError;
-pub use endpoint::IoErrorCode;
+pub use error::IoResult;
+pub use error::IoError;
+pub use error::WaterError;
 pub use syncmessage::SyncMessage;
 pub use clonemessage::CloneMessage;
+pub use serialmessage::SerialMessage;
 pub use tcp::TcpBridgeConnector;
 pub use tcp::TcpBridgeListener;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub use unix::UnixBridgeConnector;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub use unix::UnixBridgeListener;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub use shm::ShmBridgeConnector;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub use shm::ShmBridgeListener;
+pub use tls::TlsBridgeConnector;
+pub use tls::TlsBridgeListener;
+pub use tls::TlsConfig;
+pub use bridge::BridgeConnector;
+pub use bridge::BridgeListener;
+pub use bridge::ReconnectPolicy;
+pub use bridge::ConnectorState;
+pub use bridge::HeartbeatPolicy;
+pub use bridge::SourcePolicy;
+pub use bridge::CompressionStats;
+pub use bridge::BridgeEvent;
+pub use bridge::BridgeEventKind;
+pub use bridge::BRIDGE_EVENT_GID;
 pub use net::ID;
+pub use net::MAX_HOPS;
 
 pub use endpoint::recvorblock;
 pub use endpoint::recvorblockforever;
 pub use endpoint::recv;
+pub use selector::Selector;
+pub use rpc::RpcClient;
+pub use rpc::RpcServer;
 
 /// Gets the current system time.
 pub use time::get_time;
@@ -75,16 +109,38 @@ pub mod timespec;
 pub mod syncmessage;
 /// The sender/receiver combination.
 pub mod endpoint;
+/// The errors that operations can return.
+pub mod error;
 /// The network.
 pub mod net;
+/// Waiting on multiple endpoints at the same time.
+pub mod selector;
+/// Request and reply on top of endpoints.
+pub mod rpc;
 /// A raw message is a byte array. A sub-type of Message.
 pub mod rawmessage;
+/// The generic bridge that transports are built on.
+pub mod bridge;
 /// TCP network bridge.
 pub mod tcp;
+/// Unix domain socket network bridge. _Only on Linux x86-64._
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub mod unix;
+/// Shared memory network bridge. _Only on Linux x86-64._
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub mod shm;
+/// TLS network bridge.
+pub mod tls;
+// The system calls the bridges need. The numbers and layouts in it are those
+// of Linux x86-64, so it is not built anywhere else.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod sys;
 // A message can be sent or received.
 pub mod message;
 /// A clone message is a non-unique type instance. A sub-type of Message.
 pub mod clonemessage;
+/// A serial message is an encoded type instance that can cross bridges. A sub-type of Message.
+pub mod serialmessage;
 /// Provides functionality of a native Rust channel.
 //pub mod compat;
 /// Provides a high throughput MPMC queue implementation.
@@ -4,6 +4,12 @@ use std::any::TypeId;
 use rawmessage::RawMessage;
 use syncmessage::SyncMessage;
 use clonemessage::CloneMessage;
+use serialmessage::SerialMessage;
+use error::IoResult;
+use error::WaterError;
+
+use rustc_serialize::Encodable;
+use rustc_serialize::Decodable;
 
 pub fn workaround_to_static_bug() {
     panic!("sync message was not correct type");
@@ -26,13 +32,20 @@ pub fn workaround_to_static_bug() {
 ///
 /// If the type is clone or sync you should use `is_type` and `unwraptype`. The
 /// `unwraptype` will panic if the expected type is not the type contained in the
-/// message. This panic is the only sane way to handle this situation. I may implement
-/// a Result enum later if this is desired. If you use `is_type` you can check for
-/// the type of the message and prevent any panic from `unwraptype`.
+/// message. If you use `is_type` you can check for the type of the message and
+/// prevent any panic from `unwraptype`, or you can use `trytypeunwrap` which will
+/// return `WaterError::WrongType` instead of a panic. A serial message is checked
+/// with `is_serialtag` and unwrapped with `serialunwrap` using the tag it was
+/// created with.
+///
+/// The `dstgid` field can be used to address a group of endpoints. When it is
+/// not zero only endpoints that are a member of that group (see `Endpoint::joingroup`)
+/// will receive the message. It works together with `dstsid` so you can address
+/// a group on a specific net or on all nets.
 /// ```
 ///     let result = endpoint.recvorblock( Timespec { sec: 5i64, nsec: 0i32 } );
 ///     if result.is_err() { try_again_or_quit; }
-///     let message = result.ok();
+///     let message = result.unwrap();
 ///     if !message.is_sync() && !message.is_clone() { maybe_check_for_raw; }
 ///     if message.is_type::<Apple>() {
 ///         let apple: Apple = message.unwraptype();
@@ -46,7 +59,11 @@ pub struct Message {
     pub srceid:         u64,             // source endpoint id
     pub dstsid:         u64,             // destination server id
     pub dsteid:         u64,             // destination endpoint id
+    pub dstgid:         u64,             // destination group id
+    pub corid:          u64,             // correlation id (request/reply)
     pub canloop:        bool,            // can loop back into sender?
+    pub hops:           u8,              // bridges crossed so far
+    pub msgid:          u64,             // broadcast id (unique per source net)
     pub payload:        MessagePayload,  // actual payload
 }
 
@@ -55,6 +72,7 @@ pub enum MessagePayload {
     Raw(RawMessage),
     Sync(SyncMessage),
     Clone(CloneMessage),
+    Serial(SerialMessage),
 }
 
 unsafe impl Send for Message {}
@@ -69,19 +87,33 @@ impl Clone for Message {
             MessagePayload::Raw(ref msg) => {
                 Message {
                     canloop: self.canloop,
+                    hops: self.hops,
+                    msgid: self.msgid,
                     srcsid: self.srcsid, srceid: self.srceid,
-                    dstsid: self.dstsid, dsteid: self.dsteid,
+                    dstsid: self.dstsid, dsteid: self.dsteid, dstgid: self.dstgid, corid: self.corid,
                     payload: MessagePayload::Raw((*msg).clone()),
                 }
             },
             MessagePayload::Clone(ref msg) => {
                 Message {
                     canloop: self.canloop,
+                    hops: self.hops,
+                    msgid: self.msgid,
                     srcsid: self.srcsid, srceid: self.srceid,
-                    dstsid: self.dstsid, dsteid: self.dsteid,
+                    dstsid: self.dstsid, dsteid: self.dsteid, dstgid: self.dstgid, corid: self.corid,
                     payload: MessagePayload::Clone((*msg).clone()),
                 }                
             }
+            MessagePayload::Serial(ref msg) => {
+                Message {
+                    canloop: self.canloop,
+                    hops: self.hops,
+                    msgid: self.msgid,
+                    srcsid: self.srcsid, srceid: self.srceid,
+                    dstsid: self.dstsid, dsteid: self.dsteid, dstgid: self.dstgid, corid: self.corid,
+                    payload: MessagePayload::Serial((*msg).clone()),
+                }
+            }
             MessagePayload::Sync(ref msg) => {
                 panic!("Tried to clone a SyncMessage which is unique!");
             }
@@ -97,6 +129,7 @@ impl Message {
             MessagePayload::Raw(ref msg) => msg.cap(),
             MessagePayload::Sync(ref msg) => msg.payload.cap(),
             MessagePayload::Clone(ref msg) => msg.payload.cap(),
+            MessagePayload::Serial(ref msg) => msg.payload.cap(),
         }
     }
 
@@ -117,8 +150,10 @@ impl Message {
             MessagePayload::Sync(ref msg) => {
                 Message {
                     canloop: self.canloop,
+                    hops: self.hops,
+                    msgid: self.msgid,
                     srcsid: self.srcsid, srceid: self.srceid,
-                    dstsid: self.dstsid, dsteid: self.dsteid,
+                    dstsid: self.dstsid, dsteid: self.dsteid, dstgid: self.dstgid, corid: self.corid,
                     payload: MessagePayload::Sync((*msg).internal_clone(0x879)),
                 }
             },
@@ -133,7 +168,9 @@ impl Message {
             MessagePayload::Raw(ref msg) => {
                 Message {
                     canloop: self.canloop,
-                    dstsid: self.dstsid, dsteid: self.dsteid,
+                    hops: self.hops,
+                    msgid: self.msgid,
+                    dstsid: self.dstsid, dsteid: self.dsteid, dstgid: self.dstgid, corid: self.corid,
                     srcsid: self.srcsid, srceid: self.srceid,
                     payload: MessagePayload::Raw(msg.dup())
                 }
@@ -154,7 +191,9 @@ impl Message {
             MessagePayload::Raw(ref msg) => {
                 Message {
                     canloop: self.canloop,
-                    dstsid: self.dstsid, dsteid: self.dsteid,
+                    hops: self.hops,
+                    msgid: self.msgid,
+                    dstsid: self.dstsid, dsteid: self.dsteid, dstgid: self.dstgid, corid: self.corid,
                     srcsid: self.srcsid, srceid: self.srceid,
                     payload: MessagePayload::Raw(msg.dup())
                 }
@@ -222,20 +261,35 @@ impl Message {
         }
     }
 
-    /// For a sync or clone message type only this will extract the
+    /// For a sync, clone, or serial message type only this will extract the
     /// instance of the type contained. You must be explicit about
     /// the type contained. You can use `is_type::<T>` to check
     /// the type.
+    ///
+    /// A serial message has to be decoded with `serialunwrap` instead.
     pub fn typeunwrap<T: 'static>(self) -> T {
         match self.payload {
             MessagePayload::Clone(msg) => msg.get_payload::<T>(),
             MessagePayload::Sync(msg) => msg.get_payload::<T>(),
+            MessagePayload::Serial(_) => {
+                panic!("message was type serial! [consider using serialunwrap]")
+            },
             _ => {
                 panic!("message was not clone or sync type! [consider checking type]")
             }
         }
     }
 
+    /// Works like `typeunwrap` except if the message does not contain the type
+    /// then `WaterError::WrongType` is returned instead of a panic.
+    pub fn trytypeunwrap<T: Send + 'static>(self) -> IoResult<T> {
+        if !self.is_type::<T>() {
+            return Err(WaterError::WrongType);
+        }
+
+        Ok(self.typeunwrap::<T>())
+    }
+
     /// Get a reference to the clone message API for this message without
     /// consuming this message. This can be useful if you still need to
     /// keep the message around maybe for resending.
@@ -310,6 +364,7 @@ impl Message {
             MessagePayload::Sync(_) => false,
             MessagePayload::Raw(_) => false,
             MessagePayload::Clone(_) => true,
+            MessagePayload::Serial(_) => false,
         }
     }
 
@@ -319,6 +374,7 @@ impl Message {
             MessagePayload::Sync(_) => false,
             MessagePayload::Raw(_) => true,
             MessagePayload::Clone(_) => false,
+            MessagePayload::Serial(_) => false,
         }
     }
 
@@ -328,6 +384,63 @@ impl Message {
             MessagePayload::Sync(_) => true,
             MessagePayload::Raw(_) => false,
             MessagePayload::Clone(_) => false,
+            MessagePayload::Serial(_) => false,
+        }
+    }
+
+    /// Check if this is a serial message.
+    pub fn is_serial(&self) -> bool {
+        match self.payload {
+            MessagePayload::Serial(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Get a reference to the serial message API for this message without
+    /// consuming this message.
+    pub fn get_serialref(&self) -> &SerialMessage {
+        match self.payload {
+            MessagePayload::Serial(ref msg) => {
+                msg
+            },
+            _ => {
+                panic!("message was not type serial! [consider checking type]")
+            }
+        }
+    }
+
+    /// Get the serial message by consuming this message.
+    pub fn get_serial(self) -> SerialMessage {
+        match self.payload {
+            MessagePayload::Serial(msg) => {
+                msg
+            },
+            _ => {
+                panic!("message was not type serial! [consider checking type]")
+            }
+        }
+    }
+
+    /// This will return `true` if this is a serial message created with the tag.
+    pub fn is_serialtag(&self, tag: &str) -> bool {
+        self.is_serial() && self.get_serialref().is_tag(tag)
+    }
+
+    /// Decode the type of a serial message created with the tag. It works the
+    /// same on the local net and on a remote net since it is decoded either way.
+    /// Will panic if it is not a serial message with the tag or if it can not be
+    /// decoded as the type.
+    pub fn serialunwrap<T: Decodable>(self, tag: &str) -> T {
+        self.get_serial().get_payload::<T>(tag)
+    }
+
+    /// Works like `serialunwrap` except `WaterError::WrongType` is returned if it
+    /// is not a serial message with the tag, and `WaterError::Protocol` if it can
+    /// not be decoded as the type.
+    pub fn tryserialunwrap<T: Decodable>(self, tag: &str) -> IoResult<T> {
+        match self.payload {
+            MessagePayload::Serial(msg) => msg.try_payload::<T>(tag),
+            _ => Err(WaterError::WrongType),
         }
     }
 
@@ -335,8 +448,10 @@ impl Message {
     pub fn new_fromraw(rmsg: RawMessage) -> Message {
         Message {
             canloop: false,
+            hops: 0,
+            msgid: 0,
             srcsid: 0, srceid: 0,
-            dstsid: 0, dsteid: 0,
+            dstsid: 0, dsteid: 0, dstgid: 0, corid: 0,
             payload: MessagePayload::Raw(rmsg),
         }
     }
@@ -345,8 +460,10 @@ impl Message {
     pub fn new_raw(cap: usize) -> Message {
         Message {
             canloop: false,
+            hops: 0,
+            msgid: 0,
             srcsid: 0, srceid: 0,
-            dstsid: 0, dsteid: 0,
+            dstsid: 0, dsteid: 0, dstgid: 0, corid: 0,
             payload: MessagePayload::Raw(RawMessage::new(cap)),
         }
     }
@@ -358,7 +475,9 @@ impl Message {
 
         Message {
             canloop: false,
-            srcsid: 0, srceid: 0, dstsid: 0, dsteid: 0,
+            hops: 0,
+            msgid: 0,
+            srcsid: 0, srceid: 0, dstsid: 0, dsteid: 0, dstgid: 0, corid: 0,
             payload: payload,
         }
     }
@@ -370,7 +489,25 @@ impl Message {
 
         Message {
             canloop: false,
-            srcsid: 0, srceid: 0, dstsid: 0, dsteid: 0,
+            hops: 0,
+            msgid: 0,
+            srcsid: 0, srceid: 0, dstsid: 0, dsteid: 0, dstgid: 0, corid: 0,
+            payload: payload,
+        }
+    }
+
+    /// Helper function for creating a serial message with a type instance. The
+    /// type is encoded right away, and unlike sync and clone messages the message
+    /// can cross bridges to other processes and machines. It is decoded again by
+    /// `serialunwrap` with the same tag on whichever side receives it.
+    pub fn new_serial<T: Encodable>(tag: &str, t: T) -> Message {
+        let payload = MessagePayload::Serial(SerialMessage::new(tag, &t));
+
+        Message {
+            canloop: false,
+            hops: 0,
+            msgid: 0,
+            srcsid: 0, srceid: 0, dstsid: 0, dsteid: 0, dstgid: 0, corid: 0,
             payload: payload,
         }
     }
@@ -23,18 +23,36 @@ use std::intrinsics::transmute;
 use std::io::timer::sleep;
 use std::time::duration::Duration;
 use std::thread::Thread;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::RingBuf;
+use std::collections::hash_map::Entry;
+use std::rand::random;
 
 use get_time;
 use Timespec;
 
 use rawmessage::RawMessage;
 use endpoint::Endpoint;
+use endpoint::Delivery;
+use endpoint::EndpointStats;
+use error::IoResult;
 use message::Message;
 use message::MessagePayload;
 
-use tcp;
-use tcp::TcpBridgeListener;
-use tcp::TcpBridgeConnector;
+use bridge::BridgeListener;
+use bridge::BridgeConnector;
+use bridge::ReconnectPolicy;
+use bridge::Transport;
+use bridge::RouteUpdateMessage;
+use bridge::eventloop::EventLoop;
+use tcp::TcpTransport;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+use unix::UnixTransport;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+use shm::ShmTransport;
+use tls::TlsTransport;
+use tls::TlsConfig;
 
 /// We use this to be able to easily, but maybe dangerously
 /// change the actual type that ID represents. Hopefully,
@@ -46,9 +64,200 @@ pub type ID = u64;
 /// messages across the network even though it may work.
 pub const UNUSED_ID: ID = !0u64;
 
+/// A net this many hops away is unreachable, and a message that has crossed
+/// this many bridges is dropped. This keeps routing loops from lasting.
+pub const MAX_HOPS: u8 = 16;
+
+/// The number of broadcasts a net remembers having delivered. A broadcast that
+/// comes back around a loop of bridges after it was forgotten is delivered
+/// again, but by then `MAX_HOPS` has likely dropped it.
+pub const SEEN_SIZE: usize = 4096;
+
+/// The addresses an endpoint was indexed under. We keep these so
+/// that we can remove it from the indexes even after the endpoint
+/// has changed its addresses.
+struct Indexed {
+    ep:             Endpoint,
+    sid:            ID,
+    eid:            ID,
+    gids:           Vec<ID>,
+}
+
 struct Internal {
-    endpoints:      Vec<Endpoint>,
-    hueid:          ID,              // highest unused endpoint id
+    endpoints:      HashMap<usize, Indexed>,        // all endpoints by `Endpoint::id`
+    byaddr:         HashMap<(ID, ID), Vec<usize>>,  // all endpoints by (sid, eid)
+    bysid:          HashMap<ID, Vec<usize>>,        // all endpoints by sid
+    bygid:          HashMap<ID, Vec<usize>>,        // local endpoints by group
+    bridges:        Vec<usize>,                     // endpoints not on the local net
+    neighbors:      HashMap<ID, usize>,             // links to each directly bridged net
+    adverts:        HashMap<ID, HashMap<ID, u8>>,   // routes advertised by each neighbor
+    seen:           HashSet<(ID, u64)>,             // broadcasts delivered by (srcsid, msgid)
+    seenorder:      RingBuf<(ID, u64)>,             // the same oldest first to forget them
+    eventloop:      Option<Arc<EventLoop>>,         // runs the stream links once started
+    hueid:          ID,                             // highest unused endpoint id
+}
+
+fn indexinsert<K: Eq + ::std::hash::Hash>(map: &mut HashMap<K, Vec<usize>>, key: K, id: usize) {
+    match map.entry(key) {
+        Entry::Occupied(mut e) => { e.get_mut().push(id); },
+        Entry::Vacant(e) => { e.insert(vec![id]); },
+    }
+}
+
+fn indexremove<K: Eq + ::std::hash::Hash>(map: &mut HashMap<K, Vec<usize>>, key: K, id: usize) {
+    match map.entry(key) {
+        Entry::Occupied(mut e) => {
+            e.get_mut().retain(|x| *x != id);
+            if e.get().len() == 0 {
+                e.remove();
+            }
+        },
+        Entry::Vacant(_) => { },
+    }
+}
+
+impl Internal {
+    /// Place the endpoint into the indexes using its current addresses.
+    fn index(&mut self, lsid: ID, ep: Endpoint) {
+        let id = ep.id();
+        let sid = ep.getsid();
+        let eid = ep.geteid();
+        let gids = ep.getgroups();
+
+        indexinsert(&mut self.byaddr, (sid, eid), id);
+        indexinsert(&mut self.bysid, sid, id);
+
+        if sid == lsid {
+            for gid in gids.iter() {
+                indexinsert(&mut self.bygid, *gid, id);
+            }
+        } else {
+            self.bridges.push(id);
+        }
+
+        self.endpoints.insert(id, Indexed { ep: ep, sid: sid, eid: eid, gids: gids });
+    }
+
+    /// Remove the endpoint from the indexes and return it.
+    fn unindex(&mut self, lsid: ID, id: usize) -> Option<Endpoint> {
+        let entry = match self.endpoints.remove(&id) {
+            Some(entry) => entry,
+            None => return None,
+        };
+
+        indexremove(&mut self.byaddr, (entry.sid, entry.eid), id);
+        indexremove(&mut self.bysid, entry.sid, id);
+
+        if entry.sid == lsid {
+            for gid in entry.gids.iter() {
+                indexremove(&mut self.bygid, *gid, id);
+            }
+        } else {
+            self.bridges.retain(|x| *x != id);
+        }
+
+        Some(entry.ep)
+    }
+
+    /// Find the neighbor to reach the net through and the hops to it.
+    fn nexthop(&self, sid: ID) -> Option<(ID, u8)> {
+        if self.neighbors.contains_key(&sid) {
+            return Some((sid, 1));
+        }
+
+        let mut best: Option<(ID, u8)> = None;
+        for (neighbor, routes) in self.adverts.iter() {
+            match routes.get(&sid) {
+                Some(&hops) if hops + 1 < MAX_HOPS => {
+                    // Ties go to the lowest net ID so every net picks the same.
+                    best = match best {
+                        Some((bn, bh)) if bh < hops + 1 || (bh == hops + 1 && bn < *neighbor) => best,
+                        _ => Some((*neighbor, hops + 1)),
+                    };
+                },
+                _ => { },
+            }
+        }
+        best
+    }
+
+    /// Clone the endpoints for the IDs so they can be used with out the lock.
+    fn collect(&self, ids: Option<&Vec<usize>>, out: &mut Vec<Endpoint>) {
+        match ids {
+            Some(ids) => {
+                for id in ids.iter() {
+                    match self.endpoints.get(id) {
+                        Some(entry) => out.push(entry.ep.clone()),
+                        None => { },
+                    }
+                }
+            },
+            None => { },
+        }
+    }
+
+    /// Clone only the first endpoint for the IDs that is still there.
+    fn collectone(&self, ids: Option<&Vec<usize>>, out: &mut Vec<Endpoint>) {
+        match ids.and_then(|ids| ids.iter().filter_map(|id| self.endpoints.get(id)).next()) {
+            Some(entry) => out.push(entry.ep.clone()),
+            None => { },
+        }
+    }
+}
+
+/// The outcome of a send for each endpoint the message was offered to.
+///
+/// If nothing was delivered and nothing was rejected because of a limit
+/// then nobody is listening for the message.
+#[derive(Clone, Debug)]
+pub struct SendReport {
+    /// The system ID, endpoint ID, and outcome for each endpoint.
+    pub outcomes:       Vec<(ID, ID, Delivery)>,
+}
+
+impl SendReport {
+    fn count(&self, delivery: Delivery) -> usize {
+        self.outcomes.iter().filter(|o| o.2 == delivery).count()
+    }
+
+    /// Return the number of endpoints that took the message.
+    pub fn delivered(&self) -> usize { self.count(Delivery::Delivered) }
+    /// Return the number of endpoints that rejected the message because of their pending limit.
+    pub fn queuefull(&self) -> usize { self.count(Delivery::QueueFull) }
+    /// Return the number of endpoints that rejected the message because of their memory limit.
+    pub fn memorylimit(&self) -> usize { self.count(Delivery::MemoryLimit) }
+    /// Return the number of endpoints that were not addressed by the message.
+    pub fn filtered(&self) -> usize { self.count(Delivery::Filtered) }
+    /// Return the number of endpoints that rejected the message because of any limit.
+    pub fn rejected(&self) -> usize { self.queuefull() + self.memorylimit() }
+
+    /// Return `true` if there was nobody to deliver the message to.
+    pub fn nolisteners(&self) -> bool {
+        self.delivered() == 0 && self.rejected() == 0
+    }
+}
+
+/// The statistics of all endpoints on a net added together.
+#[derive(Copy, Clone, Debug)]
+pub struct NetStats {
+    /// The number of endpoints on the net.
+    pub endpoints:          usize,
+    /// The number of messages taken into the queues.
+    pub msgsin:             usize,
+    /// The number of messages received out of the queues.
+    pub msgsout:            usize,
+    /// The number of messages currently in the queues.
+    pub pending:            usize,
+    /// The highest peak pending count of any endpoint.
+    pub peakpending:        usize,
+    /// The number of bytes used by the messages currently in the queues.
+    pub bytesqueued:        usize,
+    /// The number of messages rejected because of the pending limit.
+    pub droppedpending:     usize,
+    /// The number of messages rejected because of the memory limit.
+    pub droppedmemory:      usize,
+    /// The number of threads sleeping while waiting for a message.
+    pub sleepers:           usize,
 }
 
 /// Forms a group of endpoints that can all communicate locally. All
@@ -105,7 +314,8 @@ impl Net {
             {
                 let mut i = net.i.lock().unwrap();
                 wokesomeone = false;
-                for ep in i.endpoints.iter_mut() {
+                for entry in i.endpoints.values_mut() {
+                    let ep = &mut entry.ep;
                     // The `ctime` check is for sleeping who want to timeout after
                     // a certain amount of time. The `hasmessages()` and `sleepercount()`
                     // clause is to help wake sleepers that did not get properly woken. This
@@ -133,6 +343,31 @@ impl Net {
         }
     }
 
+    /// Return the statistics of every endpoint on this net added together.
+    pub fn stats(&self) -> NetStats {
+        let i = self.i.lock().unwrap();
+        let mut stats = NetStats {
+            endpoints: i.endpoints.len(), msgsin: 0, msgsout: 0, pending: 0, peakpending: 0,
+            bytesqueued: 0, droppedpending: 0, droppedmemory: 0, sleepers: 0,
+        };
+
+        for entry in i.endpoints.values() {
+            let epstats: EndpointStats = entry.ep.stats();
+            stats.msgsin += epstats.msgsin;
+            stats.msgsout += epstats.msgsout;
+            stats.pending += epstats.pending;
+            stats.bytesqueued += epstats.bytesqueued;
+            stats.droppedpending += epstats.droppedpending;
+            stats.droppedmemory += epstats.droppedmemory;
+            stats.sleepers += epstats.sleepers;
+            if epstats.peakpending > stats.peakpending {
+                stats.peakpending = epstats.peakpending;
+            }
+        }
+
+        stats
+    }
+
     /// Return the number of endpoints on this net.
     pub fn getepcount(&self) -> usize {
         self.i.lock().unwrap().endpoints.len()
@@ -140,10 +375,10 @@ impl Net {
 
     /// Create new net with the specified ID.
     ///
-    /// You can use the same ID for multiple nets and they will
-    /// properly bridge except traffic will not be segmented 
-    /// between the two nets. This may be desired. You should
-    /// use an ID that is `100` or above. 
+    /// You can use the same ID for multiple nets as long as they
+    /// are never bridged together, since messages are routed by
+    /// net ID. A bridge to a net with the same ID is refused at
+    /// the handshake. You should use an ID that is `100` or above.
     ///
     ///     use water::Net;
     ///     let net = Net::new(100);
@@ -151,7 +386,16 @@ impl Net {
     pub fn new(sid: ID) -> Net {
         let net = Net {
             i:  Arc::new(Mutex::new(Internal {
-                endpoints:      Vec::new(),
+                endpoints:      HashMap::new(),
+                byaddr:         HashMap::new(),
+                bysid:          HashMap::new(),
+                bygid:          HashMap::new(),
+                bridges:        Vec::new(),
+                neighbors:      HashMap::new(),
+                adverts:        HashMap::new(),
+                seen:           HashSet::new(),
+                seenorder:      RingBuf::new(),
+                eventloop:      None,
                 hueid:          0x10000,
             })),
             sid:    sid,
@@ -174,8 +418,8 @@ impl Net {
     ///      let net = Net::new(100);
     ///      net.tcplisten(String::from_str("localhost:40100"))
     ///
-    pub fn tcplisten(&self, addr: String) -> TcpBridgeListener {
-        tcp::listener::TcpBridgeListener::new(self, addr)
+    pub fn tcplisten(&self, addr: String) -> BridgeListener {
+        BridgeListener::new(self, Box::new(TcpTransport::new(addr)))
     }
 
     // Tries to maintain a TCP connecton to the specified remote
@@ -186,10 +430,106 @@ impl Net {
     ///      let net = Net::new(100);
     ///      net.tcpconnect(String::from_str("localhost:40100"))
     ///    
-    pub fn tcpconnect(&self, addr: String) -> TcpBridgeConnector {
-        tcp::connector::TcpBridgeConnector::new(self, addr)
+    pub fn tcpconnect(&self, addr: String) -> BridgeConnector {
+        BridgeConnector::new(self, Box::new(TcpTransport::new(addr)))
     } 
 
+    /// Tries to maintain a TCP connection like `tcpconnect`, but when a
+    /// connection can not be made to an address the next one is tried, and
+    /// the policy sets how long to wait once all of them have failed.
+    ///
+    ///      use water::Net;
+    ///      use water::ReconnectPolicy;
+    ///      let net = Net::new(100);
+    ///      net.tcpconnectlist(vec![
+    ///          String::from_str("localhost:40100"),
+    ///          String::from_str("localhost:40101"),
+    ///      ], ReconnectPolicy::new());
+    ///
+    pub fn tcpconnectlist(&self, addrs: Vec<String>, policy: ReconnectPolicy) -> BridgeConnector {
+        let transports = addrs.into_iter().map(|addr| Box::new(TcpTransport::new(addr)) as Box<Transport>).collect();
+        BridgeConnector::new_failover(self, transports, policy)
+    }
+
+    /// Listens for and accepts Unix domain socket connections from remote
+    /// networks on the same machine. The address is the path of the socket
+    /// file, or if it starts with `@` a name in the abstract namespace. The
+    /// socket file is removed when the listener is terminated.
+    ///
+    ///      use water::Net;
+    ///      let net = Net::new(100);
+    ///      net.unixlisten(String::from_str("/tmp/water.sock"));
+    ///
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    pub fn unixlisten(&self, addr: String) -> BridgeListener {
+        BridgeListener::new(self, Box::new(UnixTransport::new(addr)))
+    }
+
+    /// Tries to maintain a Unix domain socket connection to the specified
+    /// remote network. See `unixlisten` for the address format.
+    ///
+    ///      use water::Net;
+    ///      let net = Net::new(100);
+    ///      net.unixconnect(String::from_str("@water"));
+    ///
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    pub fn unixconnect(&self, addr: String) -> BridgeConnector {
+        BridgeConnector::new(self, Box::new(UnixTransport::new(addr)))
+    }
+
+    /// Listens for a shared memory link from a remote network in another
+    /// process on the same machine. The address is the path of the file
+    /// holding the region, which should be under `/dev/shm`. Only one link
+    /// at a time is accepted and the file is removed when the listener is
+    /// terminated.
+    ///
+    ///      use water::Net;
+    ///      let net = Net::new(100);
+    ///      net.shmlisten(String::from_str("/dev/shm/water"));
+    ///
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    pub fn shmlisten(&self, addr: String) -> BridgeListener {
+        BridgeListener::new(self, Box::new(ShmTransport::new(addr)))
+    }
+
+    /// Tries to maintain a shared memory link to the specified remote
+    /// network. See `shmlisten` for the address format.
+    ///
+    ///      use water::Net;
+    ///      let net = Net::new(100);
+    ///      net.shmconnect(String::from_str("/dev/shm/water"));
+    ///
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    pub fn shmconnect(&self, addr: String) -> BridgeConnector {
+        BridgeConnector::new(self, Box::new(ShmTransport::new(addr)))
+    }
+
+    /// Listens for TLS connections from remote networks. The configuration
+    /// must have a certificate, and says what is accepted from the remote.
+    ///
+    ///      use water::Net;
+    ///      use water::TlsConfig;
+    ///      let net = Net::new(100);
+    ///      let config = TlsConfig::with_cert(Path::new("net.pem"), Path::new("net.key"));
+    ///      net.tlslisten(String::from_str("localhost:40100"), config);
+    ///
+    pub fn tlslisten(&self, addr: String, config: TlsConfig) -> BridgeListener {
+        BridgeListener::new(self, Box::new(TlsTransport::new(addr, config)))
+    }
+
+    /// Tries to maintain a TLS connection to the specified remote network.
+    ///
+    ///      use water::Net;
+    ///      use water::TlsConfig;
+    ///      let net = Net::new(100);
+    ///      let mut config = TlsConfig::new();
+    ///      config.ca = Some(Path::new("ca.pem"));
+    ///      net.tlsconnect(String::from_str("localhost:40100"), config);
+    ///
+    pub fn tlsconnect(&self, addr: String, config: TlsConfig) -> BridgeConnector {
+        BridgeConnector::new(self, Box::new(TlsTransport::new(addr, config)))
+    }
+
     /// Send message with specified from addresses.
     pub fn sendas(&self, mut msg: Message, fromsid: ID, fromeid: ID) -> usize {
         msg.srcsid = fromsid;
@@ -201,24 +541,186 @@ impl Net {
     pub fn send(&self, msg: Message) -> usize {
         if msg.is_raw() {
             // Duplicate it to not share the buffer with the sender.
-            self.send_internal(msg.dup())
+            self.send_internal(self.stampbroadcast(msg.dup()), None)
         } else {
-            self.send_internal(msg)
+            self.send_internal(self.stampbroadcast(msg), None)
         }
     }
 
-    // Try to give the message to all endpoints. The endpoints do the logic
-    // to determine if they will recieve the message.
-    fn send_internal(&self, msg: Message) -> usize {
+    /// _(internal)_ Send a message a bridge received from its remote. It is not
+    /// given back to the endpoint of that bridge, since a broadcast would only
+    /// go back to where it came from.
+    pub fn forward(&self, msg: Message, from: &Endpoint) -> usize {
+        self.send_internal(self.stampbroadcast(msg), Some(from.id()))
+    }
+
+    /// Send message and return the outcome for each endpoint it was offered to.
+    pub fn sendreport(&self, msg: Message) -> SendReport {
+        let msg = self.stampbroadcast(if msg.is_raw() { msg.dup() } else { msg });
+        let mut outcomes: Vec<(ID, ID, Delivery)> = Vec::new();
+
+        for ep in self.route(&msg).iter() {
+            outcomes.push((ep.getsid(), ep.geteid(), ep.offer(&msg)));
+        }
+
+        SendReport { outcomes: outcomes }
+    }
+
+    /// Send message and if any endpoint rejects it because of a limit then block until
+    /// that endpoint has space or the duration expires. Any endpoint that still has no
+    /// space when it expires is reported as rejected.
+    pub fn sendorblock(&self, msg: Message, duration: Duration) -> IoResult<SendReport> {
+        self.sendorblockuntil(msg, Some(get_time() + duration))
+    }
+
+    /// Send message and if any endpoint rejects it because of a limit then block until
+    /// that endpoint has space.
+    pub fn sendorblockforever(&self, msg: Message) -> IoResult<SendReport> {
+        self.sendorblockuntil(msg, None)
+    }
+
+    fn sendorblockuntil(&self, msg: Message, until: Option<Timespec>) -> IoResult<SendReport> {
+        let msg = self.stampbroadcast(if msg.is_raw() { msg.dup() } else { msg });
+        let mut outcomes: Vec<(Endpoint, Delivery)> = Vec::new();
+
+        for ep in self.route(&msg).into_iter() {
+            let delivery = ep.offer(&msg);
+            outcomes.push((ep, delivery));
+        }
+
+        loop {
+            // A sync message is only taken once so it is enough that anyone has it.
+            if msg.is_sync() && outcomes.iter().any(|o| o.1 == Delivery::Delivered) {
+                break;
+            }
+
+            let mut rejected = false;
+            let mut timedout = false;
+
+            for outcome in outcomes.iter_mut() {
+                if outcome.1 == Delivery::QueueFull || outcome.1 == Delivery::MemoryLimit {
+                    // It stays rejected in the report. Once the time is up the
+                    // rest are only given the message if they have space now.
+                    if !outcome.0.waitforspace(until) {
+                        timedout = true;
+                        continue;
+                    }
+                    // Someone else may have filled the space before us.
+                    outcome.1 = outcome.0.offer(&msg);
+                    if outcome.1 == Delivery::Delivered && msg.is_sync() {
+                        break;
+                    }
+                    if outcome.1 != Delivery::Delivered && outcome.1 != Delivery::Filtered {
+                        rejected = true;
+                    }
+                }
+            }
+
+            if !rejected || timedout {
+                break;
+            }
+        }
+
+        Ok(SendReport {
+            outcomes: outcomes.iter().map(|o| (o.0.getsid(), o.0.geteid(), o.1)).collect(),
+        })
+    }
+
+    // Use the indexes to find the endpoints that may want the message. This
+    // only narrows down the set, and the endpoints still do the logic to
+    // determine if they will recieve the message.
+    fn route(&self, msg: &Message) -> Vec<Endpoint> {
+        let i = self.i.lock().unwrap();
+        let mut out: Vec<Endpoint> = Vec::new();
+
+        // A broadcast falls back to the full set.
+        if msg.dstsid == 0 && msg.dsteid == 0 && msg.dstgid == 0 {
+            for entry in i.endpoints.values() {
+                out.push(entry.ep.clone());
+            }
+            return out;
+        }
+
+        // The local endpoints.
+        if msg.dstsid == 0 || msg.dstsid == 1 || msg.dstsid == self.sid {
+            if msg.dsteid != 0 {
+                i.collect(i.byaddr.get(&(self.sid, msg.dsteid)), &mut out);
+            } else if msg.dstgid != 0 {
+                i.collect(i.bygid.get(&msg.dstgid), &mut out);
+            } else {
+                i.collect(i.bysid.get(&self.sid), &mut out);
+            }
+        }
+
+        // The bridges to remote nets.
+        if msg.dstsid == 0 {
+            i.collect(Some(&i.bridges), &mut out);
+        } else if msg.dstsid != 1 && msg.dstsid != self.sid {
+            // There may be more than one link to the neighbor, such as a
+            // connector and a listener connection, but only one carries it.
+            match i.nexthop(msg.dstsid) {
+                Some((neighbor, _)) => i.collectone(i.bysid.get(&neighbor), &mut out),
+                None => i.collectone(i.bysid.get(&msg.dstsid), &mut out),
+            }
+        }
+
+        out
+    }
+
+    /// Give a broadcast sent from this net an ID, and remember it so that it is
+    /// not delivered again when a bridge brings it back.
+    fn stampbroadcast(&self, mut msg: Message) -> Message {
+        if msg.dstsid == 0 && msg.msgid == 0 {
+            // Random so that it is unique without asking the other nets.
+            while msg.msgid == 0 {
+                msg.msgid = random::<u64>();
+            }
+            self.markseen(msg.srcsid, msg.msgid);
+        }
+        msg
+    }
+
+    /// _(internal)_ Returns true if the broadcast was already delivered on this
+    /// net.
+    pub fn isseen(&self, srcsid: ID, msgid: u64) -> bool {
+        self.i.lock().unwrap().seen.contains(&(srcsid, msgid))
+    }
+
+    /// _(internal)_ Remember a broadcast as delivered on this net. Returns false
+    /// if it already was, which means a bridge brought it back around a loop.
+    pub fn markseen(&self, srcsid: ID, msgid: u64) -> bool {
+        let mut i = self.i.lock().unwrap();
+
+        if !i.seen.insert((srcsid, msgid)) {
+            return false;
+        }
+
+        i.seenorder.push_back((srcsid, msgid));
+        if i.seenorder.len() > SEEN_SIZE {
+            match i.seenorder.pop_front() {
+                Some(key) => { i.seen.remove(&key); },
+                None => { },
+            }
+        }
+
+        true
+    }
+
+    // Try to give the message to all endpoints that the routing selects. The
+    // endpoints do the logic to determine if they will recieve the message.
+    fn send_internal(&self, msg: Message, skip: Option<usize>) -> usize {
         let mut ocnt = 0us;
 
         // Just to be safe I only want to call immutable methods
         // on endpoints since we are not locking and as long as
         // it is sync this is okay, but I do need to force the
         // reference to a mutable one.
-        let mut local = self.i.lock().unwrap().endpoints.clone();
+        let mut local = self.route(&msg);
 
         for ep in local.iter_mut() {
+            if Some(ep.id()) == skip {
+                continue;
+            }
             if ep.give(&msg) {
                 ocnt += 1;
             }
@@ -227,6 +729,134 @@ impl Net {
         ocnt
     }
 
+    /// Get the net ID of the neighbor that messages for the net are sent
+    /// through, or `None` if it can not be reached.
+    pub fn getnexthop(&self, sid: ID) -> Option<ID> {
+        self.i.lock().unwrap().nexthop(sid).map(|route| route.0)
+    }
+
+    /// Return every net that can be reached with the neighbor it is reached
+    /// through and the hops to it, as `(sid, neighbor, hops)`.
+    pub fn getroutes(&self) -> Vec<(ID, ID, u8)> {
+        let i = self.i.lock().unwrap();
+        let mut sids: Vec<ID> = i.neighbors.keys().map(|sid| *sid).collect();
+        for routes in i.adverts.values() {
+            for sid in routes.keys() {
+                if !sids.contains(sid) {
+                    sids.push(*sid);
+                }
+            }
+        }
+        sids.sort();
+        sids.into_iter()
+            .filter(|sid| *sid != self.sid)
+            .filter_map(|sid| i.nexthop(sid).map(|(neighbor, hops)| (sid, neighbor, hops)))
+            .collect()
+    }
+
+    /// _(internal)_ The routes to advertise to a neighbor. A route learned from
+    /// the neighbor is given back as unreachable so that it is never used to
+    /// reach the neighbor's own routes through us.
+    pub fn getadvert(&self, neighbor: ID) -> Vec<(ID, u8)> {
+        let mut routes = vec![(self.sid, 0u8)];
+        for (sid, via, hops) in self.getroutes().into_iter() {
+            if sid == neighbor {
+                continue;
+            }
+            routes.push((sid, if via == neighbor { MAX_HOPS } else { hops }));
+        }
+        routes
+    }
+
+    /// _(internal)_ Replace the routes advertised by the neighbor. Returns true
+    /// if they changed.
+    pub fn setadvert(&self, neighbor: ID, routes: Vec<(ID, u8)>) -> bool {
+        let mut i = self.i.lock().unwrap();
+
+        // Only a current link can advertise.
+        if !i.neighbors.contains_key(&neighbor) {
+            return false;
+        }
+
+        let routes: HashMap<ID, u8> = routes.into_iter()
+            .filter(|&(sid, hops)| sid != self.sid && hops < MAX_HOPS)
+            .collect();
+
+        if i.adverts.get(&neighbor) == Some(&routes) {
+            return false;
+        }
+
+        i.adverts.insert(neighbor, routes);
+        drop(i);
+        self.routeschanged();
+        true
+    }
+
+    /// _(internal)_ Returns true if messages from the net may come from the
+    /// neighbor. That is the neighbor itself, or a net it advertised a route to.
+    /// A broadcast is flooded over every path, but anything else only comes
+    /// from the neighbor if no other has a shorter route. No neighbor speaks
+    /// for us, whatever it advertised.
+    pub fn speaksfor(&self, neighbor: ID, sid: ID, flooded: bool) -> bool {
+        if sid == self.sid {
+            return false;
+        }
+        if sid == neighbor {
+            return true;
+        }
+
+        let i = self.i.lock().unwrap();
+        let hops = match i.adverts.get(&neighbor).and_then(|routes| routes.get(&sid)) {
+            Some(&hops) => hops + 1,
+            None => return false,
+        };
+
+        if flooded {
+            return true;
+        }
+
+        match i.nexthop(sid) {
+            Some((_, best)) => hops <= best,
+            None => false,
+        }
+    }
+
+    /// _(internal)_ Called when a link to a neighbor is up.
+    pub fn addneighbor(&self, neighbor: ID) {
+        let mut i = self.i.lock().unwrap();
+        let count = i.neighbors.get(&neighbor).map(|count| *count).unwrap_or(0);
+        i.neighbors.insert(neighbor, count + 1);
+        drop(i);
+        self.routeschanged();
+    }
+
+    /// _(internal)_ Called when a link to a neighbor is down. Once the last
+    /// link to it is gone every route through it is withdrawn.
+    pub fn removeneighbor(&self, neighbor: ID) {
+        let mut i = self.i.lock().unwrap();
+        let count = i.neighbors.get(&neighbor).map(|count| *count).unwrap_or(0);
+        if count > 1 {
+            i.neighbors.insert(neighbor, count - 1);
+            return;
+        }
+        i.neighbors.remove(&neighbor);
+        i.adverts.remove(&neighbor);
+        drop(i);
+        self.routeschanged();
+    }
+
+    /// Have every bridge advertise its routes again.
+    fn routeschanged(&self) {
+        let mut bridges: Vec<Endpoint> = Vec::new();
+        {
+            let i = self.i.lock().unwrap();
+            i.collect(Some(&i.bridges), &mut bridges);
+        }
+        for ep in bridges.iter() {
+            ep.give(&Message::new_clone(RouteUpdateMessage));
+        }
+    }
+
     /// Returns a ID that is unique to this net. It does this by tracking
     /// all IDs used and always returns one higher than the highest ID endpoint
     /// that is currently on the network.
@@ -260,39 +890,46 @@ impl Net {
 
         let ep = Endpoint::new(self.sid, eid, self.clone());
 
-        i.endpoints.push(ep.clone());
+        i.index(self.sid, ep.clone());
 
         ep
     }
 
+    /// _(internal)_ Get the event loop that runs the stream links of the bridges
+    /// on this net, starting it the first time. It stops once the net is gone.
+    pub fn geteventloop(&self) -> IoResult<Arc<EventLoop>> {
+        let mut i = self.i.lock().unwrap();
+
+        if i.eventloop.is_none() {
+            i.eventloop = Some(Arc::new(try!(EventLoop::new())));
+        }
+
+        Ok(i.eventloop.as_ref().unwrap().clone())
+    }
+
     /// Not recommend for usage.
     pub fn add_endpoint(&self, ep: Endpoint) {
-        self.i.lock().unwrap().endpoints.push(ep);
+        self.i.lock().unwrap().index(self.sid, ep);
+    }
+
+    /// _(internal)_ Update the indexes after the endpoint has changed any of
+    /// its addresses. Does nothing if the endpoint is not on this net.
+    pub fn reindex(&self, thisep: &Endpoint) {
+        let mut i = self.i.lock().unwrap();
+        match i.unindex(self.sid, thisep.id()) {
+            Some(ep) => i.index(self.sid, ep),
+            None => { },
+        }
     }
 
     /// Not recommened for usage.
     pub fn drop_endpoint(&self, thisep: &Endpoint) {
+        // The drop method of Endpoint likely called this method so we
+        // make sure to release the lock before our instance is dropped.
         let mut lock = self.i.lock().unwrap();
-        let endpoints = &mut lock.endpoints;
-        let mut ndx = 0us;
-        let mut fnd = false;
-        for i in range(0us, endpoints.len()) {
-            if endpoints[i].id() == thisep.id() {
-                // The drop method of Endpoint likely called this method
-                // so hopefully it is capable of handling a nested call
-                // back into it's self.
-                ndx = i;
-                if fnd {
-                    panic!("multiple ep with same id");
-                }
-                fnd = true;
-            }
-        }
-
-        if fnd {
-            //println!("thread:{} drop_endpoint thisep:{:x} refcnt:{}", Thread::current().name().unwrap_or("none"), thisep.id(), thisep.getrefcnt());
-            endpoints.remove(ndx);
-        }
+        let ep = lock.unindex(self.sid, thisep.id());
+        drop(lock);
+        drop(ep);
     }
 
     /// Return a new endpoint with an automatically assigned unique ID.
@@ -304,7 +941,7 @@ impl Net {
 
         let epclone = ep.clone();
 
-        i.endpoints.push(epclone);
+        i.index(self.sid, epclone);
 
         ep
     }
@@ -0,0 +1,223 @@
+//! Here we implement a request and reply layer on top of `Endpoint`. A
+//! request is stamped with a correlation ID which the reply carries back
+//! so that the caller can tell its reply apart from any other message. It
+//! works with sync and clone messages on the local net and with raw
+//! messages across bridges since the correlation ID crosses with them.
+
+#![allow(unused_imports)]
+#![allow(dead_code)]
+#![allow(unused_variables)]
+
+use std::time::duration::Duration;
+
+use time::get_time;
+
+use net::Net;
+use net::ID;
+use endpoint::Endpoint;
+use message::Message;
+use error::IoResult;
+use error::WaterError;
+
+/// Sends requests and waits for the matching replies.
+///
+/// The client owns an endpoint which the replies are addressed to. Any message
+/// that arrives on it which is not the reply being waited on is thrown away, which
+/// includes late replies to earlier calls that timed out. Therefore, you should not
+/// use the endpoint of a client for anything else, and a client should only be used
+/// by one thread at a time which is why `call` takes `&mut self`.
+///
+///     #![allow(unstable)]
+///     use water::Net;
+///     use water::Duration;
+///     use water::Message;
+///     use water::RpcClient;
+///
+///     let net = Net::new(100);
+///     let server = net.new_endpoint();
+///     let mut client = RpcClient::new(&net);
+///
+///     // With nobody to reply it will time out.
+///     let result = client.call(1, server.geteid(), Message::new_clone(3us), Duration::milliseconds(10));
+///     assert!(result.is_err());
+///
+pub struct RpcClient {
+    ep:             Endpoint,
+    lastcorid:      u64,
+}
+
+impl RpcClient {
+    /// Create a client with a new endpoint on the net.
+    pub fn new(net: &Net) -> RpcClient {
+        RpcClient::from_endpoint(net.new_endpoint())
+    }
+
+    /// Create a client using the endpoint.
+    pub fn from_endpoint(ep: Endpoint) -> RpcClient {
+        RpcClient {
+            ep:             ep,
+            lastcorid:      0,
+        }
+    }
+
+    /// Return the endpoint the replies are received on.
+    pub fn getendpoint(&self) -> &Endpoint {
+        &self.ep
+    }
+
+    /// Send the message as a request to the destination and block until the reply
+    /// arrives or the timeout expires. If nobody was given the request then
+    /// `WaterError::NetDisconnected` is returned.
+    pub fn call(&mut self, dstsid: ID, dsteid: ID, mut msg: Message, timeout: Duration) -> IoResult<Message> {
+        // Zero means no correlation ID, so never use it.
+        self.lastcorid += 1;
+        let corid = self.lastcorid;
+
+        msg.dstsid = dstsid;
+        msg.dsteid = dsteid;
+        msg.corid = corid;
+
+        if self.ep.send(msg) == 0 {
+            return Err(WaterError::NetDisconnected);
+        }
+
+        let until = get_time() + timeout;
+
+        loop {
+            let now = get_time();
+            if now >= until {
+                return Err(WaterError::TimedOut);
+            }
+
+            let reply = try!(self.ep.recvorblock(until - now));
+
+            if reply.corid == corid {
+                return Ok(reply);
+            }
+        }
+    }
+
+    /// Send the type as a sync message request. See `call`.
+    pub fn callsynctype<T: Send + 'static>(&mut self, dstsid: ID, dsteid: ID, t: T, timeout: Duration) -> IoResult<Message> {
+        self.call(dstsid, dsteid, Message::new_sync(t), timeout)
+    }
+
+    /// Send the type as a clone message request. See `call`.
+    pub fn callclonetype<T: Send + Clone + 'static>(&mut self, dstsid: ID, dsteid: ID, t: T, timeout: Duration) -> IoResult<Message> {
+        self.call(dstsid, dsteid, Message::new_clone(t), timeout)
+    }
+}
+
+struct Handler {
+    accepts:        Box<Fn(&Message) -> bool + Send>,
+    handler:        Box<FnMut(Message) -> Option<Message> + Send>,
+}
+
+/// Receives requests on an endpoint and replies using the handlers registered.
+///
+/// A handler is registered for a payload type and is called with each request that
+/// contains that type. If the handler returns a message it is sent back to the source
+/// of the request with the correlation ID of the request.
+///
+///     #![allow(unstable)]
+///     use water::Net;
+///     use water::Duration;
+///     use water::Message;
+///     use water::RpcServer;
+///
+///     let net = Net::new(100);
+///     let mut server = RpcServer::new(net.new_endpoint());
+///
+///     server.handle::<usize, _>(|msg| {
+///         let value: usize = msg.typeunwrap();
+///         Some(Message::new_clone(value * 2))
+///     });
+///
+///     // This would normally be called in a loop on its own thread.
+///     server.serveonce(Duration::milliseconds(10));
+///
+pub struct RpcServer {
+    ep:             Endpoint,
+    handlers:       Vec<Handler>,
+}
+
+impl RpcServer {
+    /// Create a server that receives requests on the endpoint.
+    pub fn new(ep: Endpoint) -> RpcServer {
+        RpcServer {
+            ep:             ep,
+            handlers:       Vec::new(),
+        }
+    }
+
+    /// Return the endpoint the requests are received on.
+    pub fn getendpoint(&self) -> &Endpoint {
+        &self.ep
+    }
+
+    /// Register a handler for requests that contain the type `T` as a sync or
+    /// clone message.
+    pub fn handle<T, F>(&mut self, f: F)
+        where T: Send + 'static, F: FnMut(Message) -> Option<Message> + Send
+    {
+        self.handlers.push(Handler {
+            accepts:    Box::new(|msg: &Message| msg.is_type::<T>()),
+            handler:    Box::new(f),
+        });
+    }
+
+    /// Register a handler for requests that are raw messages. This is the only
+    /// type of request that can arrive from across a bridge.
+    pub fn handleraw<F>(&mut self, f: F)
+        where F: FnMut(Message) -> Option<Message> + Send
+    {
+        self.handlers.push(Handler {
+            accepts:    Box::new(|msg: &Message| msg.is_raw()),
+            handler:    Box::new(f),
+        });
+    }
+
+    /// Call the first handler that accepts the message and send its reply. Returns
+    /// `false` if no handler accepted the message.
+    pub fn dispatch(&mut self, msg: Message) -> bool {
+        let srcsid = msg.srcsid;
+        let srceid = msg.srceid;
+        let corid = msg.corid;
+
+        for handler in self.handlers.iter_mut() {
+            if !(*handler.accepts)(&msg) {
+                continue;
+            }
+
+            match (*handler.handler)(msg) {
+                Some(mut reply) => {
+                    reply.dstsid = srcsid;
+                    reply.dsteid = srceid;
+                    reply.corid = corid;
+                    self.ep.send(reply);
+                },
+                None => { },
+            }
+
+            return true;
+        }
+
+        false
+    }
+
+    /// Receive one request or block until the timeout expires, then dispatch it.
+    pub fn serveonce(&mut self, timeout: Duration) -> IoResult<bool> {
+        let msg = try!(self.ep.recvorblock(timeout));
+        Ok(self.dispatch(msg))
+    }
+
+    /// Receive and dispatch requests forever.
+    pub fn serveforever(&mut self) {
+        loop {
+            match self.ep.recvorblockforever() {
+                Ok(msg) => { self.dispatch(msg); },
+                Err(_) => { },
+            }
+        }
+    }
+}
@@ -0,0 +1,240 @@
+//! Here we implement the `Selector` which provides the ability to wait
+//! on multiple endpoints at the same time. Instead of spinning it sleeps
+//! on a token shared with the endpoints which they signal when they are
+//! given a message.
+
+#![allow(unused_imports)]
+#![allow(dead_code)]
+#![allow(unused_variables)]
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::Condvar;
+use std::time::duration::Duration;
+
+use time::Timespec;
+use time::get_time;
+
+use endpoint::Endpoint;
+use error::IoResult;
+use error::WaterError;
+use message::Message;
+
+/// _(internal)_ Told each time a token is signaled. This is for a waiter that
+/// is not a thread sleeping on the token, such as the event loop of the bridges.
+pub trait Waker {
+    fn wake(&self);
+}
+
+/// _(internal)_ A wakeup token shared between a selector and its endpoints.
+///
+/// The token holds a generation counter which is incremented each time it is
+/// signaled. A waiting thread remembers the generation before it checks the
+/// endpoints and only sleeps if the generation has not changed, which keeps
+/// a message given between the check and the sleep from being missed.
+pub struct SelectToken {
+    generation:     Mutex<u64>,
+    condvar:        Condvar,
+    waker:          Option<Box<Waker + Send + Sync>>,
+}
+
+impl SelectToken {
+    pub fn new() -> SelectToken {
+        SelectToken {
+            generation:     Mutex::new(0),
+            condvar:        Condvar::new(),
+            waker:          None,
+        }
+    }
+
+    /// Create a token that also tells the waker each time it is signaled.
+    pub fn with_waker(waker: Box<Waker + Send + Sync>) -> SelectToken {
+        SelectToken {
+            generation:     Mutex::new(0),
+            condvar:        Condvar::new(),
+            waker:          Some(waker),
+        }
+    }
+
+    /// Return the current generation.
+    pub fn generation(&self) -> u64 {
+        *self.generation.lock().unwrap()
+    }
+
+    /// Wake every thread waiting on this token.
+    pub fn signal(&self) {
+        {
+            let mut generation = self.generation.lock().unwrap();
+            *generation += 1;
+            self.condvar.notify_all();
+        }
+
+        match self.waker {
+            Some(ref waker) => waker.wake(),
+            None => { },
+        }
+    }
+
+    /// Wait until the generation differs from `generation` or until the time
+    /// specified has been reached. Returns `false` if the time was reached.
+    fn waitchange(&self, generation: u64, until: Option<Timespec>) -> bool {
+        let mut lock = self.generation.lock().unwrap();
+
+        while *lock == generation {
+            match until {
+                None => {
+                    lock = self.condvar.wait(lock).unwrap();
+                },
+                Some(until) => {
+                    let now = get_time();
+                    if now >= until {
+                        return false;
+                    }
+                    let result = self.condvar.wait_timeout(lock, until - now).unwrap();
+                    lock = result.0;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+/// Waits on multiple endpoints and returns the first message that arrives on any of them.
+///
+/// Each endpoint added is told about the selector so that when it is given a message the
+/// selector is woken. When more than one endpoint has messages the selector will service
+/// them in turn, starting after the endpoint that was last serviced, so that a busy endpoint
+/// can not starve the others.
+///
+///     #![allow(unstable)]
+///     use water::Net;
+///     use water::Selector;
+///     use water::Duration;
+///
+///     let net = Net::new(100);
+///     let ep1 = net.new_endpoint();
+///     let ep2 = net.new_endpoint();
+///     let ep3 = net.new_endpoint();
+///
+///     let mut selector = Selector::new();
+///     selector.add(&ep1);
+///     selector.add(&ep2);
+///
+///     ep3.sendclonetype(3us);
+///
+///     let (ndx, msg) = selector.recvorblock(Duration::seconds(5)).unwrap();
+///     println!("endpoint {} got message [{}]", ndx, msg.typeunwrap::<usize>());
+///
+pub struct Selector {
+    token:          Arc<SelectToken>,
+    endpoints:      Vec<Endpoint>,
+    next:           usize,
+}
+
+impl Drop for Selector {
+    fn drop(&mut self) {
+        for ep in self.endpoints.iter() {
+            ep.removeselecttoken(&self.token);
+        }
+    }
+}
+
+impl Selector {
+    /// Create a new selector with no endpoints.
+    pub fn new() -> Selector {
+        Selector {
+            token:          Arc::new(SelectToken::new()),
+            endpoints:      Vec::new(),
+            next:           0,
+        }
+    }
+
+    /// Create a new selector with the endpoints from the list.
+    pub fn from_list(list: &Vec<Endpoint>) -> Selector {
+        let mut selector = Selector::new();
+        for ep in list.iter() {
+            selector.add(ep);
+        }
+        selector
+    }
+
+    /// Add an endpoint and return the index that will be used to identify it.
+    pub fn add(&mut self, ep: &Endpoint) -> usize {
+        ep.addselecttoken(self.token.clone());
+        self.endpoints.push(ep.clone());
+        self.endpoints.len() - 1
+    }
+
+    /// Remove an endpoint. _The index of any endpoint added after it will
+    /// be decreased by one._
+    pub fn remove(&mut self, ep: &Endpoint) {
+        let id = ep.id();
+        for ep in self.endpoints.iter() {
+            if ep.id() == id {
+                ep.removeselecttoken(&self.token);
+            }
+        }
+        self.endpoints.retain(|ep| ep.id() != id);
+        self.next = 0;
+    }
+
+    /// Return the number of endpoints.
+    pub fn len(&self) -> usize {
+        self.endpoints.len()
+    }
+
+    /// Return the endpoint for the index.
+    pub fn get(&self, ndx: usize) -> &Endpoint {
+        &self.endpoints[ndx]
+    }
+
+    /// Receive a message from any of the endpoints with out blocking and return an
+    /// error condition if none. On success the index of the endpoint is returned with
+    /// the message.
+    pub fn recv(&mut self) -> IoResult<(usize, Message)> {
+        let count = self.endpoints.len();
+
+        for x in range(0us, count) {
+            let ndx = (self.next + x) % count;
+            let result = self.endpoints[ndx].recv();
+            if result.is_ok() {
+                self.next = (ndx + 1) % count;
+                return Ok((ndx, result.unwrap()));
+            }
+        }
+
+        Err(WaterError::NoMessages)
+    }
+
+    /// Receive a message from any of the endpoints or block until one arrives.
+    pub fn recvorblockforever(&mut self) -> IoResult<(usize, Message)> {
+        self.recvorblockuntil(None)
+    }
+
+    /// Receive a message from any of the endpoints or block until the specified duration
+    /// expires then return an error condition.
+    pub fn recvorblock(&mut self, duration: Duration) -> IoResult<(usize, Message)> {
+        self.recvorblockuntil(Some(get_time() + duration))
+    }
+
+    fn recvorblockuntil(&mut self, until: Option<Timespec>) -> IoResult<(usize, Message)> {
+        loop {
+            let generation = self.token.generation();
+
+            let result = self.recv();
+            if result.is_ok() {
+                return result;
+            }
+
+            if !self.token.waitchange(generation, until) {
+                // One last try since we may have raced with the timeout.
+                let result = self.recv();
+                if result.is_ok() {
+                    return result;
+                }
+                return Err(WaterError::TimedOut);
+            }
+        }
+    }
+}
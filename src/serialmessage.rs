@@ -0,0 +1,91 @@
+use std::str;
+
+use rustc_serialize::Encodable;
+use rustc_serialize::Decodable;
+use rustc_serialize::json;
+
+use rawmessage::RawMessage;
+use error::IoResult;
+use error::WaterError;
+
+/// A message holding a type encoded so that it can cross process boundaries.
+///
+/// Unlike sync and clone messages the type is not copied as raw bytes but is
+/// encoded, therefore, it can contain pointers such as a `Vec` or `String`. The
+/// type is encoded when the message is created and decoded each time it is
+/// received, so a receiver on the local net works exactly like a receiver on a
+/// remote net.
+///
+/// The type is named by a tag that the sender picks, such as `"order"`, and
+/// the receiver gives the same tag to decode it. The name of the type in Rust is
+/// not used since it can differ between builds.
+pub struct SerialMessage {
+    /// The tag naming the type contained.
+    pub tag:            String,
+    /// The encoded type.
+    pub payload:        RawMessage,
+}
+
+impl Clone for SerialMessage {
+    /// Will produce a clone but will keep the same payload instance.
+    fn clone(&self) -> SerialMessage {
+        SerialMessage {
+            tag:        self.tag.clone(),
+            payload:    self.payload.clone(),
+        }
+    }
+}
+
+impl SerialMessage {
+    /// Create a new serial message by encoding the type under the tag.
+    pub fn new<T: Encodable>(tag: &str, t: &T) -> SerialMessage {
+        let encoded = json::encode(t);
+
+        SerialMessage {
+            tag:        String::from_str(tag),
+            payload:    RawMessage::new_fromstr(encoded.as_slice()),
+        }
+    }
+
+    /// _(internal)_ Create a serial message from the tag and encoded bytes that
+    /// arrived from a remote net.
+    pub fn from_parts(tag: String, payload: RawMessage) -> SerialMessage {
+        SerialMessage {
+            tag:        tag,
+            payload:    payload,
+        }
+    }
+
+    /// Check if the type with the tag is contained. `is_tag("order")`
+    pub fn is_tag(&self, tag: &str) -> bool {
+        self.tag.as_slice() == tag
+    }
+
+    /// Decode the type if the tag matches, otherwise `WaterError::WrongType` is
+    /// returned. If it can not be decoded as `T` then `WaterError::Protocol` is
+    /// returned.
+    pub fn try_payload<T: Decodable>(&self, tag: &str) -> IoResult<T> {
+        if !self.is_tag(tag) {
+            return Err(WaterError::WrongType);
+        }
+
+        let s = match str::from_utf8(self.payload.as_slice()) {
+            Ok(s) => s,
+            Err(_) => return Err(WaterError::Protocol(format!("could not decode {}", self.tag))),
+        };
+
+        match json::decode::<T>(s) {
+            Ok(t) => Ok(t),
+            Err(_) => Err(WaterError::Protocol(format!("could not decode {}", self.tag))),
+        }
+    }
+
+    /// Decode and return the type by consuming the serial message. Will panic if
+    /// the tag does not match or the type can not be decoded.
+    pub fn get_payload<T: Decodable>(self, tag: &str) -> T {
+        match self.try_payload::<T>(tag) {
+            Ok(t) => t,
+            Err(e) => panic!("serial message could not be unwrapped: {}", e),
+        }
+    }
+}
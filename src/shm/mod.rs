@@ -0,0 +1,552 @@
+//! This implements the shared memory transport for bridges. It is the fastest way
+//! to link nets in different processes on the same machine.
+//!
+//! The listener creates a file holding a region which the connector maps into its
+//! own process. The region holds two rings, one for each direction, which work
+//! like `SizedRingQueue` except that they hold bytes and have exactly one writer
+//! and one reader. A frame is copied straight from the message into the ring and
+//! is copied out only once by the other side. A side waiting for data or space
+//! sleeps on a futex in the region, so nothing is polled.
+//!
+//! The address is the path of the file. It should be on a file system kept in
+//! memory such as `/dev/shm`. Only one link at a time can use a region, and the
+//! file is removed when the listener is terminated.
+//!
+//! _A process that dies while linked leaves the region marked as in use, and the
+//! listener has to be started again with the file._
+
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::intrinsics::atomic_load_acq;
+use std::intrinsics::atomic_store_rel;
+use std::intrinsics::atomic_xadd;
+use std::intrinsics::atomic_xsub;
+use std::intrinsics::atomic_cxchg;
+use std::ptr::copy_nonoverlapping_memory;
+use std::ffi::CString;
+use std::mem::size_of;
+use std::time::duration::Duration;
+
+use libc::c_void;
+use libc::size_t;
+
+use time::Timespec;
+use time::get_time;
+
+use error::IoResult;
+use error::WaterError;
+use bridge::BridgeListener;
+use bridge::BridgeConnector;
+use bridge::Transport;
+use bridge::LinkAcceptor;
+use bridge::link::Link;
+use sys;
+use sys::Fd;
+
+/// A bridge listener using shared memory.
+pub type ShmBridgeListener = BridgeListener;
+/// A bridge connector using shared memory.
+pub type ShmBridgeConnector = BridgeConnector;
+
+/// The bytes in each ring unless another capacity is given.
+pub const DEFAULT_CAPACITY: usize = 1024 * 1024 * 4;
+
+/// Spells `WSMR` and starts every region.
+const REGION_MAGIC: u32 = 0x57534d52;
+const REGION_VERSION: u32 = 1;
+/// The rings start after this many bytes.
+const HEADER_BYTES: usize = 4096;
+
+// The states of a region.
+const STATE_IDLE: u32 = 0;
+const STATE_WAITING: u32 = 1;
+const STATE_CONNECTED: u32 = 2;
+const STATE_CLOSED: u32 = 3;
+
+/// How long to sleep at most before checking if an acceptor was closed.
+const ACCEPT_POLL_MS: i64 = 100;
+
+#[repr(C)]
+struct RegionHeader {
+    magic:          u32,
+    version:        u32,
+    capacity:       u64,
+    state:          u32,
+    /// The number of sides of a link that still have the region mapped.
+    sides:          u32,
+}
+
+#[repr(C)]
+struct RingHeader {
+    /// The total bytes ever written.
+    head:           u64,
+    /// The total bytes ever read.
+    tail:           u64,
+    /// Changed each time a unit is written.
+    dataseq:        u32,
+    /// Changed each time a unit is read.
+    spaceseq:       u32,
+    closed:         u32,
+    pad:            u32,
+}
+
+fn timespecfrom(ms: i64) -> sys::timespec {
+    sys::timespec {
+        tv_sec:     ms / 1000,
+        tv_nsec:    (ms % 1000) * 1000000,
+    }
+}
+
+/// The region mapped into this process.
+struct Mapping {
+    base:           *mut u8,
+    size:           usize,
+    fd:             Fd,
+    /// The file the region is in.
+    path:           String,
+}
+
+unsafe impl Send for Mapping { }
+unsafe impl Sync for Mapping { }
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { sys::munmap(self.base as *mut c_void, self.size as size_t) };
+    }
+}
+
+impl Mapping {
+    fn map(fd: Fd, size: usize, path: String) -> IoResult<Mapping> {
+        let base = unsafe {
+            sys::mmap(
+                0 as *mut c_void, size as size_t, sys::PROT_READ | sys::PROT_WRITE,
+                sys::MAP_SHARED, fd.fd, 0
+            )
+        };
+
+        if base as isize == -1 {
+            return Err(sys::lasterror());
+        }
+
+        Ok(Mapping { base: base as *mut u8, size: size, fd: fd, path: path })
+    }
+
+    fn region(&self) -> *mut RegionHeader {
+        self.base as *mut RegionHeader
+    }
+
+    fn ring(&self, n: usize) -> *mut RingHeader {
+        (self.base as usize + 64 * (n + 1)) as *mut RingHeader
+    }
+
+    fn data(&self, n: usize) -> *mut u8 {
+        (self.base as usize + HEADER_BYTES + self.capacity() * n) as *mut u8
+    }
+
+    fn capacity(&self) -> usize {
+        unsafe { (*self.region()).capacity as usize }
+    }
+
+    fn state(&self) -> u32 {
+        unsafe { atomic_load_acq(&mut (*self.region()).state) }
+    }
+
+    fn setstate(&self, state: u32) {
+        unsafe {
+            atomic_store_rel(&mut (*self.region()).state, state);
+            sys::futexwake(&mut (*self.region()).state);
+        }
+    }
+
+    fn sides(&self) -> u32 {
+        unsafe { atomic_load_acq(&mut (*self.region()).sides) }
+    }
+
+    /// Sleep while the state is `state` for at most `ms` milliseconds.
+    fn waitstate(&self, state: u32, ms: i64) {
+        let ts = timespecfrom(ms);
+        unsafe { sys::futexwait(&mut (*self.region()).state, state, Some(&ts)) };
+    }
+
+    /// Make both rings empty and open for a new link.
+    fn reset(&self) {
+        for n in range(0us, 2us) {
+            unsafe {
+                let ring = self.ring(n);
+                atomic_store_rel(&mut (*ring).head, 0);
+                atomic_store_rel(&mut (*ring).tail, 0);
+                atomic_store_rel(&mut (*ring).closed, 0);
+            }
+        }
+    }
+
+    /// Close both rings and wake anyone waiting on them.
+    fn close(&self) {
+        for n in range(0us, 2us) {
+            unsafe {
+                let ring = self.ring(n);
+                atomic_store_rel(&mut (*ring).closed, 1);
+                atomic_xadd(&mut (*ring).dataseq, 1);
+                atomic_xadd(&mut (*ring).spaceseq, 1);
+                sys::futexwake(&mut (*ring).dataseq);
+                sys::futexwake(&mut (*ring).spaceseq);
+            }
+        }
+        self.setstate(STATE_CLOSED);
+    }
+
+    /// Copy bytes into the ring starting at the position, wrapping at the end.
+    fn copyin(&self, n: usize, pos: u64, src: &[u8]) {
+        let cap = self.capacity();
+        let off = (pos as usize) & (cap - 1);
+        let first = if src.len() < cap - off { src.len() } else { cap - off };
+        unsafe {
+            copy_nonoverlapping_memory((self.data(n) as usize + off) as *mut u8, src.as_ptr(), first);
+            copy_nonoverlapping_memory(self.data(n), (src.as_ptr() as usize + first) as *const u8, src.len() - first);
+        }
+    }
+
+    /// Copy bytes out of the ring starting at the position, wrapping at the end.
+    fn copyout(&self, n: usize, pos: u64, dst: &mut [u8]) {
+        let cap = self.capacity();
+        let off = (pos as usize) & (cap - 1);
+        let first = if dst.len() < cap - off { dst.len() } else { cap - off };
+        unsafe {
+            copy_nonoverlapping_memory(dst.as_mut_ptr(), (self.data(n) as usize + off) as *const u8, first);
+            copy_nonoverlapping_memory((dst.as_mut_ptr() as usize + first) as *mut u8, self.data(n), dst.len() - first);
+        }
+    }
+
+    /// Write a unit made of the parts into the ring, waiting for space.
+    fn write(&self, n: usize, parts: &[&[u8]]) -> IoResult<()> {
+        let size = parts.iter().fold(0us, |size, part| size + part.len());
+        let need = (size_of::<u64>() + size) as u64;
+        let cap = self.capacity() as u64;
+
+        if need > cap {
+            return Err(WaterError::Protocol(format!("unit size {} exceeds ring capacity {}", size, cap)));
+        }
+
+        let ring = self.ring(n);
+
+        unsafe {
+            let head = atomic_load_acq(&mut (*ring).head);
+
+            loop {
+                let seq = atomic_load_acq(&mut (*ring).spaceseq);
+
+                if atomic_load_acq(&mut (*ring).closed) != 0 {
+                    return Err(WaterError::NetDisconnected);
+                }
+
+                if cap - (head - atomic_load_acq(&mut (*ring).tail)) >= need {
+                    break;
+                }
+
+                sys::futexwait(&mut (*ring).spaceseq, seq, None);
+            }
+
+            let sizebytes: [u8; 8] = [
+                (size >> 56) as u8, (size >> 48) as u8, (size >> 40) as u8, (size >> 32) as u8,
+                (size >> 24) as u8, (size >> 16) as u8, (size >> 8) as u8, size as u8,
+            ];
+            self.copyin(n, head, &sizebytes);
+
+            let mut pos = head + sizebytes.len() as u64;
+            for part in parts.iter() {
+                self.copyin(n, pos, *part);
+                pos += part.len() as u64;
+            }
+
+            // The reader only looks at what is before the head.
+            atomic_store_rel(&mut (*ring).head, head + need);
+            atomic_xadd(&mut (*ring).dataseq, 1);
+            sys::futexwake(&mut (*ring).dataseq);
+        }
+
+        Ok(())
+    }
+
+    /// Read a unit from the ring, waiting for one until the deadline.
+    fn read(&self, n: usize, max: u64, deadline: Option<Timespec>) -> IoResult<Vec<u8>> {
+        let ring = self.ring(n);
+
+        unsafe {
+            let tail = atomic_load_acq(&mut (*ring).tail);
+
+            loop {
+                let seq = atomic_load_acq(&mut (*ring).dataseq);
+
+                if atomic_load_acq(&mut (*ring).head) != tail {
+                    break;
+                }
+
+                if atomic_load_acq(&mut (*ring).closed) != 0 {
+                    return Err(WaterError::NetDisconnected);
+                }
+
+                match deadline {
+                    Some(deadline) => {
+                        let left = (deadline - get_time()).num_milliseconds();
+                        if left <= 0 {
+                            return Err(WaterError::TimedOut);
+                        }
+                        let ts = timespecfrom(left);
+                        sys::futexwait(&mut (*ring).dataseq, seq, Some(&ts));
+                    },
+                    None => {
+                        sys::futexwait(&mut (*ring).dataseq, seq, None);
+                    },
+                }
+            }
+
+            let mut sizebytes = [0u8; 8];
+            self.copyout(n, tail, &mut sizebytes);
+            let size = sizebytes.iter().fold(0u64, |size, byte| (size << 8) | *byte as u64);
+
+            if size > max {
+                return Err(WaterError::Protocol(format!("unit size {} exceeds maximum {}", size, max)));
+            }
+
+            let mut unit: Vec<u8> = Vec::with_capacity(size as usize);
+            unit.set_len(size as usize);
+            self.copyout(n, tail + sizebytes.len() as u64, unit.as_mut_slice());
+
+            atomic_store_rel(&mut (*ring).tail, tail + sizebytes.len() as u64 + size);
+            atomic_xadd(&mut (*ring).spaceseq, 1);
+            sys::futexwake(&mut (*ring).spaceseq);
+
+            Ok(unit)
+        }
+    }
+}
+
+/// One side of a link. When the last handle to it is dropped the region is
+/// released so that the listener can accept another link.
+struct Side {
+    map:            Arc<Mapping>,
+}
+
+impl Drop for Side {
+    fn drop(&mut self) {
+        // The other side can not go on without this one.
+        unsafe { atomic_xsub(&mut (*self.map.region()).sides, 1) };
+        self.map.close();
+    }
+}
+
+/// A link over the rings of a region.
+struct ShmLink {
+    side:           Arc<Side>,
+    tx:             usize,
+    rx:             usize,
+    timeout:        Option<u64>,
+}
+
+impl Link for ShmLink {
+    fn sendunit(&mut self, unit: &[u8]) -> IoResult<()> {
+        self.side.map.write(self.tx, &[unit])
+    }
+
+    fn sendunitv(&mut self, parts: &[&[u8]]) -> IoResult<()> {
+        self.side.map.write(self.tx, parts)
+    }
+
+    fn recvunit(&mut self, max: u64) -> IoResult<Vec<u8>> {
+        let deadline = self.timeout.map(|ms| get_time() + Duration::milliseconds(ms as i64));
+        self.side.map.read(self.rx, max, deadline)
+    }
+
+    fn settimeout(&mut self, ms: Option<u64>) {
+        self.timeout = ms;
+    }
+
+    fn shutdown(&mut self) {
+        self.side.map.close();
+    }
+
+    fn duplicate(&self) -> Box<Link> {
+        Box::new(ShmLink {
+            side:       self.side.clone(),
+            tx:         self.tx,
+            rx:         self.rx,
+            timeout:    self.timeout,
+        })
+    }
+
+    fn getpeer(&self) -> String {
+        self.side.map.path.clone()
+    }
+}
+
+/// The shared memory transport.
+pub struct ShmTransport {
+    path:           String,
+    capacity:       usize,
+}
+
+impl ShmTransport {
+    pub fn new(path: String) -> ShmTransport {
+        ShmTransport::with_capacity(path, DEFAULT_CAPACITY)
+    }
+
+    /// Use rings holding `capacity` bytes which is rounded up to a power of two.
+    /// A frame larger than the ring can not be sent and tears down the link, so
+    /// set the maximum frame size of the bridge below it.
+    pub fn with_capacity(path: String, capacity: usize) -> ShmTransport {
+        ShmTransport {
+            path:       path,
+            capacity:   capacity.next_power_of_two(),
+        }
+    }
+}
+
+impl Transport for ShmTransport {
+    fn getaddr(&self) -> String {
+        self.path.clone()
+    }
+
+    fn listen(&self) -> IoResult<Box<LinkAcceptor>> {
+        let cpath = CString::from_slice(self.path.as_bytes());
+        let fd = unsafe { sys::open(cpath.as_ptr(), sys::O_RDWR | sys::O_CREAT | sys::O_TRUNC | sys::O_CLOEXEC, 0o600) };
+        if fd < 0 {
+            return Err(sys::lasterror());
+        }
+        let fd = Fd { fd: fd };
+
+        let size = HEADER_BYTES + self.capacity * 2;
+        if unsafe { sys::ftruncate(fd.fd, size as i64) } < 0 {
+            return Err(sys::lasterror());
+        }
+
+        let map = try!(Mapping::map(fd, size, self.path.clone()));
+
+        // The file starts out zeroed, so only the magic is left to tell a
+        // connector that the rest is ready.
+        unsafe {
+            let region = map.region();
+            (*region).capacity = self.capacity as u64;
+            (*region).version = REGION_VERSION;
+            atomic_store_rel(&mut (*region).magic, REGION_MAGIC);
+        }
+
+        Ok(Box::new(ShmLinkAcceptor {
+            map:        Arc::new(map),
+            closed:     Arc::new(AtomicBool::new(false)),
+            path:       Some(self.path.clone()),
+        }))
+    }
+
+    fn connect(&self) -> IoResult<Box<Link>> {
+        let cpath = CString::from_slice(self.path.as_bytes());
+        let fd = unsafe { sys::open(cpath.as_ptr(), sys::O_RDWR | sys::O_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(sys::lasterror());
+        }
+        let fd = Fd { fd: fd };
+
+        let size = unsafe { sys::lseek(fd.fd, 0, sys::SEEK_END) };
+        if size < HEADER_BYTES as i64 {
+            return Err(WaterError::BridgeFailure(format!("{} is not a shared memory region", self.path)));
+        }
+
+        let map = try!(Mapping::map(fd, size as usize, self.path.clone()));
+
+        unsafe {
+            let region = map.region();
+            if atomic_load_acq(&mut (*region).magic) != REGION_MAGIC || (*region).version != REGION_VERSION {
+                return Err(WaterError::BridgeFailure(format!("{} is not a shared memory region", self.path)));
+            }
+
+            if HEADER_BYTES + map.capacity() * 2 != map.size {
+                return Err(WaterError::BridgeFailure(format!("{} has the wrong size", self.path)));
+            }
+
+            if atomic_cxchg(&mut (*region).state, STATE_WAITING, STATE_CONNECTED) != STATE_WAITING {
+                return Err(WaterError::BridgeFailure(format!("{} is not accepting a link", self.path)));
+            }
+
+            atomic_xadd(&mut (*region).sides, 1);
+        }
+
+        let map = Arc::new(map);
+        map.setstate(STATE_CONNECTED);
+
+        Ok(Box::new(ShmLink {
+            side:       Arc::new(Side { map: map }),
+            tx:         1,
+            rx:         0,
+            timeout:    None,
+        }))
+    }
+}
+
+struct ShmLinkAcceptor {
+    map:            Arc<Mapping>,
+    closed:         Arc<AtomicBool>,
+    /// The file to remove when closed.
+    path:           Option<String>,
+}
+
+impl LinkAcceptor for ShmLinkAcceptor {
+    fn accept(&mut self) -> IoResult<Box<Link>> {
+        // Wait until the previous link, if any, has been let go by both sides.
+        loop {
+            if self.closed.load(Ordering::SeqCst) {
+                return Err(WaterError::BridgeFailure(String::from_str("acceptor is closed")));
+            }
+
+            let state = self.map.state();
+
+            if (state == STATE_IDLE || state == STATE_CLOSED) && self.map.sides() == 0 {
+                self.map.reset();
+                self.map.setstate(STATE_WAITING);
+                break;
+            }
+
+            self.map.waitstate(state, ACCEPT_POLL_MS);
+        }
+
+        // Wait for a connector to take the region.
+        loop {
+            if self.closed.load(Ordering::SeqCst) {
+                unsafe { atomic_cxchg(&mut (*self.map.region()).state, STATE_WAITING, STATE_IDLE) };
+                return Err(WaterError::BridgeFailure(String::from_str("acceptor is closed")));
+            }
+
+            if self.map.state() == STATE_CONNECTED {
+                unsafe { atomic_xadd(&mut (*self.map.region()).sides, 1) };
+                return Ok(Box::new(ShmLink {
+                    side:       Arc::new(Side { map: self.map.clone() }),
+                    tx:         0,
+                    rx:         1,
+                    timeout:    None,
+                }));
+            }
+
+            self.map.waitstate(STATE_WAITING, ACCEPT_POLL_MS);
+        }
+    }
+
+    fn closeaccept(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.map.setstate(self.map.state());
+
+        match self.path.take() {
+            Some(path) => {
+                let cpath = CString::from_slice(path.as_bytes());
+                unsafe { sys::unlink(cpath.as_ptr()) };
+            },
+            None => { },
+        }
+    }
+
+    fn duplicate(&self) -> Box<LinkAcceptor> {
+        Box::new(ShmLinkAcceptor {
+            map:        self.map.clone(),
+            closed:     self.closed.clone(),
+            path:       self.path.clone(),
+        })
+    }
+}
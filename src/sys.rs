@@ -0,0 +1,206 @@
+//! _(internal)_ The system calls used by the bridges which the standard library
+//! does not provide. Only Linux is supported at the moment.
+
+#![allow(non_camel_case_types)]
+
+use libc::c_int;
+use libc::c_char;
+use libc::c_void;
+use libc::size_t;
+use libc::ssize_t;
+use libc::c_long;
+
+use std::os;
+
+use error::IoResult;
+use error::WaterError;
+
+pub const AF_UNIX: c_int = 1;
+pub const SOCK_STREAM: c_int = 1;
+pub const SOCK_CLOEXEC: c_int = 0o2000000;
+pub const SOL_SOCKET: c_int = 1;
+pub const SO_RCVTIMEO: c_int = 20;
+pub const SHUT_RDWR: c_int = 2;
+
+pub const O_RDWR: c_int = 2;
+pub const O_CREAT: c_int = 0o100;
+pub const O_TRUNC: c_int = 0o1000;
+pub const O_CLOEXEC: c_int = 0o2000000;
+pub const SEEK_END: c_int = 2;
+
+pub const PROT_READ: c_int = 1;
+pub const PROT_WRITE: c_int = 2;
+pub const MAP_SHARED: c_int = 1;
+
+pub const F_GETFL: c_int = 3;
+pub const F_SETFL: c_int = 4;
+pub const O_NONBLOCK: c_int = 0o4000;
+
+pub const EPOLL_CLOEXEC: c_int = 0o2000000;
+pub const EPOLL_CTL_ADD: c_int = 1;
+pub const EPOLL_CTL_DEL: c_int = 2;
+pub const EPOLL_CTL_MOD: c_int = 3;
+pub const EPOLLIN: u32 = 0x001;
+pub const EPOLLOUT: u32 = 0x004;
+pub const EPOLLERR: u32 = 0x008;
+pub const EPOLLHUP: u32 = 0x010;
+pub const EPOLLRDHUP: u32 = 0x2000;
+
+pub const EFD_CLOEXEC: c_int = 0o2000000;
+pub const EFD_NONBLOCK: c_int = 0o4000;
+
+pub const SYS_FUTEX: c_long = 202;
+/// These are not the private variants since the futex may be shared with
+/// another process.
+pub const FUTEX_WAIT: c_int = 0;
+pub const FUTEX_WAKE: c_int = 1;
+
+/// The most parts `writev` takes at once.
+pub const IOV_MAX: usize = 1024;
+
+pub const EINTR: c_int = 4;
+pub const EAGAIN: c_int = 11;
+pub const ETIMEDOUT: c_int = 110;
+
+pub type socklen_t = u32;
+
+#[repr(C)]
+pub struct sockaddr_un {
+    pub sun_family:     u16,
+    pub sun_path:       [u8; 108],
+}
+
+#[repr(C)]
+pub struct iovec {
+    pub iov_base:       *const c_void,
+    pub iov_len:        size_t,
+}
+
+/// The kernel packs this on x86-64, which is the only layout supported here.
+#[repr(C, packed)]
+#[derive(Copy)]
+pub struct epoll_event {
+    pub events:         u32,
+    pub data:           u64,
+}
+
+#[repr(C)]
+pub struct timespec {
+    pub tv_sec:         i64,
+    pub tv_nsec:        i64,
+}
+
+#[repr(C)]
+pub struct timeval {
+    pub tv_sec:         i64,
+    pub tv_usec:        i64,
+}
+
+extern {
+    pub fn socket(domain: c_int, ty: c_int, protocol: c_int) -> c_int;
+    pub fn bind(fd: c_int, addr: *const sockaddr_un, len: socklen_t) -> c_int;
+    pub fn listen(fd: c_int, backlog: c_int) -> c_int;
+    pub fn accept(fd: c_int, addr: *mut sockaddr_un, len: *mut socklen_t) -> c_int;
+    pub fn connect(fd: c_int, addr: *const sockaddr_un, len: socklen_t) -> c_int;
+    pub fn read(fd: c_int, buf: *mut c_void, count: size_t) -> ssize_t;
+    pub fn write(fd: c_int, buf: *const c_void, count: size_t) -> ssize_t;
+    pub fn writev(fd: c_int, iov: *const iovec, iovcnt: c_int) -> ssize_t;
+    pub fn shutdown(fd: c_int, how: c_int) -> c_int;
+    pub fn close(fd: c_int) -> c_int;
+    pub fn setsockopt(fd: c_int, level: c_int, name: c_int, val: *const c_void, len: socklen_t) -> c_int;
+    pub fn unlink(path: *const c_char) -> c_int;
+    pub fn open(path: *const c_char, flags: c_int, mode: c_int) -> c_int;
+    pub fn ftruncate(fd: c_int, len: i64) -> c_int;
+    pub fn lseek(fd: c_int, offset: i64, whence: c_int) -> i64;
+    pub fn mmap(addr: *mut c_void, len: size_t, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+    pub fn munmap(addr: *mut c_void, len: size_t) -> c_int;
+    pub fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+    pub fn epoll_create1(flags: c_int) -> c_int;
+    pub fn epoll_ctl(epfd: c_int, op: c_int, fd: c_int, event: *mut epoll_event) -> c_int;
+    pub fn epoll_wait(epfd: c_int, events: *mut epoll_event, maxevents: c_int, timeout: c_int) -> c_int;
+    pub fn eventfd(initval: u32, flags: c_int) -> c_int;
+    pub fn syscall(num: c_long, ...) -> c_long;
+}
+
+/// Block while the word at `addr` holds `val` or until woken. The timeout is
+/// relative and `None` blocks until woken.
+pub unsafe fn futexwait(addr: *mut u32, val: u32, timeout: Option<&timespec>) -> c_int {
+    let ts = match timeout {
+        Some(ts) => ts as *const timespec,
+        None => 0 as *const timespec,
+    };
+    syscall(SYS_FUTEX, addr, FUTEX_WAIT, val, ts, 0us, 0us) as c_int
+}
+
+/// Wake every thread, in any process, blocked on the word at `addr`.
+pub unsafe fn futexwake(addr: *mut u32) -> c_int {
+    syscall(SYS_FUTEX, addr, FUTEX_WAKE, ::std::i32::MAX, 0us, 0us, 0us) as c_int
+}
+
+/// Write all of the parts one after another, with as few system calls as the
+/// kernel allows.
+pub fn writeall(fd: c_int, parts: &[&[u8]]) -> IoResult<()> {
+    let mut parts: Vec<&[u8]> = parts.iter().map(|part| *part).filter(|part| part.len() > 0).collect();
+    let mut first = 0us;
+
+    while first < parts.len() {
+        let iov: Vec<iovec> = parts.slice_from(first).iter().take(IOV_MAX).map(|part| iovec {
+            iov_base:   part.as_ptr() as *const c_void,
+            iov_len:    part.len() as size_t,
+        }).collect();
+
+        let put = unsafe { writev(fd, iov.as_ptr(), iov.len() as c_int) };
+
+        if put < 0 {
+            if errno() == EINTR {
+                continue;
+            }
+            return Err(lasterror());
+        }
+
+        // Skip what was written, which may end part way into a part.
+        let mut put = put as usize;
+        while put > 0 {
+            if put >= parts[first].len() {
+                put -= parts[first].len();
+                first += 1;
+            } else {
+                parts[first] = parts[first].slice_from(put);
+                put = 0;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Make reads and writes on the descriptor fail with `EAGAIN` instead of
+/// blocking.
+pub fn setnonblocking(fd: c_int) -> IoResult<()> {
+    let flags = unsafe { fcntl(fd, F_GETFL) };
+    if flags < 0 || unsafe { fcntl(fd, F_SETFL, flags | O_NONBLOCK) } < 0 {
+        return Err(lasterror());
+    }
+    Ok(())
+}
+
+/// Return the error number of the last system call.
+pub fn errno() -> c_int {
+    os::errno() as c_int
+}
+
+/// Return the last system call error as a bridge failure.
+pub fn lasterror() -> WaterError {
+    WaterError::BridgeFailure(os::last_os_error())
+}
+
+/// A file descriptor which is closed when dropped.
+pub struct Fd {
+    pub fd:             c_int,
+}
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        unsafe { close(self.fd) };
+    }
+}
@@ -0,0 +1,415 @@
+//! This implements the TLS transport for bridges. It is TCP with everything
+//! encrypted, and the remote is verified by a certificate authority or by the
+//! fingerprint of its certificate.
+//!
+//! A certificate can also be tied to the net IDs it may use, so that a remote
+//! has to prove which net it is before its handshake is believed.
+//!
+//! The TLS handshake is done by `Link::establish` on the thread that does the
+//! bridge handshake, so a slow remote can not hold up accepting others.
+//!
+//! _OpenSSL does not let one thread read while another writes, so the link
+//! takes turns. The RX thread only holds the stream for `POLL_MS` at a time
+//! while waiting for a unit, which gives the TX thread a chance to send._
+
+use std::io::{TcpListener, TcpStream, Listener, Acceptor, IoErrorKind};
+use std::io::net::tcp::TcpAcceptor;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use openssl::ssl::SslContext;
+use openssl::ssl::SslMethod;
+use openssl::ssl::SslStream;
+use openssl::ssl::SslVerifyMode;
+use openssl::ssl::error::SslError;
+use openssl::x509::X509FileType;
+use openssl::x509::X509StoreContext;
+use openssl::crypto::hash::HashType;
+use openssl::nid::Nid;
+
+use time::get_time;
+use time::Timespec;
+use Duration;
+
+use net::ID;
+use error::IoResult;
+use error::WaterError;
+use bridge::BridgeListener;
+use bridge::BridgeConnector;
+use bridge::Transport;
+use bridge::LinkAcceptor;
+use bridge::link::Link;
+
+/// A bridge listener using TLS.
+pub type TlsBridgeListener = BridgeListener;
+/// A bridge connector using TLS.
+pub type TlsBridgeConnector = BridgeConnector;
+
+/// How long the RX thread holds the stream while waiting for a unit.
+const POLL_MS: u64 = 20;
+/// How long the remote has to finish the TLS handshake in milliseconds.
+const HANDSHAKE_MS: u64 = 10000;
+
+/// The certificates and what is accepted from the remote.
+#[derive(Clone)]
+pub struct TlsConfig {
+    /// The certificate presented to the remote in PEM format. A listener must
+    /// have one and a connector only needs one for mutual authentication.
+    pub cert:           Option<Path>,
+    /// The private key of the certificate in PEM format.
+    pub key:            Option<Path>,
+    /// The certificate authorities in PEM format that the certificate of the
+    /// remote has to be signed by.
+    pub ca:             Option<Path>,
+    /// The SHA-256 fingerprints of the certificates accepted from the remote.
+    /// If any are given the certificate of the remote has to be one of them,
+    /// which works with self-signed certificates.
+    pub pins:           Vec<Vec<u8>>,
+    /// Make the remote present a certificate. A connector always does if `ca`
+    /// or `pins` are set.
+    pub requirepeer:    bool,
+    /// The net ID each certificate common name may use in its handshake. If
+    /// any are given the remote has to present one of them.
+    pub identities:     HashMap<String, ID>,
+}
+
+impl TlsConfig {
+    /// Accept any remote, and present no certificate.
+    pub fn new() -> TlsConfig {
+        TlsConfig {
+            cert:           Option::None,
+            key:            Option::None,
+            ca:             Option::None,
+            pins:           Vec::new(),
+            requirepeer:    false,
+            identities:     HashMap::new(),
+        }
+    }
+
+    /// Present the certificate with its private key.
+    pub fn with_cert(cert: Path, key: Path) -> TlsConfig {
+        let mut config = TlsConfig::new();
+        config.cert = Option::Some(cert);
+        config.key = Option::Some(key);
+        config
+    }
+}
+
+fn sslerror(err: SslError) -> WaterError {
+    WaterError::BridgeFailure(format!("tls: {:?}", err))
+}
+
+fn check(err: Option<SslError>) -> IoResult<()> {
+    match err {
+        Some(err) => Err(sslerror(err)),
+        None => Ok(()),
+    }
+}
+
+/// The certificate of the remote is checked after the handshake, against the
+/// pins, so OpenSSL is told to take anything when there is no authority.
+fn acceptany(_preverify: bool, _ctx: &X509StoreContext) -> bool {
+    true
+}
+
+/// The TLS transport. The address has the format "<host/ip>:<port>".
+pub struct TlsTransport {
+    addr:           String,
+    config:         TlsConfig,
+}
+
+impl TlsTransport {
+    pub fn new(addr: String, config: TlsConfig) -> TlsTransport {
+        TlsTransport {
+            addr:       addr,
+            config:     config,
+        }
+    }
+
+    fn context(&self) -> IoResult<SslContext> {
+        let mut ctx = try!(SslContext::new(SslMethod::Tlsv1).map_err(sslerror));
+
+        match (&self.config.cert, &self.config.key) {
+            (&Some(ref cert), &Some(ref key)) => {
+                try!(check(ctx.set_certificate_file(cert, X509FileType::PEM)));
+                try!(check(ctx.set_private_key_file(key, X509FileType::PEM)));
+            },
+            _ => { },
+        }
+
+        match self.config.ca {
+            Some(ref ca) => {
+                try!(check(ctx.set_CA_file(ca)));
+                ctx.set_verify(SslVerifyMode::SslVerifyPeer, Option::None);
+            },
+            None => {
+                // Still ask for the certificate so that it can be pinned.
+                ctx.set_verify(SslVerifyMode::SslVerifyPeer, Option::Some(acceptany as fn(bool, &X509StoreContext) -> bool));
+            },
+        }
+
+        Ok(ctx)
+    }
+}
+
+impl Transport for TlsTransport {
+    fn getaddr(&self) -> String {
+        self.addr.clone()
+    }
+
+    fn listen(&self) -> IoResult<Box<LinkAcceptor>> {
+        if self.config.cert.is_none() || self.config.key.is_none() {
+            return Err(WaterError::BridgeFailure(String::from_str("tls: a listener needs a certificate")));
+        }
+
+        let ctx = try!(self.context());
+        let listener = try!(TcpListener::bind(self.addr.as_slice()));
+        let acceptor = try!(listener.listen());
+        Ok(Box::new(TlsLinkAcceptor {
+            acceptor:   acceptor,
+            ctx:        Arc::new(ctx),
+            config:     Arc::new(self.config.clone()),
+        }))
+    }
+
+    fn connect(&self) -> IoResult<Box<Link>> {
+        let ctx = try!(self.context());
+        let tcp = try!(TcpStream::connect(self.addr.as_slice()));
+        let required = self.config.requirepeer || self.config.ca.is_some() || self.config.pins.len() > 0;
+        Ok(Box::new(TlsLink::new(tcp, Arc::new(ctx), false, required, Arc::new(self.config.clone()))))
+    }
+}
+
+struct TlsLinkAcceptor {
+    acceptor:       TcpAcceptor,
+    ctx:            Arc<SslContext>,
+    config:         Arc<TlsConfig>,
+}
+
+impl LinkAcceptor for TlsLinkAcceptor {
+    fn accept(&mut self) -> IoResult<Box<Link>> {
+        let tcp = try!(self.acceptor.accept());
+        Ok(Box::new(TlsLink::new(tcp, self.ctx.clone(), true, self.config.requirepeer, self.config.clone())))
+    }
+
+    fn closeaccept(&mut self) {
+        self.acceptor.close_accept();
+    }
+
+    fn duplicate(&self) -> Box<LinkAcceptor> {
+        Box::new(TlsLinkAcceptor {
+            acceptor:   self.acceptor.clone(),
+            ctx:        self.ctx.clone(),
+            config:     self.config.clone(),
+        })
+    }
+}
+
+/// A link over a TLS stream.
+pub struct TlsLink {
+    /// Empty until the TLS handshake is done.
+    ssl:            Arc<Mutex<Option<SslStream<TcpStream>>>>,
+    /// Used to close the stream without waiting for the lock.
+    tcp:            TcpStream,
+    ctx:            Arc<SslContext>,
+    server:         bool,
+    /// The remote has to present a certificate.
+    required:       bool,
+    peer:           String,
+    /// The common name of the certificate of the remote.
+    identity:       Option<String>,
+    config:         Arc<TlsConfig>,
+    timeout:        Option<u64>,
+}
+
+impl TlsLink {
+    fn new(mut tcp: TcpStream, ctx: Arc<SslContext>, server: bool, required: bool, config: Arc<TlsConfig>) -> TlsLink {
+        let peer = match tcp.peer_name() {
+            Ok(addr) => format!("{}", addr),
+            Err(_) => String::new(),
+        };
+
+        TlsLink {
+            ssl:        Arc::new(Mutex::new(Option::None)),
+            tcp:        tcp,
+            ctx:        ctx,
+            server:     server,
+            required:   required,
+            peer:       peer,
+            identity:   Option::None,
+            config:     config,
+            timeout:    Option::None,
+        }
+    }
+
+    /// Check the certificate of the remote against the pins and return its
+    /// common name.
+    fn verify(ssl: &SslStream<TcpStream>, required: bool, config: &TlsConfig) -> IoResult<Option<String>> {
+        let cert = match ssl.get_peer_certificate() {
+            Some(cert) => cert,
+            None if required => {
+                return Err(WaterError::Protocol(String::from_str("tls: remote presented no certificate")));
+            },
+            None => return Ok(Option::None),
+        };
+
+        if config.pins.len() > 0 {
+            let fingerprint = match cert.fingerprint(HashType::SHA256) {
+                Some(fingerprint) => fingerprint,
+                None => return Err(WaterError::Protocol(String::from_str("tls: certificate has no fingerprint"))),
+            };
+
+            if !config.pins.iter().any(|pin| *pin == fingerprint) {
+                return Err(WaterError::Protocol(String::from_str("tls: certificate of remote is not pinned")));
+            }
+        }
+
+        Ok(cert.subject_name().text_by_nid(Nid::CN).map(|cn| cn.to_string()))
+    }
+
+    /// Read exactly `size` bytes. Only once nothing of it has been read is the
+    /// stream let go between polls, and only then can the deadline end it.
+    fn readexact(&mut self, size: usize, deadline: Option<Timespec>) -> IoResult<Vec<u8>> {
+        let mut buf: Vec<u8> = Vec::with_capacity(size);
+        unsafe { buf.set_len(size) };
+        let mut got = 0us;
+
+        while got < size {
+            let mut lock = self.ssl.lock().unwrap();
+            let ssl = match *lock {
+                Some(ref mut ssl) => ssl,
+                None => return Err(WaterError::BridgeFailure(String::from_str("tls: link is not established"))),
+            };
+
+            ssl.get_mut().set_read_timeout(Some(POLL_MS));
+
+            match ssl.read(buf.slice_from_mut(got)) {
+                Ok(count) => {
+                    got += count;
+                },
+                Err(ref e) if e.kind == IoErrorKind::TimedOut => {
+                    if got == 0 {
+                        match deadline {
+                            Some(deadline) if get_time() >= deadline => return Err(WaterError::TimedOut),
+                            _ => { },
+                        }
+                    }
+                },
+                Err(ref e) if e.kind == IoErrorKind::EndOfFile => {
+                    return Err(WaterError::NetDisconnected);
+                },
+                Err(e) => {
+                    return Err(WaterError::BridgeFailure(format!("{}", e)));
+                },
+            }
+        }
+
+        Ok(buf)
+    }
+}
+
+impl Link for TlsLink {
+    fn establish(&mut self) -> IoResult<()> {
+        // A remote that never finishes the handshake must not hold the link
+        // forever.
+        let mut inner = self.tcp.clone();
+        inner.set_read_timeout(Some(HANDSHAKE_MS));
+
+        let result = if self.server {
+            SslStream::new_server(&*self.ctx, inner)
+        } else {
+            SslStream::new(&*self.ctx, inner)
+        };
+
+        let mut ssl = try!(result.map_err(sslerror));
+        self.identity = try!(TlsLink::verify(&ssl, self.required, &*self.config));
+        ssl.get_mut().set_read_timeout(None);
+
+        *self.ssl.lock().unwrap() = Option::Some(ssl);
+        Ok(())
+    }
+
+    fn sendunit(&mut self, unit: &[u8]) -> IoResult<()> {
+        self.sendunitv(&[unit])
+    }
+
+    fn sendunitv(&mut self, parts: &[&[u8]]) -> IoResult<()> {
+        self.sendbatch(&[parts.to_vec()])
+    }
+
+    fn sendbatch(&mut self, units: &[Vec<&[u8]>]) -> IoResult<()> {
+        // Joined so the batch is sealed into as few records as it can be
+        // rather than one for each part.
+        let mut buf: Vec<u8> = Vec::new();
+        for parts in units.iter() {
+            let size = parts.iter().fold(0us, |size, part| size + part.len());
+            try!(buf.write_be_u64(size as u64));
+            for part in parts.iter() {
+                buf.push_all(*part);
+            }
+        }
+
+        let mut lock = self.ssl.lock().unwrap();
+        let ssl = match *lock {
+            Some(ref mut ssl) => ssl,
+            None => return Err(WaterError::BridgeFailure(String::from_str("tls: link is not established"))),
+        };
+
+        try!(ssl.write(buf.as_slice()));
+        Ok(try!(ssl.flush()))
+    }
+
+    fn recvunit(&mut self, max: u64) -> IoResult<Vec<u8>> {
+        let deadline = self.timeout.map(|ms| get_time() + Duration::milliseconds(ms as i64));
+
+        let header = try!(self.readexact(8, deadline));
+        let size = header.iter().fold(0u64, |size, byte| (size << 8) | *byte as u64);
+
+        // Refuse before anything is allocated for it.
+        if size > max {
+            return Err(WaterError::Protocol(format!("unit size {} exceeds maximum {}", size, max)));
+        }
+
+        // The remote is in the middle of sending it so do not give up on it.
+        self.readexact(size as usize, Option::None)
+    }
+
+    fn settimeout(&mut self, ms: Option<u64>) {
+        self.timeout = ms;
+    }
+
+    fn shutdown(&mut self) {
+        self.tcp.close_read();
+        self.tcp.close_write();
+    }
+
+    fn duplicate(&self) -> Box<Link> {
+        Box::new(TlsLink {
+            ssl:        self.ssl.clone(),
+            tcp:        self.tcp.clone(),
+            ctx:        self.ctx.clone(),
+            server:     self.server,
+            required:   self.required,
+            peer:       self.peer.clone(),
+            identity:   self.identity.clone(),
+            config:     self.config.clone(),
+            timeout:    Option::None,
+        })
+    }
+
+    fn getpeer(&self) -> String {
+        self.peer.clone()
+    }
+
+    fn allows(&self, sid: ID) -> bool {
+        if self.config.identities.len() == 0 {
+            return true;
+        }
+
+        match self.identity {
+            Some(ref identity) => self.config.identities.get(identity) == Some(&sid),
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! This implements the Unix domain socket transport for bridges. It is for nets
+//! in different processes on the same machine and avoids the overhead and port
+//! management of TCP over the loopback.
+//!
+//! The address is the path of the socket file. If it starts with `@` the rest
+//! is a name in the abstract namespace instead, which has no file and goes away
+//! with the last socket using it. The socket file of a listener is removed
+//! when the listener is terminated.
+
+use std::io;
+use std::io::IoErrorKind;
+use std::io::standard_error;
+use std::mem::zeroed;
+use std::mem::size_of;
+use std::sync::Arc;
+use std::ffi::CString;
+
+use libc::c_int;
+use libc::c_void;
+use libc::size_t;
+
+use error::IoResult;
+use error::WaterError;
+use bridge::BridgeListener;
+use bridge::BridgeConnector;
+use bridge::Transport;
+use bridge::LinkAcceptor;
+use bridge::link::Link;
+use bridge::link::Stream;
+use bridge::link::StreamLink;
+use sys;
+use sys::Fd;
+
+/// A bridge listener using a Unix domain socket.
+pub type UnixBridgeListener = BridgeListener;
+/// A bridge connector using a Unix domain socket.
+pub type UnixBridgeConnector = BridgeConnector;
+
+/// Build the socket address for the address string.
+fn sockaddr(addr: &str) -> IoResult<(sys::sockaddr_un, sys::socklen_t)> {
+    let mut sa: sys::sockaddr_un = unsafe { zeroed() };
+    sa.sun_family = sys::AF_UNIX as u16;
+
+    let bytes = addr.as_bytes();
+    let abstractns = bytes.len() > 0 && bytes[0] == b'@';
+
+    // A path needs room for its terminating zero. An abstract name starts
+    // with a zero instead of the `@`.
+    if bytes.len() == 0 || bytes.len() >= sa.sun_path.len() {
+        return Err(WaterError::BridgeFailure(format!("unix socket address {} is invalid", addr)));
+    }
+
+    for (ndx, byte) in bytes.iter().enumerate() {
+        sa.sun_path[ndx] = *byte;
+    }
+
+    if abstractns {
+        sa.sun_path[0] = 0;
+        Ok((sa, (2 + bytes.len()) as sys::socklen_t))
+    } else {
+        Ok((sa, (2 + bytes.len() + 1) as sys::socklen_t))
+    }
+}
+
+fn newsocket() -> IoResult<Fd> {
+    let fd = unsafe { sys::socket(sys::AF_UNIX, sys::SOCK_STREAM | sys::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(sys::lasterror());
+    }
+    Ok(Fd { fd: fd })
+}
+
+/// A connected Unix domain stream socket.
+pub struct UnixSocket {
+    fd:             Arc<Fd>,
+}
+
+impl Clone for UnixSocket {
+    /// The clone uses the same socket.
+    fn clone(&self) -> UnixSocket {
+        UnixSocket {
+            fd:     self.fd.clone(),
+        }
+    }
+}
+
+impl UnixSocket {
+    /// Connect to the address.
+    pub fn connect(addr: &str) -> IoResult<UnixSocket> {
+        let (sa, len) = try!(sockaddr(addr));
+        let fd = try!(newsocket());
+
+        if unsafe { sys::connect(fd.fd, &sa, len) } < 0 {
+            return Err(sys::lasterror());
+        }
+
+        Ok(UnixSocket { fd: Arc::new(fd) })
+    }
+}
+
+impl Reader for UnixSocket {
+    fn read(&mut self, buf: &mut [u8]) -> io::IoResult<usize> {
+        loop {
+            let got = unsafe {
+                sys::read(self.fd.fd, buf.as_mut_ptr() as *mut c_void, buf.len() as size_t)
+            };
+
+            if got > 0 {
+                return Ok(got as usize);
+            }
+
+            if got == 0 {
+                return Err(standard_error(IoErrorKind::EndOfFile));
+            }
+
+            match sys::errno() {
+                sys::EINTR => continue,
+                sys::EAGAIN => return Err(standard_error(IoErrorKind::TimedOut)),
+                _ => return Err(io::IoError::last_error()),
+            }
+        }
+    }
+}
+
+impl Writer for UnixSocket {
+    fn write(&mut self, buf: &[u8]) -> io::IoResult<()> {
+        let mut done = 0us;
+
+        while done < buf.len() {
+            let put = unsafe {
+                sys::write(
+                    self.fd.fd, buf.slice_from(done).as_ptr() as *const c_void,
+                    (buf.len() - done) as size_t
+                )
+            };
+
+            if put < 0 {
+                if sys::errno() == sys::EINTR {
+                    continue;
+                }
+                return Err(io::IoError::last_error());
+            }
+
+            done += put as usize;
+        }
+
+        Ok(())
+    }
+}
+
+impl Stream for UnixSocket {
+    fn closestream(&mut self) {
+        unsafe { sys::shutdown(self.fd.fd, sys::SHUT_RDWR) };
+    }
+
+    fn setreadtimeout(&mut self, ms: Option<u64>) {
+        // Zero means no timeout.
+        let ms = ms.unwrap_or(0);
+        let tv = sys::timeval {
+            tv_sec:     (ms / 1000) as i64,
+            tv_usec:    ((ms % 1000) * 1000) as i64,
+        };
+        unsafe {
+            sys::setsockopt(
+                self.fd.fd, sys::SOL_SOCKET, sys::SO_RCVTIMEO,
+                &tv as *const sys::timeval as *const c_void,
+                size_of::<sys::timeval>() as sys::socklen_t
+            );
+        }
+    }
+
+    fn peername(&mut self) -> String {
+        // The connecting side of a Unix domain socket is almost never bound
+        // to an address so there is nothing useful to give.
+        String::new()
+    }
+
+    fn getfd(&self) -> Option<c_int> {
+        Some(self.fd.fd)
+    }
+
+    fn writev(&mut self, parts: &[&[u8]]) -> IoResult<()> {
+        sys::writeall(self.fd.fd, parts)
+    }
+}
+
+/// The Unix domain socket transport.
+pub struct UnixTransport {
+    addr:           String,
+}
+
+impl UnixTransport {
+    pub fn new(addr: String) -> UnixTransport {
+        UnixTransport {
+            addr:       addr,
+        }
+    }
+}
+
+impl Transport for UnixTransport {
+    fn getaddr(&self) -> String {
+        self.addr.clone()
+    }
+
+    fn listen(&self) -> IoResult<Box<LinkAcceptor>> {
+        let (sa, len) = try!(sockaddr(self.addr.as_slice()));
+        let fd = try!(newsocket());
+
+        if unsafe { sys::bind(fd.fd, &sa, len) } < 0 {
+            return Err(sys::lasterror());
+        }
+
+        if unsafe { sys::listen(fd.fd, 128) } < 0 {
+            return Err(sys::lasterror());
+        }
+
+        let path = if self.addr.as_slice().starts_with("@") {
+            None
+        } else {
+            Some(self.addr.clone())
+        };
+
+        Ok(Box::new(UnixLinkAcceptor { fd: Arc::new(fd), path: path }))
+    }
+
+    fn connect(&self) -> IoResult<Box<Link>> {
+        let socket = try!(UnixSocket::connect(self.addr.as_slice()));
+        Ok(Box::new(StreamLink::new(socket)))
+    }
+}
+
+struct UnixLinkAcceptor {
+    fd:             Arc<Fd>,
+    /// The socket file to remove when closed.
+    path:           Option<String>,
+}
+
+impl LinkAcceptor for UnixLinkAcceptor {
+    fn accept(&mut self) -> IoResult<Box<Link>> {
+        loop {
+            let fd = unsafe { sys::accept(self.fd.fd, 0 as *mut sys::sockaddr_un, 0 as *mut sys::socklen_t) };
+
+            if fd >= 0 {
+                let socket = UnixSocket { fd: Arc::new(Fd { fd: fd }) };
+                return Ok(Box::new(StreamLink::new(socket)));
+            }
+
+            if sys::errno() != sys::EINTR {
+                return Err(sys::lasterror());
+            }
+        }
+    }
+
+    fn closeaccept(&mut self) {
+        // This wakes any thread blocked in accept.
+        unsafe { sys::shutdown(self.fd.fd, sys::SHUT_RDWR) };
+
+        match self.path.take() {
+            Some(path) => {
+                let cpath = CString::from_slice(path.as_bytes());
+                unsafe { sys::unlink(cpath.as_ptr()) };
+            },
+            None => { },
+        }
+    }
+
+    fn duplicate(&self) -> Box<LinkAcceptor> {
+        Box::new(UnixLinkAcceptor { fd: self.fd.clone(), path: self.path.clone() })
+    }
+}
@@ -0,0 +1,86 @@
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+
+use water::Net;
+use water::Message;
+use water::Duration;
+use water::WaterError;
+
+use std::io::timer::sleep;
+
+fn key(s: &str) -> Option<Vec<u8>> {
+    Some(s.as_bytes().to_vec())
+}
+
+// The listener has its key before any connector exists. A connector that tries
+// before its own key is set only fails and tries again.
+
+#[test]
+fn authpsk() {
+    let net1 = Net::new(5001);
+    let net2 = Net::new(5002);
+    let ep1 = net1.new_endpoint();
+
+    let mut listener = net1.tcplisten(String::from_str("localhost:34233"));
+    listener.setpsk(key("swordfish"));
+    let mut connector = net2.tcpconnect(String::from_str("localhost:34233"));
+    connector.setpsk(key("swordfish"));
+
+    while listener.getnegcount() < 1 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    let mut msg = Message::new_raw(4);
+    msg.dstsid = 5001;
+    net2.new_endpoint().send(msg);
+    assert!(ep1.recvorblock(Duration::seconds(5)).is_ok());
+
+    listener.terminate();
+    connector.terminate();
+}
+
+#[test]
+fn authwrongkey() {
+    let net1 = Net::new(5011);
+    let net2 = Net::new(5012);
+
+    let mut listener = net1.tcplisten(String::from_str("localhost:34234"));
+    listener.setpsk(key("marlin"));
+    let mut connector = net2.tcpconnect(String::from_str("localhost:34234"));
+    connector.setpsk(key("swordfish"));
+
+    while listener.getauthfailures() < 1 || connector.getauthfailures() < 1 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    assert!(listener.getlasterror() == Some(WaterError::AuthFailed));
+    assert!(listener.getnegcount() == 0);
+    assert!(!connector.connected());
+    assert!(net1.stats().endpoints == 0);
+
+    listener.terminate();
+    connector.terminate();
+}
+
+#[test]
+fn authmissing() {
+    let net1 = Net::new(5021);
+    let net2 = Net::new(5022);
+
+    // Without a key it is not let in, rather than being let in without one.
+    let mut listener = net1.tcplisten(String::from_str("localhost:34235"));
+    listener.setpsk(key("swordfish"));
+    let mut connector = net2.tcpconnect(String::from_str("localhost:34235"));
+
+    while listener.getauthfailures() < 1 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    assert!(listener.getnegcount() == 0);
+    assert!(!connector.connected());
+
+    listener.terminate();
+    connector.terminate();
+}
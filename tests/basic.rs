@@ -90,7 +90,7 @@ fn funnyworker(mut net: Net, dbgid: uint) {
             }
 
             //println!("thread[{}] reading struct", dbgid);
-            let safestruct: SafeStructure = result.ok().get_raw().readstruct(0);
+            let safestruct: SafeStructure = result.unwrap().get_raw().readstruct(0);
             if safestruct.c != 0x10 {
                 if got[safestruct.a as uint][safestruct.b as uint] != 0u8 {
                     panic!("got {} twice from thread {}", safestruct.b, safestruct.a);
@@ -208,7 +208,7 @@ fn _basicio() {
             panic!("timed out waiting for messages likely..");
         }
 
-        let raw = result.ok().get_raw();
+        let raw = result.unwrap().get_raw();
         let safestruct: SafeStructure = raw.readstruct(0);
 
         //println!("main: got message {}:{}:{}", safestruct.a, safestruct.b, safestruct.c);
@@ -0,0 +1,198 @@
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+
+use water::Net;
+use water::Endpoint;
+use water::Message;
+use water::Duration;
+use water::SourcePolicy;
+
+use std::io::timer::sleep;
+
+/// Wait until every net has a route to every other net.
+fn waitroutes(nets: &[Net]) {
+    for net in nets.iter() {
+        for other in nets.iter() {
+            if net.getserveraddr() == other.getserveraddr() {
+                continue;
+            }
+            while net.getnexthop(other.getserveraddr()).is_none() {
+                sleep(Duration::milliseconds(10));
+            }
+        }
+    }
+}
+
+/// Broadcast from the first endpoint and check every endpoint gets it once.
+fn broadcastonce(eps: &[Endpoint]) {
+    let mut msg = Message::new_raw(4);
+    msg.dstsid = 0;
+    msg.dsteid = 0;
+    msg.get_rawmutref().as_mutslice()[0] = 0x42;
+    eps[0].send(msg);
+
+    for ep in eps.iter().skip(1) {
+        let msg = ep.recvorblock(Duration::seconds(5)).unwrap();
+        assert!(msg.get_raw().as_slice()[0] == 0x42);
+    }
+
+    // Give any copy going around a loop time to arrive.
+    sleep(Duration::milliseconds(300));
+
+    for ep in eps.iter() {
+        assert!(ep.recvorblock(Duration::milliseconds(10)).is_err());
+    }
+}
+
+#[test]
+fn broadcasttriangle() {
+    let nets = vec![Net::new(2001), Net::new(2002), Net::new(2003)];
+    let eps: Vec<Endpoint> = nets.iter().map(|net| net.new_endpoint()).collect();
+
+    let mut listener1 = nets[0].tcplisten(String::from_str("localhost:34222"));
+    let mut listener2 = nets[1].tcplisten(String::from_str("localhost:34223"));
+    let mut connector1 = nets[1].tcpconnect(String::from_str("localhost:34222"));
+    let mut connector2 = nets[2].tcpconnect(String::from_str("localhost:34222"));
+    let mut connector3 = nets[2].tcpconnect(String::from_str("localhost:34223"));
+
+    waitroutes(nets.as_slice());
+    while listener1.getnegcount() < 2 || listener2.getnegcount() < 1 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    broadcastonce(eps.as_slice());
+
+    // A copy over a longer path is not a spoof.
+    assert!(listener1.getspoofed() == 0 && listener2.getspoofed() == 0);
+    assert!(connector1.getspoofed() == 0 && connector2.getspoofed() == 0 && connector3.getspoofed() == 0);
+
+    listener1.terminate();
+    listener2.terminate();
+    connector1.terminate();
+    connector2.terminate();
+    connector3.terminate();
+}
+
+#[test]
+fn broadcastring() {
+    let nets = vec![Net::new(2011), Net::new(2012), Net::new(2013), Net::new(2014)];
+    let eps: Vec<Endpoint> = nets.iter().map(|net| net.new_endpoint()).collect();
+    let ports = ["localhost:34224", "localhost:34225", "localhost:34226", "localhost:34227"];
+
+    let mut listeners = Vec::new();
+    let mut connectors = Vec::new();
+    for n in range(0us, 4us) {
+        listeners.push(nets[n].tcplisten(String::from_str(ports[n])));
+        connectors.push(nets[(n + 1) % 4].tcpconnect(String::from_str(ports[n])));
+    }
+
+    waitroutes(nets.as_slice());
+    while listeners.iter().any(|listener| listener.getnegcount() < 1) {
+        sleep(Duration::milliseconds(10));
+    }
+
+    broadcastonce(eps.as_slice());
+
+    // The same from the other side of the ring.
+    let mut reversed: Vec<Endpoint> = eps.iter().map(|ep| ep.clone()).collect();
+    reversed.reverse();
+    broadcastonce(reversed.as_slice());
+
+    assert!(listeners.iter().all(|listener| listener.getspoofed() == 0));
+    assert!(connectors.iter().all(|connector| connector.getspoofed() == 0));
+
+    for listener in listeners.iter_mut() {
+        listener.terminate();
+    }
+    for connector in connectors.iter_mut() {
+        connector.terminate();
+    }
+}
+
+#[test]
+fn broadcastnoecho() {
+    let net1 = Net::new(2021);
+    let net2 = Net::new(2022);
+    let ep1 = net1.new_endpoint();
+    let ep2 = net2.new_endpoint();
+
+    let mut listener = net1.tcplisten(String::from_str("localhost:34245"));
+    let mut connector = net2.tcpconnect(String::from_str("localhost:34245"));
+
+    waitroutes(&[net1.clone(), net2.clone()]);
+    while listener.getnegcount() < 1 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    let before = listener.getconnections()[0].clone();
+
+    let mut msg = Message::new_raw(4);
+    msg.dstsid = 0;
+    ep2.send(msg);
+    assert!(ep1.recvorblock(Duration::seconds(5)).is_ok());
+    sleep(Duration::milliseconds(300));
+
+    // It is not sent back over the link it came in on.
+    let conn = listener.getconnections()[0].clone();
+    assert!(conn.msgsin == before.msgsin + 1);
+    assert!(conn.msgsout == before.msgsout);
+
+    listener.terminate();
+    connector.terminate();
+}
+
+#[test]
+fn broadcastrewrite() {
+    let nets = vec![Net::new(2031), Net::new(2032), Net::new(2033)];
+    let eps: Vec<Endpoint> = nets.iter().map(|net| net.new_endpoint()).collect();
+    let ports = ["localhost:34246", "localhost:34247", "localhost:34248"];
+
+    // Every link has a listener at one end that rewrites what it thinks is
+    // spoofed.
+    let mut listeners = Vec::new();
+    for n in range(0us, 3us) {
+        let mut listener = nets[n].tcplisten(String::from_str(ports[n]));
+        listener.setsourcepolicy(SourcePolicy::Rewrite);
+        listeners.push(listener);
+    }
+    let mut connectors = Vec::new();
+    for n in range(0us, 3us) {
+        connectors.push(nets[(n + 1) % 3].tcpconnect(String::from_str(ports[n])));
+    }
+
+    waitroutes(nets.as_slice());
+    while listeners.iter().any(|listener| listener.getnegcount() < 1) {
+        sleep(Duration::milliseconds(10));
+    }
+
+    for n in range(0us, 3us) {
+        let mut msg = Message::new_raw(4);
+        msg.dstsid = 0;
+        eps[n].send(msg);
+
+        // Nobody gets a copy with the source rewritten, not even the sender.
+        for m in range(0us, 3us) {
+            if m != n {
+                let msg = eps[m].recvorblock(Duration::seconds(5)).unwrap();
+                assert!(msg.srcsid == nets[n].getserveraddr());
+                assert!(msg.srceid == eps[n].geteid());
+            }
+        }
+        sleep(Duration::milliseconds(300));
+        for ep in eps.iter() {
+            assert!(ep.recvorblock(Duration::milliseconds(10)).is_err());
+        }
+    }
+
+    assert!(listeners.iter().all(|listener| listener.getspoofed() == 0));
+    assert!(connectors.iter().all(|connector| connector.getspoofed() == 0));
+
+    for listener in listeners.iter_mut() {
+        listener.terminate();
+    }
+    for connector in connectors.iter_mut() {
+        connector.terminate();
+    }
+}
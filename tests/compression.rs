@@ -0,0 +1,70 @@
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+
+use water::Net;
+use water::Message;
+use water::Duration;
+
+use std::io::timer::sleep;
+
+/// A payload that compresses well, like repetitive telemetry.
+fn telemetry(size: usize) -> Message {
+    let mut msg = Message::new_raw(size);
+    for (ndx, byte) in msg.get_rawmutref().as_mutslice().iter_mut().enumerate() {
+        *byte = (ndx % 32) as u8;
+    }
+    msg
+}
+
+#[test]
+fn compressionlink() {
+    let net1 = Net::new(6001);
+    let net2 = Net::new(6002);
+    let ep1 = net1.new_endpoint();
+    let ep2 = net2.new_endpoint();
+
+    let mut listener = net1.tcplisten(String::from_str("localhost:34236"));
+    listener.setcompression(Some(1024));
+    let mut connector = net2.tcpconnect(String::from_str("localhost:34236"));
+
+    while listener.getnegcount() < 1 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    // Too small to be compressed.
+    let mut msg = telemetry(512);
+    msg.dstsid = 6002;
+    ep1.send(msg);
+    assert!(ep2.recvorblock(Duration::seconds(5)).unwrap().get_raw().len() == 512);
+
+    let stats = listener.getconnections()[0].compression;
+    assert!(stats.compressedout == 0 && stats.uncompressedout == 0);
+
+    let mut msg = telemetry(65536);
+    msg.dstsid = 6002;
+    ep1.send(msg);
+    let msg = ep2.recvorblock(Duration::seconds(5)).unwrap();
+    let raw = msg.get_raw();
+    assert!(raw.len() == 65536);
+    assert!(raw.as_slice().iter().enumerate().all(|(ndx, byte)| *byte == (ndx % 32) as u8));
+
+    let stats = listener.getconnections()[0].compression;
+    assert!(stats.uncompressedout == 65536);
+    assert!(stats.compressedout > 0 && stats.compressedout < 65536);
+
+    let stats = connector.getcompressionstats().unwrap();
+    assert!(stats.uncompressedin == 65536);
+    assert!(stats.compressedin == listener.getconnections()[0].compression.compressedout);
+
+    // The connector did not ask to compress so it sends as it is.
+    let mut msg = telemetry(65536);
+    msg.dstsid = 6001;
+    ep2.send(msg);
+    assert!(ep1.recvorblock(Duration::seconds(5)).is_ok());
+    assert!(connector.getcompressionstats().unwrap().compressedout == 0);
+
+    listener.terminate();
+    connector.terminate();
+}
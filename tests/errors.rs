@@ -0,0 +1,67 @@
+#![allow(unused_imports)]
+#![allow(dead_code)]
+#![allow(unused_variables)]
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+
+use water::Net;
+use water::Endpoint;
+use water::Message;
+use water::Duration;
+use water::IoResult;
+use water::WaterError;
+
+use std::error::Error;
+
+struct Apple;
+impl Clone for Apple { fn clone(&self) -> Apple { Apple } }
+struct Grape;
+impl Clone for Grape { fn clone(&self) -> Grape { Grape } }
+
+fn getapple(ep: &Endpoint) -> IoResult<Apple> {
+    let msg = try!(ep.recv());
+    let apple = try!(msg.trytypeunwrap::<Apple>());
+    Ok(apple)
+}
+
+fn getappleboxed(ep: &Endpoint) -> Result<Apple, Box<Error>> {
+    let msg = try!(ep.recvorblock(Duration::milliseconds(10)));
+    Ok(try!(msg.trytypeunwrap::<Apple>()))
+}
+
+#[test]
+fn errorcodes() {
+    let net = Net::new(100);
+    let ep1 = net.new_endpoint();
+    let ep2 = net.new_endpoint();
+
+    assert!(ep1.recv().err().unwrap() == WaterError::NoMessages);
+    assert!(ep1.recvorblock(Duration::milliseconds(10)).err().unwrap() == WaterError::TimedOut);
+
+    // The errors propagate with `try!`.
+    assert!(getapple(&ep1).err().unwrap() == WaterError::NoMessages);
+
+    ep2.sendclonetype(Grape);
+    assert!(getapple(&ep1).err().unwrap() == WaterError::WrongType);
+
+    ep2.sendclonetype(Apple);
+    assert!(getapple(&ep1).is_ok());
+}
+
+#[test]
+fn errorboxed() {
+    let net = Net::new(100);
+    let ep1 = net.new_endpoint();
+
+    // A water error can be converted into a boxed standard error.
+    let err = getappleboxed(&ep1).err().unwrap();
+    assert!(err.description() == "timed out");
+}
+
+#[test]
+fn errordisplay() {
+    assert!(format!("{}", WaterError::NoMessages) == "no messages");
+    assert!(format!("{}", WaterError::Protocol(String::from_str("bad magic"))) == "protocol error: bad magic");
+}
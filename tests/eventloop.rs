@@ -0,0 +1,81 @@
+#![cfg(all(target_os = "linux", target_arch = "x86_64"))]
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+
+use water::Net;
+use water::Message;
+use water::Duration;
+use water::get_time;
+
+use std::io::File;
+use std::io::timer::sleep;
+
+const PEERS: usize = 32;
+
+/// The number of threads in this process.
+fn threads() -> usize {
+    let status = File::open(&Path::new("/proc/self/status")).read_to_string().unwrap();
+    let line = status.as_slice().lines().find(|line| line.starts_with("Threads:")).unwrap();
+    line.slice_from(8).trim().parse::<usize>().unwrap()
+}
+
+// This is the only test in the file so nothing else starts or stops threads
+// while they are counted.
+
+#[test]
+fn eventloopmany() {
+    let net = Net::new(7000);
+    let ep = net.new_endpoint();
+    let mut listener = net.tcplisten(String::from_str("localhost:34240"));
+    let base = threads();
+
+    let mut nets: Vec<Net> = Vec::new();
+    let mut eps = Vec::new();
+    let mut connectors = Vec::new();
+    for n in range(0us, PEERS) {
+        let peer = Net::new(7001 + n as u64);
+        eps.push(peer.new_endpoint());
+        connectors.push(peer.tcpconnect(String::from_str("localhost:34240")));
+        nets.push(peer);
+    }
+
+    while listener.getnegcount() < PEERS as u64 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    // Every link of a net is run by its one event loop, so each peer only adds
+    // its connector and its own loop. The handshake threads are gone soon
+    // after the links are up.
+    let deadline = get_time() + Duration::seconds(5);
+    while threads() - base >= 3 * PEERS && get_time() < deadline {
+        sleep(Duration::milliseconds(10));
+    }
+    assert!(threads() - base < 3 * PEERS);
+
+    for n in range(0us, PEERS) {
+        let mut msg = Message::new_raw(8);
+        msg.dstsid = 7000;
+        eps[n].send(msg);
+    }
+
+    for _ in range(0us, PEERS) {
+        assert!(ep.recvorblock(Duration::seconds(5)).unwrap().srcsid > 7000);
+    }
+
+    // A broadcast goes out over every link.
+    let mut msg = Message::new_raw(8);
+    msg.dstsid = 0;
+    ep.send(msg);
+
+    for n in range(0us, PEERS) {
+        assert!(eps[n].recvorblock(Duration::seconds(5)).unwrap().srcsid == 7000);
+    }
+
+    for connector in connectors.iter_mut() {
+        connector.terminate();
+    }
+    listener.terminate();
+    assert!(listener.getclientcount() == 0);
+}
@@ -0,0 +1,117 @@
+#![allow(unused_imports)]
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+
+use water::Net;
+use water::Endpoint;
+use water::Duration;
+use water::BridgeEvent;
+use water::BridgeEventKind;
+use water::BRIDGE_EVENT_GID;
+use water::ReconnectPolicy;
+
+use std::io::TcpStream;
+use std::io::timer::sleep;
+
+fn events(net: &Net) -> Endpoint {
+    let ep = net.new_endpoint();
+    ep.joingroup(BRIDGE_EVENT_GID);
+    ep
+}
+
+fn nextevent(ep: &Endpoint) -> BridgeEvent {
+    ep.recvorblock(Duration::seconds(10)).unwrap().typeunwrap::<BridgeEvent>()
+}
+
+fn connect(addr: &str) -> TcpStream {
+    loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => return stream,
+            Err(_) => sleep(Duration::milliseconds(10)),
+        }
+    }
+}
+
+#[test]
+fn eventsconnect() {
+    let net1 = Net::new(234);
+    let events1 = events(&net1);
+    let net2 = Net::new(875);
+    let events2 = events(&net2);
+    // Only members of the group get the events.
+    let other = net2.new_endpoint();
+
+    let mut listener = net1.tcplisten(String::from_str("localhost:34213"));
+    let mut connector = net2.tcpconnect(String::from_str("localhost:34213"));
+
+    // The connector may have to retry while the listener starts.
+    let mut event = nextevent(&events2);
+    while event.kind == BridgeEventKind::Reconnecting {
+        event = nextevent(&events2);
+    }
+    assert!(event.kind == BridgeEventKind::Connected);
+    assert!(event.sid == 234);
+    assert!(event.addr == String::from_str("localhost:34213"));
+
+    let event = nextevent(&events1);
+    assert!(event.kind == BridgeEventKind::Connected);
+    assert!(event.sid == 875);
+    assert!(listener.getnegcount() == 1);
+
+    let id = listener.getconnections()[0].id;
+    listener.disconnect(id);
+
+    let event = nextevent(&events1);
+    assert!(event.kind == BridgeEventKind::Disconnected);
+    assert!(event.sid == 875);
+
+    let event = nextevent(&events2);
+    assert!(event.kind == BridgeEventKind::Disconnected);
+    assert!(event.sid == 234);
+
+    assert!(other.recvorblock(Duration::milliseconds(100)).is_err());
+
+    listener.terminate();
+    connector.terminate();
+}
+
+#[test]
+fn eventshandshakefailed() {
+    let net = Net::new(234);
+    let events = events(&net);
+    let mut listener = net.tcplisten(String::from_str("localhost:34214"));
+
+    let mut stream = connect("localhost:34214");
+    stream.write_be_u64(18);
+    stream.write(b"GET / HTTP/1.0\r\n\r\n");
+
+    let event = nextevent(&events);
+    assert!(event.kind == BridgeEventKind::HandshakeFailed);
+    assert!(event.error.is_some());
+
+    listener.terminate();
+}
+
+#[test]
+fn eventsreconnecting() {
+    let net = Net::new(875);
+    let events = events(&net);
+
+    let mut policy = ReconnectPolicy::new();
+    policy.initialdelay = Duration::milliseconds(10);
+    policy.maxattempts = Some(2);
+    let mut connector = net.tcpconnectlist(vec![String::from_str("localhost:34215")], policy);
+
+    let event = nextevent(&events);
+    assert!(event.kind == BridgeEventKind::Reconnecting);
+    assert!(event.attempts == 1);
+    assert!(event.error.is_some());
+
+    let event = nextevent(&events);
+    assert!(event.kind == BridgeEventKind::GivenUp);
+    assert!(event.attempts == 2);
+
+    connector.terminate();
+}
@@ -0,0 +1,262 @@
+#![allow(unused_imports)]
+#![allow(dead_code)]
+#![allow(unused_variables)]
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+
+use water::Net;
+use water::Message;
+use water::RawMessage;
+use water::Duration;
+use water::WaterError;
+use water::bridge::frame::Frame;
+use water::bridge::frame::HEADER_SIZE;
+use water::bridge::frame::FRAME_SERIAL;
+use water::bridge::handshake::Handshake;
+use water::bridge::link::readunit;
+use water::bridge::link::writeunit;
+
+use std::io::MemReader;
+use std::io::MemWriter;
+use std::io::TcpStream;
+use std::io::timer::sleep;
+
+/// A small generator so the garbage is the same every run.
+struct XorShift {
+    state:      u64,
+}
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        range(0us, len).map(|_| self.next() as u8).collect()
+    }
+}
+
+/// Encode the frame as it would be on a stream link.
+fn encode(frame: &Frame) -> Vec<u8> {
+    let mut w = MemWriter::new();
+    writeunit(&mut w, frame.encode().as_slice()).unwrap();
+    w.into_inner()
+}
+
+fn decode(bytes: Vec<u8>, maxframe: u64) -> Result<Message, WaterError> {
+    let mut r = MemReader::new(bytes);
+    let unit = try!(readunit(&mut r, maxframe));
+    let frame = try!(Frame::decode(unit.as_slice()));
+    frame.into_message()
+}
+
+fn rawframe() -> Frame {
+    let mut msg = Message::new_fromraw(RawMessage::new_fromstr("hello"));
+    msg.srcsid = 1;
+    msg.srceid = 2;
+    msg.dstsid = 3;
+    msg.dsteid = 4;
+    msg.dstgid = 5;
+    msg.corid = 6;
+    Frame::from_message(msg).unwrap()
+}
+
+fn connect(addr: &str) -> TcpStream {
+    // The listener starts on its own thread so it may not be ready yet.
+    loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => return stream,
+            Err(_) => sleep(Duration::milliseconds(10)),
+        }
+    }
+}
+
+#[test]
+fn frameroundtrip() {
+    let msg = decode(encode(&rawframe()), 1024).unwrap();
+    assert!(msg.srcsid == 1 && msg.srceid == 2);
+    assert!(msg.dstsid == 3 && msg.dsteid == 4 && msg.dstgid == 5);
+    assert!(msg.corid == 6);
+    assert!(msg.get_raw().as_slice() == b"hello");
+}
+
+#[test]
+fn frametruncated() {
+    let bytes = encode(&rawframe());
+    for len in range(0us, bytes.len()) {
+        assert!(decode(bytes.slice_to(len).to_vec(), 1024).is_err());
+    }
+}
+
+#[test]
+fn framelimits() {
+    // Claims to be enormous, which must be refused before allocating.
+    let mut w = MemWriter::new();
+    w.write_be_u64(!0u64);
+    w.write_u8(1);
+    match decode(w.into_inner(), 1024) {
+        Err(WaterError::Protocol(_)) => { },
+        _ => panic!("huge frame was not refused"),
+    }
+
+    // Too small to hold the header.
+    let mut w = MemWriter::new();
+    writeunit(&mut w, range(0, HEADER_SIZE - 1).map(|_| 1u8).collect::<Vec<u8>>().as_slice());
+    match decode(w.into_inner(), 1024) {
+        Err(WaterError::Protocol(_)) => { },
+        _ => panic!("tiny frame was not refused"),
+    }
+
+    // Unknown type.
+    let mut frame = rawframe();
+    frame.msgtype = 99;
+    match decode(encode(&frame), 1024) {
+        Err(WaterError::Protocol(_)) => { },
+        _ => panic!("unknown type was not refused"),
+    }
+
+    // A serial tag longer than the payload.
+    let mut frame = rawframe();
+    frame.msgtype = FRAME_SERIAL;
+    frame.payload = RawMessage::new(5);
+    frame.payload.write_from_slice(0, &[0xff, 0xff, 1, 2, 3]);
+    match decode(encode(&frame), 1024) {
+        Err(WaterError::Protocol(_)) => { },
+        _ => panic!("bad serial tag was not refused"),
+    }
+
+    // An empty stream is just a closed link.
+    assert!(decode(Vec::new(), 1024).err().unwrap() == WaterError::NetDisconnected);
+}
+
+#[test]
+fn framefuzz() {
+    let mut rng = XorShift { state: 0x2545F4914F6CDD1D };
+
+    // Pure garbage.
+    for _ in range(0us, 2000us) {
+        let len = (rng.next() % 256) as usize;
+        let bytes = rng.bytes(len);
+        decode(bytes, 4096);
+    }
+
+    // A valid frame with bytes flipped, which keeps the length sane more
+    // often so the decoder gets further into the frame.
+    let good = encode(&rawframe());
+    for _ in range(0us, 2000us) {
+        let mut bytes = good.clone();
+        for _ in range(0us, 1 + (rng.next() % 4) as usize) {
+            let ndx = (rng.next() % bytes.len() as u64) as usize;
+            bytes[ndx] = rng.next() as u8;
+        }
+        decode(bytes, 4096);
+    }
+}
+
+/// A frame with a payload that compresses well.
+fn bigframe() -> Frame {
+    let mut msg = Message::new_raw(4096);
+    for (ndx, byte) in msg.get_rawmutref().as_mutslice().iter_mut().enumerate() {
+        *byte = (ndx % 16) as u8;
+    }
+    Frame::from_message(msg).unwrap()
+}
+
+#[test]
+fn framecompress() {
+    let mut frame = bigframe();
+    assert!(frame.compress());
+    assert!(frame.payload.len() < 4096);
+
+    let mut w = MemWriter::new();
+    writeunit(&mut w, frame.encode().as_slice()).unwrap();
+    let mut r = MemReader::new(w.into_inner());
+    let mut frame = Frame::decode(readunit(&mut r, 8192).unwrap().as_slice()).unwrap();
+    assert!(frame.compressed);
+
+    // It can not be used until it is decompressed.
+    assert!(frame.clone().into_message().is_err());
+
+    frame.decompress(8192).unwrap();
+    let msg = frame.into_message().unwrap();
+    let raw = msg.get_raw();
+    assert!(raw.len() == 4096);
+    assert!(raw.as_slice().iter().enumerate().all(|(ndx, byte)| *byte == (ndx % 16) as u8));
+
+    // Something that does not get smaller is sent as it is.
+    let mut frame = rawframe();
+    assert!(!frame.compress());
+    assert!(!frame.compressed);
+}
+
+#[test]
+fn framecompresslimit() {
+    // Small on the link but too large once decompressed.
+    let mut frame = bigframe();
+    frame.compress();
+    assert!(frame.decompress(1024).is_err());
+
+    // A size that does not match what it decompresses to.
+    let mut frame = bigframe();
+    frame.compress();
+    frame.payload.as_mutslice()[7] ^= 1;
+    assert!(frame.decompress(8192).is_err());
+}
+
+#[test]
+fn framecompressbomb() {
+    // Compresses to almost nothing but claims to be tiny once decompressed.
+    let mut msg = Message::new_raw(1024 * 1024 * 8);
+    for byte in msg.get_rawmutref().as_mutslice().iter_mut() {
+        *byte = 0;
+    }
+    let mut frame = Frame::from_message(msg).unwrap();
+    assert!(frame.compress());
+    assert!(frame.payload.len() < 65536);
+    for ndx in range(0us, 8us) {
+        frame.payload.as_mutslice()[ndx] = if ndx == 7 { 16 } else { 0 };
+    }
+
+    match frame.decompress(1024 * 1024 * 16) {
+        Err(WaterError::Protocol(_)) => { },
+        _ => panic!("inflating past the claimed size was not refused"),
+    }
+}
+
+#[test]
+fn frametcpteardown() {
+    let net = Net::new(234);
+    let mut listener = net.tcplisten(String::from_str("localhost:34206"));
+    listener.setmaxframe(1024);
+
+    let mut stream = connect("localhost:34206");
+
+    readunit(&mut stream, 1024).unwrap();
+    writeunit(&mut stream, Handshake::new(875).encode().as_slice()).unwrap();
+
+    while listener.getnegcount() < 1 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    stream.write_be_u64(4096);
+    stream.write_u8(1);
+
+    while listener.getprotoerrors() < 1 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    match listener.getlasterror() {
+        Some(WaterError::Protocol(_)) => { },
+        _ => panic!("protocol error was not recorded"),
+    }
+
+    // The listener closed its side of the link.
+    assert!(stream.read_u8().is_err());
+
+    listener.terminate();
+}
@@ -0,0 +1,120 @@
+#![allow(unused_imports)]
+#![allow(dead_code)]
+#![allow(unused_variables)]
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+
+use water::Net;
+use water::Endpoint;
+use water::Message;
+use water::ID;
+use water::Duration;
+
+struct Ping {
+    a:      u64,
+}
+
+impl Clone for Ping { fn clone(&self) -> Ping { Ping { a: self.a } } }
+
+const GRPA: ID = 0x3000;
+const GRPB: ID = 0x3001;
+
+fn sendtogroup(ep: &Endpoint, gid: ID, a: u64) -> usize {
+    let mut msg = Message::new_clone(Ping { a: a });
+    msg.dstsid = 1;
+    msg.dstgid = gid;
+    ep.send(msg)
+}
+
+#[test]
+fn grouplocal() {
+    let net = Net::new(100);
+    let sender = net.new_endpoint();
+    let epa = net.new_endpoint();
+    let epb = net.new_endpoint();
+    let epc = net.new_endpoint();
+
+    // The `epb` endpoint is a member of two groups and `epc` of none.
+    epa.setgid(GRPA);
+    epb.joingroup(GRPA);
+    epb.joingroup(GRPB);
+
+    assert!(epb.ingroup(GRPA) && epb.ingroup(GRPB));
+    assert!(epb.getgid() == GRPA);
+    assert!(epc.getgroups().len() == 0);
+
+    assert!(sendtogroup(&sender, GRPA, 1) == 2);
+    assert!(epa.recv().unwrap().typeunwrap::<Ping>().a == 1);
+    assert!(epb.recv().unwrap().typeunwrap::<Ping>().a == 1);
+    assert!(epc.recv().is_err());
+
+    assert!(sendtogroup(&sender, GRPB, 2) == 1);
+    assert!(epa.recv().is_err());
+    assert!(epb.recv().unwrap().typeunwrap::<Ping>().a == 2);
+    assert!(epc.recv().is_err());
+
+    // After leaving the group it should no longer be offered the message.
+    epb.leavegroup(GRPA);
+    assert!(sendtogroup(&sender, GRPA, 3) == 1);
+    assert!(epa.recv().unwrap().typeunwrap::<Ping>().a == 3);
+    assert!(epb.recv().is_err());
+
+    // A group of zero means no group addressing at all.
+    assert!(sendtogroup(&sender, 0, 4) == 3);
+}
+
+#[test]
+fn groupsync() {
+    let net = Net::new(100);
+    let sender = net.new_endpoint();
+    let epa = net.new_endpoint();
+    let epb = net.new_endpoint();
+    let epc = net.new_endpoint();
+
+    epa.setgid(GRPA);
+    epb.setgid(GRPA);
+
+    // Only one member of the group can take a sync message and
+    // the endpoint outside of the group should never see it.
+    let mut msg = Message::new_sync(Ping { a: 5 });
+    msg.dstsid = 1;
+    msg.dstgid = GRPA;
+    assert!(sender.send(msg) == 2);
+
+    let mut got = 0us;
+    if epa.recv().is_ok() { got += 1; }
+    if epb.recv().is_ok() { got += 1; }
+    assert!(got == 1);
+    assert!(epc.recv().is_err());
+}
+
+#[test]
+fn grouptcp() {
+    let net1 = Net::new(234);
+    let ep1 = net1.new_endpoint();
+    let net2 = Net::new(875);
+    let epin = net2.new_endpoint();
+    let epout = net2.new_endpoint();
+
+    epin.setgid(GRPA);
+
+    let mut listener = net1.tcplisten(String::from_str("localhost:34201"));
+    let mut connector = net2.tcpconnect(String::from_str("localhost:34201"));
+
+    while !connector.connected() { }
+    while listener.getnegcount() < 1 { }
+
+    // The group is carried across the bridge and honored on the remote net.
+    let mut msg = Message::new_raw(4);
+    msg.dstsid = 875;
+    msg.dstgid = GRPA;
+    ep1.send(msg);
+
+    assert!(epin.recvorblock(Duration::seconds(10)).is_ok());
+    assert!(epout.recvorblock(Duration::milliseconds(100)).is_err());
+
+    listener.terminate();
+    connector.terminate();
+}
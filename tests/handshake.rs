@@ -0,0 +1,126 @@
+#![allow(unused_imports)]
+#![allow(dead_code)]
+#![allow(unused_variables)]
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+
+use water::Net;
+use water::Endpoint;
+use water::Message;
+use water::Duration;
+use water::WaterError;
+use water::bridge::handshake::Handshake;
+use water::bridge::handshake::VERSION;
+use water::bridge::handshake::FEATURES;
+use water::bridge::link::readunit;
+use water::bridge::link::writeunit;
+
+use std::io::{TcpListener, TcpStream, Listener, Acceptor};
+use std::io::timer::sleep;
+use std::thread::Thread;
+
+fn isprotocol(err: Option<WaterError>) -> bool {
+    match err {
+        Some(WaterError::Protocol(_)) => true,
+        _ => false,
+    }
+}
+
+fn connect(addr: &str) -> TcpStream {
+    // The listener starts on its own thread so it may not be ready yet.
+    loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => return stream,
+            Err(_) => sleep(Duration::milliseconds(10)),
+        }
+    }
+}
+
+#[test]
+fn handshakebadmagic() {
+    let net = Net::new(234);
+    let mut listener = net.tcplisten(String::from_str("localhost:34204"));
+
+    let mut stream = connect("localhost:34204");
+
+    // The listener sends its handshake without waiting for ours.
+    let hs = Handshake::decode(readunit(&mut stream, 1024).unwrap().as_slice()).unwrap();
+    assert!(hs.sid == 234);
+    assert!(hs.version == VERSION);
+    assert!(hs.features == FEATURES);
+
+    // This is not a water bridge.
+    stream.write_be_u64(18);
+    stream.write(b"GET / HTTP/1.0\r\n\r\n");
+
+    while listener.gethandshakefailures() < 1 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    assert!(isprotocol(listener.getlasterror()));
+    assert!(listener.getnegcount() == 0);
+    assert!(net.stats().endpoints == 0);
+
+    listener.terminate();
+}
+
+#[test]
+fn handshakeversion() {
+    let net = Net::new(875);
+
+    let t = Thread::scoped(move || {
+        let mut acceptor = TcpListener::bind("localhost:34205").listen().unwrap();
+        let mut stream = acceptor.accept().unwrap();
+        // Pretend to be from the future.
+        let mut hs = Handshake::new(234);
+        hs.version = VERSION + 1;
+        writeunit(&mut stream, hs.encode().as_slice()).unwrap();
+        readunit(&mut stream, 1024).unwrap();
+    });
+
+    let mut connector = net.tcpconnect(String::from_str("localhost:34205"));
+
+    while connector.gethandshakefailures() < 1 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    assert!(isprotocol(connector.getlasterror()));
+    assert!(!connector.connected());
+
+    connector.terminate();
+}
+
+#[test]
+fn handshakesameid() {
+    let net1 = Net::new(4001);
+    let net2 = Net::new(4001);
+    let mut listener = net1.tcplisten(String::from_str("localhost:34249"));
+    let mut connector = net2.tcpconnect(String::from_str("localhost:34249"));
+
+    // Messages are routed by net ID so the two can not be bridged.
+    while listener.gethandshakefailures() < 1 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    assert!(isprotocol(listener.getlasterror()));
+    assert!(listener.getnegcount() == 0);
+    assert!(!connector.connected());
+
+    connector.terminate();
+    listener.terminate();
+}
+
+#[test]
+fn handshakeunit() {
+    let hs = Handshake::new(100);
+    let mut other = Handshake::new(200);
+    assert!(hs.negotiate(&other).unwrap() == FEATURES);
+
+    other.features = 1;
+    assert!(hs.negotiate(&other).unwrap() == 1);
+
+    other.features = 0;
+    assert!(hs.negotiate(&other).is_err());
+}
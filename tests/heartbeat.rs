@@ -0,0 +1,122 @@
+#![allow(unused_imports)]
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+
+use water::Net;
+use water::Duration;
+use water::WaterError;
+use water::HeartbeatPolicy;
+use water::bridge::handshake::Handshake;
+use water::bridge::link::readunit;
+use water::bridge::link::writeunit;
+
+use std::io::{TcpListener, TcpStream, Listener, Acceptor};
+use std::io::timer::sleep;
+use std::thread::Thread;
+
+fn connect(addr: &str) -> TcpStream {
+    // The listener starts on its own thread so it may not be ready yet.
+    loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => return stream,
+            Err(_) => sleep(Duration::milliseconds(10)),
+        }
+    }
+}
+
+fn fast() -> Option<HeartbeatPolicy> {
+    Some(HeartbeatPolicy {
+        interval:   Duration::milliseconds(20),
+        timeout:    Duration::milliseconds(300),
+    })
+}
+
+/// Do the handshake like a peer would, then never send anything again.
+fn silentpeer(stream: &mut TcpStream, sid: u64) {
+    readunit(stream, 1024).unwrap();
+    writeunit(stream, Handshake::new(sid).encode().as_slice());
+}
+
+#[test]
+fn heartbeatrtt() {
+    let net1 = Net::new(234);
+    let net2 = Net::new(875);
+
+    let mut listener = net1.tcplisten(String::from_str("localhost:34216"));
+    listener.setheartbeat(fast());
+    let mut connector = net2.tcpconnect(String::from_str("localhost:34216"));
+    connector.setheartbeat(fast());
+
+    while listener.getnegcount() < 1 { }
+
+    while connector.getrtt().is_none() {
+        sleep(Duration::milliseconds(10));
+    }
+    while listener.getconnections()[0].rtt.is_none() {
+        sleep(Duration::milliseconds(10));
+    }
+
+    assert!(connector.getrtt().unwrap() < Duration::seconds(1));
+
+    // Pongs keep a quiet link alive.
+    sleep(Duration::milliseconds(600));
+    assert!(listener.gettimeouts() == 0);
+    assert!(connector.gettimeouts() == 0);
+    assert!(listener.getnegcount() == 1);
+
+    listener.terminate();
+    connector.terminate();
+}
+
+#[test]
+fn heartbeatlistenertimeout() {
+    let net = Net::new(234);
+    let mut listener = net.tcplisten(String::from_str("localhost:34217"));
+    listener.setheartbeat(fast());
+
+    let mut stream = connect("localhost:34217");
+    silentpeer(&mut stream, 875);
+
+    while listener.gettimeouts() < 1 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    match listener.getlasterror() {
+        Some(WaterError::TimedOut) => { },
+        _ => panic!("expected a timeout"),
+    }
+
+    while listener.getclientcount() > 0 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    listener.terminate();
+}
+
+#[test]
+fn heartbeatconnectortimeout() {
+    let net = Net::new(875);
+
+    // A listener that goes silent on every connection. The connector has to
+    // notice and connect again.
+    let t = Thread::scoped(move || {
+        let mut acceptor = TcpListener::bind("localhost:34218").listen().unwrap();
+        let mut first = acceptor.accept().unwrap();
+        silentpeer(&mut first, 234);
+        let mut second = acceptor.accept().unwrap();
+        silentpeer(&mut second, 234);
+    });
+
+    let mut connector = net.tcpconnect(String::from_str("localhost:34218"));
+    connector.setheartbeat(fast());
+
+    t.join();
+
+    while connector.gettimeouts() < 1 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    connector.terminate();
+}
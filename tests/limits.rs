@@ -0,0 +1,123 @@
+#![allow(unused_imports)]
+#![allow(dead_code)]
+#![allow(unused_variables)]
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+
+use water::Net;
+use water::Endpoint;
+use water::Message;
+use water::Delivery;
+use water::Duration;
+use water::WaterError;
+
+use std::thread::Thread;
+use std::io::timer::sleep;
+
+fn rawto(ep: &Endpoint, cap: usize) -> Message {
+    let mut msg = Message::new_raw(cap);
+    msg.dstsid = 1;
+    msg.dsteid = ep.geteid();
+    msg
+}
+
+#[test]
+fn limitreport() {
+    let net = Net::new(100);
+    let sender = net.new_endpoint();
+    let full = net.new_endpoint();
+    let heavy = net.new_endpoint();
+
+    full.setlimitpending(1);
+    heavy.setlimitmemory(16);
+
+    assert!(sender.sendreport(rawto(&full, 8)).delivered() == 1);
+    let report = sender.sendreport(rawto(&full, 8));
+    assert!(report.queuefull() == 1 && report.delivered() == 0);
+    assert!(!report.nolisteners());
+
+    assert!(sender.sendreport(rawto(&heavy, 16)).delivered() == 1);
+    let report = sender.sendreport(rawto(&heavy, 16));
+    assert!(report.memorylimit() == 1);
+    assert!(report.outcomes[0].1 == heavy.geteid());
+    assert!(report.outcomes[0].2 == Delivery::MemoryLimit);
+
+    // Nobody has this endpoint ID so nobody is listening.
+    let mut msg = Message::new_raw(8);
+    msg.dstsid = 1;
+    msg.dsteid = 0x9999;
+    assert!(sender.sendreport(msg).nolisteners());
+}
+
+#[test]
+fn limitfiltered() {
+    let net = Net::new(100);
+    let sender = net.new_endpoint();
+    let ep = net.new_endpoint();
+
+    // The sender is offered its own broadcast but filters it out.
+    let report = sender.sendreport(Message::new_raw(8));
+    assert!(report.delivered() == 1);
+    assert!(report.filtered() == 1);
+}
+
+#[test]
+fn limitblocktimeout() {
+    let net = Net::new(100);
+    let sender = net.new_endpoint();
+    let full = net.new_endpoint();
+
+    full.setlimitpending(1);
+    sender.send(rawto(&full, 8));
+
+    let report = sender.sendorblock(rawto(&full, 8), Duration::milliseconds(100)).unwrap();
+    assert!(report.queuefull() == 1);
+    assert!(report.delivered() == 0);
+}
+
+#[test]
+fn limitblockpartial() {
+    let net = Net::new(100);
+    let sender = net.new_endpoint();
+    let full = net.new_endpoint();
+    let free = net.new_endpoint();
+
+    full.setlimitpending(1);
+    sender.send(rawto(&full, 8));
+
+    // The endpoint with space has the message even though the other timed out,
+    // so sending it again would give it a duplicate.
+    let mut msg = Message::new_raw(8);
+    msg.dstsid = 1;
+    let report = sender.sendorblock(msg, Duration::milliseconds(100)).unwrap();
+    assert!(report.delivered() == 1);
+    assert!(report.rejected() == 1);
+    assert!(report.outcomes.iter().any(|o| o.1 == full.geteid() && o.2 == Delivery::QueueFull));
+    assert!(free.recv().is_ok());
+}
+
+#[test]
+fn limitblockflow() {
+    let net = Net::new(100);
+    let sender = net.new_endpoint();
+    let consumer = net.new_endpoint();
+
+    consumer.setlimitpending(2);
+
+    // The producer can only get ahead by two messages, and every
+    // message must arrive since the sender waits for space.
+    let msg = rawto(&consumer, 8);
+    let t = Thread::scoped(move || {
+        for _ in range(0us, 100us) {
+            let report = sender.sendorblock(msg.clone(), Duration::seconds(10)).unwrap();
+            assert!(report.delivered() == 1);
+        }
+    });
+
+    for _ in range(0us, 100us) {
+        consumer.recvorblock(Duration::seconds(10)).unwrap();
+        sleep(Duration::milliseconds(1));
+    }
+}
@@ -0,0 +1,59 @@
+#![allow(unused_imports)]
+#![allow(dead_code)]
+#![allow(unused_variables)]
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+
+use water::WaterError;
+use water::bridge::link::Link;
+use water::bridge::link::DatagramLink;
+use water::bridge::handshake::exchange;
+use water::bridge::handshake::FEATURES;
+
+use std::io::net::udp::UdpSocket;
+use std::thread::Thread;
+
+fn pair() -> (DatagramLink, DatagramLink) {
+    let a = UdpSocket::bind("127.0.0.1:34207").unwrap();
+    let b = UdpSocket::bind("127.0.0.1:34208").unwrap();
+    let aaddr = a.socket_name().unwrap();
+    let baddr = b.socket_name().unwrap();
+    (DatagramLink::new(a, baddr), DatagramLink::new(b, aaddr))
+}
+
+#[test]
+fn linkdatagram() {
+    let (mut a, mut b) = pair();
+
+    // The handshake runs over any link.
+    let t = Thread::scoped(move || {
+        let remote = exchange(&mut b, 875).unwrap();
+        assert!(remote.sid == 234);
+        b.sendunit(b"hello").unwrap();
+        b
+    });
+
+    let remote = exchange(&mut a, 234).unwrap();
+    assert!(remote.sid == 875);
+    assert!(remote.features == FEATURES);
+    assert!(a.recvunit(1024).unwrap().as_slice() == b"hello");
+
+    let mut b = t.join().ok().unwrap();
+
+    // Too large for the receiver.
+    b.sendunit(b"hello").unwrap();
+    match a.recvunit(2) {
+        Err(WaterError::Protocol(_)) => { },
+        _ => panic!("large unit was not refused"),
+    }
+
+    // Shutting down a duplicate wakes a blocked receiver.
+    let mut dup = a.duplicate();
+    let t = Thread::scoped(move || {
+        a.recvunit(1024)
+    });
+    dup.shutdown();
+    assert!(t.join().ok().unwrap().err().unwrap() == WaterError::NetDisconnected);
+}
@@ -0,0 +1,61 @@
+#![allow(unused_imports)]
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+
+use water::Net;
+use water::Message;
+use water::RawMessage;
+use water::Duration;
+
+use std::io::timer::sleep;
+
+#[test]
+fn listenerconnections() {
+    let net1 = Net::new(234);
+    let ep1 = net1.new_endpoint();
+    let net2 = Net::new(875);
+    let ep2 = net2.new_endpoint();
+    let net3 = Net::new(876);
+
+    let mut listener = net1.tcplisten(String::from_str("localhost:34212"));
+    let mut connector2 = net2.tcpconnect(String::from_str("localhost:34212"));
+    let mut connector3 = net3.tcpconnect(String::from_str("localhost:34212"));
+
+    while listener.getnegcount() < 2 { }
+
+    let conns = listener.getconnections();
+    assert!(conns.len() == 2);
+    assert!(conns.iter().any(|conn| conn.sid == 875));
+    assert!(conns.iter().any(|conn| conn.sid == 876));
+    assert!(conns.iter().all(|conn| conn.negotiated && conn.addr.len() > 0));
+
+    let mut msg = Message::new_fromraw(RawMessage::new_fromstr("ABCD"));
+    msg.dstsid = 875;
+    msg.dsteid = ep2.geteid();
+    ep1.send(msg);
+    ep2.recvorblock(Duration::seconds(10)).unwrap();
+
+    let conn = listener.getconnections().into_iter().find(|conn| conn.sid == 875).unwrap();
+    assert!(conn.msgsout == 1);
+    assert!(conn.bytesout > 4);
+    assert!(conn.msgsin == 0);
+
+    // Drop one peer. Its connector will come back, but with a new connection.
+    let id = listener.getconnections().into_iter().find(|conn| conn.sid == 876).unwrap().id;
+    assert!(listener.disconnect(id));
+    while listener.getconnections().iter().any(|conn| conn.id == id) {
+        sleep(Duration::milliseconds(10));
+    }
+    assert!(!listener.disconnect(id));
+
+    // Everything is closed and joined by the time this returns.
+    listener.terminate();
+    assert!(listener.getclientcount() == 0);
+    assert!(listener.getnegcount() == 0);
+    assert!(net1.stats().endpoints == 1);
+
+    connector2.terminate();
+    connector3.terminate();
+}
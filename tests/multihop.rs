@@ -0,0 +1,159 @@
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+
+use water::Net;
+use water::Message;
+use water::Duration;
+use water::MAX_HOPS;
+
+use std::io::timer::sleep;
+
+#[test]
+fn multihopchain() {
+    // A <-> B <-> C where A and C only learn of each other through B.
+    let neta = Net::new(1001);
+    let netb = Net::new(1002);
+    let netc = Net::new(1003);
+    let epc = netc.new_endpoint();
+
+    let mut listenera = neta.tcplisten(String::from_str("localhost:34219"));
+    let mut connectorb = netb.tcpconnect(String::from_str("localhost:34219"));
+    let mut listenerb = netb.tcplisten(String::from_str("localhost:34220"));
+    let mut connectorc = netc.tcpconnect(String::from_str("localhost:34220"));
+
+    while neta.getnexthop(1003) != Some(1002) {
+        sleep(Duration::milliseconds(10));
+    }
+    while netc.getnexthop(1001) != Some(1002) {
+        sleep(Duration::milliseconds(10));
+    }
+
+    assert!(neta.getnexthop(1002) == Some(1002));
+    assert!(neta.getroutes().iter().any(|&(sid, via, hops)| sid == 1003 && via == 1002 && hops == 2));
+
+    let mut msg = Message::new_raw(4);
+    msg.dstsid = 1003;
+    msg.dsteid = epc.geteid();
+    msg.get_rawmutref().as_mutslice()[0] = 0x42;
+    neta.new_endpoint().send(msg);
+
+    let msg = epc.recvorblock(Duration::seconds(5)).unwrap();
+    assert!(msg.srcsid == 1001);
+    assert!(msg.hops == 2);
+    assert!(msg.get_raw().as_slice()[0] == 0x42);
+
+    // Once B loses C the route has to be withdrawn from A too.
+    connectorc.terminate();
+
+    while neta.getnexthop(1003).is_some() {
+        sleep(Duration::milliseconds(10));
+    }
+
+    listenera.terminate();
+    listenerb.terminate();
+    connectorb.terminate();
+}
+
+#[test]
+fn multihoplimit() {
+    let net1 = Net::new(1011);
+    let net2 = Net::new(1012);
+    let ep2 = net2.new_endpoint();
+
+    let mut listener = net1.tcplisten(String::from_str("localhost:34221"));
+    let mut connector = net2.tcpconnect(String::from_str("localhost:34221"));
+
+    while listener.getnegcount() < 1 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    // This one has already crossed too many bridges. It is given to the net
+    // like a bridge does, since an endpoint would start the count over.
+    let mut msg = Message::new_raw(4);
+    msg.dstsid = 1012;
+    msg.hops = MAX_HOPS;
+    net1.send(msg);
+
+    let mut msg = Message::new_raw(4);
+    msg.dstsid = 1012;
+    msg.hops = MAX_HOPS - 1;
+    net1.send(msg);
+
+    let msg = ep2.recvorblock(Duration::seconds(5)).unwrap();
+    assert!(msg.hops == MAX_HOPS);
+    assert!(ep2.recvorblock(Duration::milliseconds(200)).is_err());
+
+    listener.terminate();
+    connector.terminate();
+}
+
+#[test]
+fn multihopresend() {
+    let net1 = Net::new(1021);
+    let net2 = Net::new(1022);
+    let ep1 = net1.new_endpoint();
+    let ep2 = net2.new_endpoint();
+
+    let mut listener = net1.tcplisten(String::from_str("localhost:34241"));
+    let mut connector = net2.tcpconnect(String::from_str("localhost:34241"));
+
+    while listener.getnegcount() < 1 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    let mut msg = Message::new_raw(4);
+    msg.dstsid = 1022;
+    msg.hops = MAX_HOPS - 1;
+    net1.send(msg);
+
+    let msg = ep2.recvorblock(Duration::seconds(5)).unwrap();
+    assert!(msg.hops == MAX_HOPS);
+
+    // Sending it again makes it a new message that can cross bridges again.
+    let mut msg = msg;
+    msg.dstsid = 1021;
+    msg.dsteid = ep1.geteid();
+    ep2.send(msg);
+
+    let msg = ep1.recvorblock(Duration::seconds(5)).unwrap();
+    assert!(msg.srcsid == 1022);
+    assert!(msg.hops == 1);
+
+    listener.terminate();
+    connector.terminate();
+}
+
+#[test]
+fn multihoptwolinks() {
+    let net1 = Net::new(1031);
+    let net2 = Net::new(1032);
+    let ep2 = net2.new_endpoint();
+
+    // Each net connects to the other so there are two links between them.
+    let mut listener1 = net1.tcplisten(String::from_str("localhost:34243"));
+    let mut listener2 = net2.tcplisten(String::from_str("localhost:34244"));
+    let mut connector1 = net1.tcpconnect(String::from_str("localhost:34244"));
+    let mut connector2 = net2.tcpconnect(String::from_str("localhost:34243"));
+
+    while listener1.getnegcount() < 1 || listener2.getnegcount() < 1 {
+        sleep(Duration::milliseconds(10));
+    }
+    while !connector1.connected() || !connector2.connected() {
+        sleep(Duration::milliseconds(10));
+    }
+
+    let mut msg = Message::new_raw(4);
+    msg.dstsid = 1032;
+    msg.dsteid = ep2.geteid();
+    assert!(net1.new_endpoint().send(msg) == 1);
+
+    assert!(ep2.recvorblock(Duration::seconds(5)).is_ok());
+    assert!(ep2.recvorblock(Duration::milliseconds(200)).is_err());
+
+    listener1.terminate();
+    listener2.terminate();
+    connector1.terminate();
+    connector2.terminate();
+}
@@ -61,13 +61,13 @@ fn pingpong_water(m: uint, n: uint) {
         let ta = Thread::spawn(move || {
             for _ in range(0, n) {
                 epa.sendsynctype(());
-                epa.recvorblock(Duration::seconds(9)).ok();
+                epa.recvorblock(Duration::seconds(9)).unwrap();
             }
         });
 
         let tb = Thread::spawn(move || {
             for _ in range(0, n) {
-                epb.recvorblock(Duration::seconds(9)).ok();
+                epb.recvorblock(Duration::seconds(9)).unwrap();
                 epb.sendsynctype(());
             }
         });
@@ -8,6 +8,7 @@ use water::Endpoint;
 use water::Message;
 use water::ID;
 use water::Duration;
+use water::Selector;
 use time::Timespec;
 
 use std::thread::JoinGuard;
@@ -122,7 +123,7 @@ fn thread_pipelineworker(ep: Endpoint) {
 /// forwards the data for the second processing pass to the processors.
 ///
 fn thread_pipelinemanager(mep: Endpoint, corecnt: usize) {
-    let mut endpoints: Vec<Endpoint> = Vec::new();
+    let mut selector = Selector::new();
     let mut workerthreads: Vec<JoinGuard<()>> = Vec::new();
     let mut pending: HashMap<u64, WorkPending> = HashMap::new();
 
@@ -130,8 +131,8 @@ fn thread_pipelinemanager(mep: Endpoint, corecnt: usize) {
     let net = Net::new(400);
     let ep = net.new_endpoint_withid(MANAGEREID);
 
-    endpoints.push(mep.clone());
-    endpoints.push(ep.clone());
+    selector.add(&mep);
+    selector.add(&ep);
 
     println!("[man] creating worker threads");
     // Spawn worker threads for the number of specified cores.
@@ -145,17 +146,20 @@ fn thread_pipelinemanager(mep: Endpoint, corecnt: usize) {
     loop {
         // Check for requests and results from data processors.
         println!("[man] waiting for messages");
-        let result = water::recvorblock(&endpoints, Duration::seconds(10));
+        let result = selector.recvorblock(Duration::seconds(10));
 
         if result.is_err() {
             continue;
         }
 
-        let msg = result.unwrap();
+        let (_, msg) = result.unwrap();
 
         if msg.is_type::<Terminate>() {
             // Tell all workers to terminate.
-            ep.sendclonetype(Terminate);
+            let mut msg = Message::new_clone(Terminate);
+            msg.dstsid = 1;
+            msg.dstgid = WORKERGRPID;
+            ep.send(msg);
             return;
         }
 
@@ -191,8 +195,12 @@ fn thread_pipelinemanager(mep: Endpoint, corecnt: usize) {
                 };
                 println!("[man] sent work packet of length {:x}", work.data.len());
                 // Only the first worker to receive this gets it. This is
-                // because we are sending it as sync instead of clone.
-                ep.sendsynctype(work);
+                // because we are sending it as sync instead of clone. Only
+                // members of the worker group will be offered the message.
+                let mut msg = Message::new_sync(work);
+                msg.dstsid = 1;
+                msg.dstgid = WORKERGRPID;
+                ep.send(msg);
             }
 
             // Throw slack onto last chunk.
@@ -206,7 +214,10 @@ fn thread_pipelinemanager(mep: Endpoint, corecnt: usize) {
             println!("[man] sent slack work packet {:x}", work.data.len());
             // Only the first worker to receive this gets it. This is
             // because we are sending it as sync instead of clone.
-            ep.sendsynctype(work);
+            let mut msg = Message::new_sync(work);
+            msg.dstsid = 1;
+            msg.dstgid = WORKERGRPID;
+            ep.send(msg);
             continue;
         }
 
@@ -0,0 +1,94 @@
+#![allow(unused_imports)]
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+
+use water::Net;
+use water::Message;
+use water::RawMessage;
+use water::Duration;
+use water::ReconnectPolicy;
+use water::ConnectorState;
+
+use std::io::timer::sleep;
+
+fn policy(maxattempts: Option<u64>) -> ReconnectPolicy {
+    let mut policy = ReconnectPolicy::new();
+    policy.initialdelay = Duration::milliseconds(10);
+    policy.maxdelay = Duration::milliseconds(40);
+    policy.maxattempts = maxattempts;
+    policy
+}
+
+#[test]
+fn reconnectgiveup() {
+    // Nothing listens on this port.
+    let net = Net::new(100);
+    let mut connector = net.tcpconnectlist(vec![String::from_str("localhost:34209")], policy(Some(4)));
+
+    let mut waited = 0;
+    while connector.getstate() != ConnectorState::GivenUp {
+        sleep(Duration::milliseconds(10));
+        waited += 1;
+        assert!(waited < 1000);
+    }
+
+    assert!(connector.getattempts() == 4);
+    assert!(!connector.connected());
+    assert!(connector.getlasterror().is_some());
+
+    connector.terminate();
+    assert!(connector.getstate() == ConnectorState::Terminated);
+}
+
+#[test]
+fn reconnectbackoff() {
+    let net = Net::new(100);
+    let mut slow = policy(None);
+    slow.initialdelay = Duration::seconds(60);
+    slow.maxdelay = Duration::seconds(60);
+    let mut connector = net.tcpconnectlist(vec![String::from_str("localhost:34209")], slow);
+
+    // It should sit waiting instead of retrying right away.
+    while connector.getstate() != ConnectorState::BackingOff { }
+    sleep(Duration::milliseconds(100));
+    assert!(connector.getattempts() == 1);
+
+    // A terminate does not have to wait for the delay to pass.
+    connector.terminate();
+    assert!(connector.getstate() == ConnectorState::Terminated);
+}
+
+#[test]
+fn reconnectfailover() {
+    let net1 = Net::new(234);
+    let ep1 = net1.new_endpoint();
+    let net2 = Net::new(875);
+    let ep2 = net2.new_endpoint();
+
+    let mut listener = net1.tcplisten(String::from_str("localhost:34210"));
+    // The first address has nothing listening so it has to move on to the second.
+    let mut connector = net2.tcpconnectlist(vec![
+        String::from_str("localhost:34209"),
+        String::from_str("localhost:34210"),
+    ], policy(None));
+
+    while !connector.connected() { }
+    while listener.getnegcount() < 1 { }
+
+    assert!(connector.getstate() == ConnectorState::Connected);
+    assert!(connector.getaddr() == String::from_str("localhost:34210"));
+    assert!(connector.getattempts() == 0);
+
+    let mut msg = Message::new_fromraw(RawMessage::new_fromstr("ABCD"));
+    msg.dstsid = 875;
+    msg.dsteid = ep2.geteid();
+    ep1.send(msg);
+
+    let raw = ep2.recvorblock(Duration::seconds(10)).unwrap().get_raw();
+    assert!(raw.as_slice() == b"ABCD");
+
+    listener.terminate();
+    connector.terminate();
+}
@@ -0,0 +1,72 @@
+#![allow(unused_imports)]
+#![allow(dead_code)]
+#![allow(unused_variables)]
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+
+use water::Net;
+use water::Endpoint;
+use water::Message;
+use water::ID;
+
+fn unicast(ep: &Endpoint, eid: ID) -> usize {
+    let mut msg = Message::new_raw(8);
+    msg.dstsid = 1;
+    msg.dsteid = eid;
+    ep.send(msg)
+}
+
+#[test]
+fn routingreindex() {
+    let net = Net::new(100);
+    let sender = net.new_endpoint();
+    let ep = net.new_endpoint();
+
+    // Changing the address must move the endpoint in the routing
+    // indexes, otherwise unicast to the new address would be lost.
+    let oldeid = ep.geteid();
+    ep.seteid(0x5000);
+    assert!(unicast(&sender, oldeid) == 0);
+    assert!(unicast(&sender, 0x5000) == 1);
+    assert!(ep.recv().is_ok());
+
+    // Endpoints sharing an endpoint ID are all offered the message.
+    let other = net.new_endpoint_withid(0x5000);
+    assert!(unicast(&sender, 0x5000) == 2);
+}
+
+#[test]
+fn routingdrop() {
+    let net = Net::new(100);
+    let sender = net.new_endpoint();
+    let eid;
+    {
+        let ep = net.new_endpoint();
+        eid = ep.geteid();
+        assert!(net.getepcount() == 2);
+    }
+
+    // Once dropped it should be gone from the indexes too.
+    assert!(net.getepcount() == 1);
+    assert!(unicast(&sender, eid) == 0);
+}
+
+#[test]
+fn routingmany() {
+    let net = Net::new(100);
+    let sender = net.new_endpoint();
+    let mut eps: Vec<Endpoint> = Vec::new();
+    for _ in range(0us, 1000us) {
+        eps.push(net.new_endpoint());
+    }
+
+    for ep in eps.iter() {
+        assert!(unicast(&sender, ep.geteid()) == 1);
+        assert!(ep.recv().is_ok());
+    }
+
+    // A broadcast still reaches every endpoint except the sender.
+    assert!(sender.send(Message::new_raw(8)) == 1000);
+}
@@ -0,0 +1,150 @@
+#![allow(unused_imports)]
+#![allow(dead_code)]
+#![allow(unused_variables)]
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+
+use water::Net;
+use water::Endpoint;
+use water::Message;
+use water::RawMessage;
+use water::Duration;
+use water::WaterError;
+use water::RpcClient;
+use water::RpcServer;
+
+use std::thread::Thread;
+
+struct Add {
+    a:      u64,
+    b:      u64,
+}
+
+struct Sum {
+    c:      u64,
+}
+
+struct Stop;
+impl Clone for Stop { fn clone(&self) -> Stop { Stop } }
+
+fn makeserver(ep: Endpoint) -> RpcServer {
+    let mut server = RpcServer::new(ep);
+
+    server.handle::<Add, _>(|msg| {
+        let add: Add = msg.typeunwrap();
+        Some(Message::new_sync(Sum { c: add.a + add.b }))
+    });
+
+    server.handle::<u64, _>(|msg| {
+        let value: u64 = msg.typeunwrap();
+        Some(Message::new_clone(value * 2))
+    });
+
+    // Reply to a raw message with each byte incremented.
+    server.handleraw(|msg| {
+        let raw = msg.get_raw();
+        let mut reply = RawMessage::new(raw.len());
+        for (ndx, value) in raw.as_slice().iter().enumerate() {
+            reply.writeu8(ndx, *value + 1);
+        }
+        Some(Message::new_fromraw(reply))
+    });
+
+    server
+}
+
+#[test]
+fn rpclocal() {
+    let net = Net::new(100);
+    let sep = net.new_endpoint();
+    let seid = sep.geteid();
+
+    let t = Thread::scoped(move || {
+        let mut server = makeserver(sep);
+        for _ in range(0us, 2us) {
+            server.serveonce(Duration::seconds(10)).unwrap();
+        }
+    });
+
+    let mut client = RpcClient::new(&net);
+
+    let reply = client.callsynctype(1, seid, Add { a: 3, b: 4 }, Duration::seconds(10)).unwrap();
+    assert!(reply.typeunwrap::<Sum>().c == 7);
+
+    let reply = client.callclonetype(1, seid, 21u64, Duration::seconds(10)).unwrap();
+    assert!(reply.typeunwrap::<u64>() == 42);
+}
+
+#[test]
+fn rpcmatching() {
+    let net = Net::new(100);
+    let sep = net.new_endpoint();
+    let seid = sep.geteid();
+    let mut client = RpcClient::new(&net);
+    let ceid = client.getendpoint().geteid();
+
+    // Nobody is serving so the call times out.
+    let result = client.callclonetype(1, seid, 1u64, Duration::milliseconds(50));
+    assert!(result.err().unwrap() == WaterError::TimedOut);
+
+    // The late reply to the first call, and a message that is not a reply
+    // at all, must both be skipped by the second call.
+    let first = sep.recv().unwrap();
+    let mut late = Message::new_clone(0u64);
+    late.dstsid = 1;
+    late.dsteid = ceid;
+    late.corid = first.corid;
+    sep.send(late);
+    let mut stray = Message::new_clone(0u64);
+    stray.dstsid = 1;
+    stray.dsteid = ceid;
+    sep.send(stray);
+
+    let t = Thread::scoped(move || {
+        let mut server = makeserver(sep);
+        server.serveonce(Duration::seconds(10)).unwrap();
+    });
+
+    let reply = client.callclonetype(1, seid, 5u64, Duration::seconds(10)).unwrap();
+    assert!(reply.typeunwrap::<u64>() == 10);
+}
+
+#[test]
+fn rpcnolisteners() {
+    let net = Net::new(100);
+    let mut client = RpcClient::new(&net);
+    let result = client.callclonetype(1, 0x9999, 1u64, Duration::seconds(10));
+    assert!(result.err().unwrap() == WaterError::NetDisconnected);
+}
+
+#[test]
+fn rpctcp() {
+    let net1 = Net::new(234);
+    let net2 = Net::new(875);
+    let sep = net2.new_endpoint();
+    let seid = sep.geteid();
+
+    let mut listener = net1.tcplisten(String::from_str("localhost:34202"));
+    let mut connector = net2.tcpconnect(String::from_str("localhost:34202"));
+
+    while !connector.connected() { }
+    while listener.getnegcount() < 1 { }
+
+    let t = Thread::scoped(move || {
+        let mut server = makeserver(sep);
+        server.serveonce(Duration::seconds(10)).unwrap();
+    });
+
+    let mut client = RpcClient::new(&net1);
+    let reply = client.call(875, seid, Message::new_fromraw(RawMessage::new_fromstr("ABC")), Duration::seconds(10)).unwrap();
+    let raw = reply.get_raw();
+    assert!(raw.readu8(0) == 66);
+    assert!(raw.readu8(1) == 67);
+    assert!(raw.readu8(2) == 68);
+
+    drop(t);
+    listener.terminate();
+    connector.terminate();
+}
@@ -62,7 +62,7 @@ fn syncio() {
 
     let result = ep.recvorblock(Duration::seconds(3));
 
-    let msg: Arc<Foo> = result.ok().get_sync().get_payload();
+    let msg: Arc<Foo> = result.unwrap().get_sync().get_payload();
 
     drop(ta);
 }
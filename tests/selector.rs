@@ -0,0 +1,124 @@
+#![allow(unused_imports)]
+#![allow(dead_code)]
+#![allow(unused_variables)]
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+
+use water::Net;
+use water::Endpoint;
+use water::Message;
+use water::Selector;
+use water::Duration;
+
+use std::thread::Thread;
+use std::io::timer::sleep;
+use time::get_time;
+
+fn sendto(ep: &Endpoint, to: &Endpoint, value: usize) {
+    let mut msg = Message::new_clone(value);
+    msg.dstsid = 1;
+    msg.dsteid = to.geteid();
+    ep.send(msg);
+}
+
+#[test]
+fn selectorwakes() {
+    let net = Net::new(100);
+    let ep1 = net.new_endpoint();
+    let ep2 = net.new_endpoint();
+    let sender = net.new_endpoint();
+
+    let mut selector = Selector::new();
+    selector.add(&ep1);
+    selector.add(&ep2);
+
+    // The sender waits before sending so that the selector has to sleep
+    // and be woken by the endpoint being given the message.
+    let ep2clone = ep2.clone();
+    let t = Thread::scoped(move || {
+        sleep(Duration::milliseconds(200));
+        sendto(&sender, &ep2clone, 7);
+    });
+
+    let (ndx, msg) = selector.recvorblock(Duration::seconds(10)).unwrap();
+    assert!(ndx == 1);
+    assert!(msg.typeunwrap::<usize>() == 7);
+}
+
+#[test]
+fn selectortimeout() {
+    let net = Net::new(100);
+    let ep1 = net.new_endpoint();
+
+    let mut selector = Selector::new();
+    selector.add(&ep1);
+
+    let start = get_time();
+    assert!(selector.recvorblock(Duration::milliseconds(200)).is_err());
+    assert!((get_time() - start).num_milliseconds() >= 200);
+}
+
+#[test]
+fn selectorfair() {
+    let net = Net::new(100);
+    let ep1 = net.new_endpoint();
+    let ep2 = net.new_endpoint();
+    let sender = net.new_endpoint();
+
+    for x in range(0us, 4us) {
+        sendto(&sender, &ep1, x);
+        sendto(&sender, &ep2, x);
+    }
+
+    let mut selector = Selector::new();
+    selector.add(&ep1);
+    selector.add(&ep2);
+
+    // With both endpoints busy they should be serviced in turn instead
+    // of draining the first endpoint before looking at the second.
+    for x in range(0us, 8us) {
+        let (ndx, _) = selector.recv().unwrap();
+        assert!(ndx == x % 2);
+    }
+
+    assert!(selector.recv().is_err());
+}
+
+#[test]
+fn selectorremove() {
+    let net = Net::new(100);
+    let ep1 = net.new_endpoint();
+    let ep2 = net.new_endpoint();
+    let sender = net.new_endpoint();
+
+    let mut selector = Selector::new();
+    selector.add(&ep1);
+    selector.add(&ep2);
+    selector.remove(&ep1);
+    assert!(selector.len() == 1);
+
+    sendto(&sender, &ep1, 1);
+    assert!(selector.recv().is_err());
+    sendto(&sender, &ep2, 2);
+    assert!(selector.recv().unwrap().1.typeunwrap::<usize>() == 2);
+}
+
+#[test]
+fn selectorlist() {
+    let net = Net::new(100);
+    let ep1 = net.new_endpoint();
+    let ep2 = net.new_endpoint();
+    let sender = net.new_endpoint();
+    let list = vec![ep1.clone(), ep2.clone()];
+
+    let ep2clone = ep2.clone();
+    let t = Thread::scoped(move || {
+        sleep(Duration::milliseconds(100));
+        sendto(&sender, &ep2clone, 3);
+    });
+
+    let msg = water::recvorblock(&list, Duration::seconds(10)).unwrap();
+    assert!(msg.typeunwrap::<usize>() == 3);
+}
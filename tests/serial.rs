@@ -0,0 +1,107 @@
+#![allow(unused_imports)]
+#![allow(dead_code)]
+#![allow(unused_variables)]
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+extern crate "rustc-serialize" as rustc_serialize;
+
+use water::Net;
+use water::Endpoint;
+use water::Message;
+use water::Duration;
+use water::WaterError;
+use water::SerialMessage;
+use water::RawMessage;
+
+#[derive(RustcEncodable, RustcDecodable, Clone, PartialEq, Debug)]
+struct Order {
+    id:         u64,
+    name:       String,
+    items:      Vec<u32>,
+}
+
+const ORDER: &'static str = "order";
+
+fn makeorder() -> Order {
+    Order { id: 42, name: String::from_str("apple"), items: vec![1, 2, 3] }
+}
+
+#[test]
+fn seriallocal() {
+    let net = Net::new(100);
+    let ep1 = net.new_endpoint();
+    let ep2 = net.new_endpoint();
+
+    ep1.sendserialtype(ORDER, makeorder());
+
+    let msg = ep2.recvorblock(Duration::seconds(10)).unwrap();
+    assert!(msg.is_serial());
+    assert!(msg.is_serialtag(ORDER));
+    assert!(!msg.is_serialtag("u64"));
+    assert!(!msg.is_type::<Order>());
+    assert!(msg.serialunwrap::<Order>(ORDER) == makeorder());
+}
+
+#[test]
+fn serialmanyreceivers() {
+    let net = Net::new(100);
+    let ep1 = net.new_endpoint();
+    let ep2 = net.new_endpoint();
+    let ep3 = net.new_endpoint();
+
+    // Unlike a sync message every receiver gets its own copy. The sender does
+    // not receive its own broadcast.
+    assert!(ep1.sendserialtype(ORDER, makeorder()) == 2);
+    assert!(ep2.recv().unwrap().serialunwrap::<Order>(ORDER) == makeorder());
+    assert!(ep3.recv().unwrap().serialunwrap::<Order>(ORDER) == makeorder());
+}
+
+#[test]
+fn serialwrongtype() {
+    let msg = Message::new_serial(ORDER, makeorder());
+    assert!(msg.clone().trytypeunwrap::<Order>().err().unwrap() == WaterError::WrongType);
+    assert!(msg.clone().tryserialunwrap::<Order>("other").err().unwrap() == WaterError::WrongType);
+    match msg.clone().tryserialunwrap::<u64>(ORDER) {
+        Err(WaterError::Protocol(_)) => { },
+        _ => panic!("decoded as the wrong type"),
+    }
+    assert!(msg.get_serial().try_payload::<Order>(ORDER).unwrap() == makeorder());
+}
+
+#[test]
+fn serialneverbuilt() {
+    // The type is decoded from the bytes alone, like on a remote net that
+    // never created one itself.
+    let json = "{\"id\":7,\"name\":\"pear\",\"items\":[]}";
+    let smsg = SerialMessage::from_parts(String::from_str(ORDER), RawMessage::new_fromstr(json));
+    let order = smsg.get_payload::<Order>(ORDER);
+    assert!(order.id == 7 && order.name.as_slice() == "pear" && order.items.len() == 0);
+}
+
+#[test]
+fn serialtcp() {
+    let net1 = Net::new(234);
+    let ep1 = net1.new_endpoint();
+    let net2 = Net::new(875);
+    let ep2 = net2.new_endpoint();
+
+    let mut listener = net1.tcplisten(String::from_str("localhost:34203"));
+    let mut connector = net2.tcpconnect(String::from_str("localhost:34203"));
+
+    while !connector.connected() { }
+    while listener.getnegcount() < 1 { }
+
+    let mut msg = Message::new_serial(ORDER, makeorder());
+    msg.dstsid = 875;
+    msg.dsteid = ep2.geteid();
+    ep1.send(msg);
+
+    let msg = ep2.recvorblock(Duration::seconds(10)).unwrap();
+    assert!(msg.is_serialtag(ORDER));
+    assert!(msg.serialunwrap::<Order>(ORDER) == makeorder());
+
+    listener.terminate();
+    connector.terminate();
+}
@@ -0,0 +1,107 @@
+#![cfg(all(target_os = "linux", target_arch = "x86_64"))]
+#![allow(unused_imports)]
+#![allow(dead_code)]
+#![allow(unused_variables)]
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+
+use water::Net;
+use water::Endpoint;
+use water::Message;
+use water::RawMessage;
+use water::Duration;
+use water::BridgeListener;
+use water::BridgeConnector;
+use water::shm::ShmTransport;
+
+use std::os;
+use std::io::fs::PathExtensions;
+
+fn shmpath(name: &str) -> Path {
+    os::tmpdir().join(format!("water-{}-{}.shm", name, os::getpid()))
+}
+
+#[test]
+fn shmbasic() {
+    let path = shmpath("shmbasic");
+    let addr = String::from_str(path.as_str().unwrap());
+
+    let net1 = Net::new(234);
+    let ep1 = net1.new_endpoint();
+    let net2 = Net::new(875);
+    let ep2 = net2.new_endpoint();
+
+    let mut listener = net1.shmlisten(addr.clone());
+    let mut connector = net2.shmconnect(addr.clone());
+
+    while !connector.connected() { }
+    while listener.getnegcount() < 1 { }
+
+    let mut msg = Message::new_fromraw(RawMessage::new_fromstr("ABCD"));
+    msg.dstsid = 875;
+    msg.dsteid = ep2.geteid();
+    ep1.send(msg);
+
+    let raw = ep2.recvorblock(Duration::seconds(10)).unwrap().get_raw();
+    assert!(raw.as_slice() == b"ABCD");
+
+    // And back the other way.
+    let mut msg = Message::new_fromraw(RawMessage::new_fromstr("EFGH"));
+    msg.dstsid = 234;
+    msg.dsteid = ep1.geteid();
+    ep2.send(msg);
+
+    let raw = ep1.recvorblock(Duration::seconds(10)).unwrap().get_raw();
+    assert!(raw.as_slice() == b"EFGH");
+
+    listener.terminate();
+    connector.terminate();
+
+    // The listener removes the region file.
+    assert!(!path.exists());
+}
+
+#[test]
+fn shmwrap() {
+    let path = shmpath("shmwrap");
+    let addr = String::from_str(path.as_str().unwrap());
+
+    let net1 = Net::new(234);
+    let ep1 = net1.new_endpoint();
+    let net2 = Net::new(875);
+    let ep2 = net2.new_endpoint();
+
+    // A small ring so the messages wrap around it many times and the
+    // writer has to wait for the reader.
+    let mut listener = BridgeListener::new(&net1, Box::new(ShmTransport::with_capacity(addr.clone(), 4096)));
+    let mut connector = BridgeConnector::new(&net2, Box::new(ShmTransport::with_capacity(addr.clone(), 4096)));
+
+    while !connector.connected() { }
+    while listener.getnegcount() < 1 { }
+
+    for x in range(0us, 200us) {
+        let size = 1000 + x * 7;
+        let mut rmsg = RawMessage::new(size);
+        for ndx in range(0us, size) {
+            rmsg.writeu8(ndx, (ndx + x) as u8);
+        }
+        let mut msg = Message::new_fromraw(rmsg);
+        msg.dstsid = 875;
+        msg.dsteid = ep2.geteid();
+        ep1.send(msg);
+    }
+
+    for x in range(0us, 200us) {
+        let raw = ep2.recvorblock(Duration::seconds(10)).unwrap().get_raw();
+        let size = 1000 + x * 7;
+        assert!(raw.len() == size);
+        for ndx in range(0us, size) {
+            assert!(raw.readu8(ndx) == (ndx + x) as u8);
+        }
+    }
+
+    listener.terminate();
+    connector.terminate();
+}
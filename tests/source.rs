@@ -0,0 +1,121 @@
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+
+use water::Net;
+use water::Message;
+use water::Duration;
+use water::SourcePolicy;
+use water::net::UNUSED_ID;
+use water::bridge::frame::Frame;
+use water::bridge::handshake::Handshake;
+use water::bridge::link::readunit;
+use water::bridge::link::writeunit;
+
+use std::io::TcpStream;
+use std::io::timer::sleep;
+
+fn connect(addr: &str) -> TcpStream {
+    // The listener starts on its own thread so it may not be ready yet.
+    loop {
+        match TcpStream::connect(addr) {
+            Ok(stream) => return stream,
+            Err(_) => sleep(Duration::milliseconds(10)),
+        }
+    }
+}
+
+/// Send a message claiming to be from the source net.
+fn sendfrom(stream: &mut TcpStream, srcsid: u64, dstsid: u64) {
+    let mut msg = Message::new_raw(4);
+    msg.srcsid = srcsid;
+    msg.srceid = 7;
+    msg.dstsid = dstsid;
+    writeunit(stream, Frame::from_message(msg).unwrap().encode().as_slice());
+}
+
+#[test]
+fn sourcereject() {
+    let net = Net::new(3001);
+    let ep = net.new_endpoint();
+    let mut listener = net.tcplisten(String::from_str("localhost:34228"));
+    assert!(listener.getsourcepolicy() == SourcePolicy::Reject);
+
+    let mut stream = connect("localhost:34228");
+    readunit(&mut stream, 1024).unwrap();
+    writeunit(&mut stream, Handshake::new(3002).encode().as_slice());
+
+    // It never advertised a route to this net.
+    sendfrom(&mut stream, 3003, 3001);
+    sendfrom(&mut stream, 3002, 3001);
+
+    let msg = ep.recvorblock(Duration::seconds(5)).unwrap();
+    assert!(msg.srcsid == 3002);
+    assert!(msg.srceid == 7);
+    assert!(ep.recvorblock(Duration::milliseconds(100)).is_err());
+    assert!(listener.getspoofed() == 1);
+
+    listener.terminate();
+}
+
+#[test]
+fn sourcerewrite() {
+    let net = Net::new(3011);
+    let ep = net.new_endpoint();
+    let mut listener = net.tcplisten(String::from_str("localhost:34229"));
+    listener.setsourcepolicy(SourcePolicy::Rewrite);
+
+    let mut stream = connect("localhost:34229");
+    readunit(&mut stream, 1024).unwrap();
+    writeunit(&mut stream, Handshake::new(3012).encode().as_slice());
+
+    // Even a net that is claiming to be us.
+    sendfrom(&mut stream, 3011, 3011);
+
+    let msg = ep.recvorblock(Duration::seconds(5)).unwrap();
+    assert!(msg.srcsid == 3012);
+    assert!(msg.srceid == UNUSED_ID);
+    assert!(listener.getspoofed() == 1);
+
+    listener.terminate();
+}
+
+#[test]
+fn sourceadvert() {
+    let net = Net::new(3021);
+    let ep = net.new_endpoint();
+    let mut listener = net.tcplisten(String::from_str("localhost:34242"));
+
+    let mut near = connect("localhost:34242");
+    readunit(&mut near, 1024).unwrap();
+    writeunit(&mut near, Handshake::new(3024).encode().as_slice());
+    writeunit(&mut near, Frame::routes(&[(3024, 0), (3023, 1)]).encode().as_slice());
+
+    let mut far = connect("localhost:34242");
+    readunit(&mut far, 1024).unwrap();
+    writeunit(&mut far, Handshake::new(3022).encode().as_slice());
+    writeunit(&mut far, Frame::routes(&[(3022, 0), (3021, 0), (3023, 2)]).encode().as_slice());
+
+    while net.getnexthop(3023) != Some(3024) {
+        sleep(Duration::milliseconds(10));
+    }
+
+    // Advertising us or a longer route does not let it speak for those nets.
+    sendfrom(&mut far, 3021, 3021);
+    sendfrom(&mut far, 3023, 3021);
+    sendfrom(&mut far, 3022, 3021);
+
+    let msg = ep.recvorblock(Duration::seconds(5)).unwrap();
+    assert!(msg.srcsid == 3022);
+    assert!(listener.getspoofed() == 2);
+
+    sendfrom(&mut near, 3023, 3021);
+
+    let msg = ep.recvorblock(Duration::seconds(5)).unwrap();
+    assert!(msg.srcsid == 3023);
+    assert!(ep.recvorblock(Duration::milliseconds(100)).is_err());
+    assert!(listener.getspoofed() == 2);
+
+    listener.terminate();
+}
@@ -0,0 +1,97 @@
+#![allow(unused_imports)]
+#![allow(dead_code)]
+#![allow(unused_variables)]
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+
+use water::Net;
+use water::Endpoint;
+use water::Message;
+use water::Duration;
+
+use std::thread::Thread;
+use std::io::timer::sleep;
+
+fn rawto(ep: &Endpoint, cap: usize) -> Message {
+    let mut msg = Message::new_raw(cap);
+    msg.dstsid = 1;
+    msg.dsteid = ep.geteid();
+    msg
+}
+
+#[test]
+fn statsmemory() {
+    let net = Net::new(100);
+    let sender = net.new_endpoint();
+    let ep = net.new_endpoint();
+
+    ep.setlimitmemory(64);
+
+    // If receiving did not give the memory back the limit would
+    // eventually reject every message.
+    for _ in range(0us, 1000us) {
+        assert!(sender.send(rawto(&ep, 32)) == 1);
+        assert!(sender.send(rawto(&ep, 32)) == 1);
+        ep.recv().unwrap();
+        ep.recv().unwrap();
+    }
+
+    assert!(ep.stats().bytesqueued == 0);
+}
+
+#[test]
+fn statscounters() {
+    let net = Net::new(100);
+    let sender = net.new_endpoint();
+    let ep = net.new_endpoint();
+
+    ep.setlimitpending(3);
+    for _ in range(0us, 5us) {
+        sender.send(rawto(&ep, 16));
+    }
+
+    let stats = ep.stats();
+    assert!(stats.msgsin == 3);
+    assert!(stats.pending == 3);
+    assert!(stats.peakpending == 3);
+    assert!(stats.bytesqueued == 48);
+    assert!(stats.droppedpending == 2);
+    assert!(stats.droppedmemory == 0);
+
+    ep.recv().unwrap();
+    ep.recv().unwrap();
+
+    let stats = ep.stats();
+    assert!(stats.msgsout == 2);
+    assert!(stats.pending == 1);
+    assert!(stats.peakpending == 3);
+    assert!(stats.bytesqueued == 16);
+
+    let netstats = net.stats();
+    assert!(netstats.endpoints == 2);
+    assert!(netstats.msgsin == 3);
+    assert!(netstats.droppedpending == 2);
+}
+
+#[test]
+fn statssleepers() {
+    let net = Net::new(100);
+    let sender = net.new_endpoint();
+    let ep = net.new_endpoint();
+
+    let epclone = ep.clone();
+    let t = Thread::scoped(move || {
+        epclone.recvorblock(Duration::seconds(10)).unwrap();
+    });
+
+    while ep.stats().sleepers < 1 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    assert!(net.stats().sleepers == 1);
+    sender.send(rawto(&ep, 8));
+    drop(t);
+    assert!(ep.stats().sleepers == 0);
+}
@@ -65,7 +65,7 @@ fn tcpio_do() {
     // We need to get the message as a raw type. This will fail if
     // the message is clone or sync type. Then we get a byte slice
     // and check that the contents are correct.
-    let rawmsg = result.ok().get_raw();
+    let rawmsg = result.unwrap().get_raw();
     let slice = rawmsg.as_slice();
 
     println!("id:{} {}:{}:{}:{}", rawmsg.id(), slice[0], slice[1], slice[2], slice[3]);
@@ -0,0 +1,137 @@
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+extern crate openssl;
+
+use water::Net;
+use water::Message;
+use water::Duration;
+use water::WaterError;
+use water::TlsConfig;
+
+use openssl::x509::X509Generator;
+use openssl::crypto::hash::HashType;
+
+use std::io::File;
+use std::io::timer::sleep;
+
+/// Generate a self-signed certificate with the common name. Returns the paths
+/// of the certificate and key, and the fingerprint of the certificate.
+fn gencert(name: &str) -> (Path, Path, Vec<u8>) {
+    let gen = X509Generator::new()
+        .set_bitlength(2048)
+        .set_valid_period(1)
+        .set_CN(name)
+        .set_sign_hash(HashType::SHA256);
+    let (cert, pkey) = gen.generate().unwrap();
+
+    let certpath = Path::new(format!("/tmp/water-tls-{}.pem", name));
+    let keypath = Path::new(format!("/tmp/water-tls-{}.key", name));
+    cert.write_pem(&mut File::create(&certpath).unwrap()).unwrap();
+    pkey.write_pem(&mut File::create(&keypath).unwrap()).unwrap();
+
+    (certpath, keypath, cert.fingerprint(HashType::SHA256).unwrap())
+}
+
+fn isprotocol(err: Option<WaterError>) -> bool {
+    match err {
+        Some(WaterError::Protocol(_)) => true,
+        _ => false,
+    }
+}
+
+#[test]
+fn tlspinned() {
+    let (cert, key, fingerprint) = gencert("pinned");
+
+    let net1 = Net::new(4001);
+    let net2 = Net::new(4002);
+    let ep1 = net1.new_endpoint();
+
+    let mut listener = net1.tlslisten(String::from_str("localhost:34230"), TlsConfig::with_cert(cert, key));
+    let mut config = TlsConfig::new();
+    config.pins.push(fingerprint);
+    let mut connector = net2.tlsconnect(String::from_str("localhost:34230"), config);
+
+    while listener.getnegcount() < 1 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    let mut msg = Message::new_raw(4);
+    msg.dstsid = 4001;
+    msg.get_rawmutref().as_mutslice()[0] = 0x42;
+    net2.new_endpoint().send(msg);
+
+    let msg = ep1.recvorblock(Duration::seconds(5)).unwrap();
+    assert!(msg.srcsid == 4002);
+    assert!(msg.get_raw().as_slice()[0] == 0x42);
+
+    listener.terminate();
+    connector.terminate();
+}
+
+#[test]
+fn tlsmutual() {
+    let (servercert, serverkey, serverprint) = gencert("server");
+    let (clientcert, clientkey, clientprint) = gencert("client");
+
+    let net1 = Net::new(4011);
+    let net2 = Net::new(4012);
+    let net3 = Net::new(4013);
+
+    // Only the client certificate is accepted and only for net 4012.
+    let mut config = TlsConfig::with_cert(servercert, serverkey);
+    config.requirepeer = true;
+    config.pins.push(clientprint);
+    config.identities.insert(String::from_str("client"), 4012);
+    let mut listener = net1.tlslisten(String::from_str("localhost:34231"), config);
+
+    let mut config = TlsConfig::with_cert(clientcert, clientkey);
+    config.pins.push(serverprint);
+    let mut connector1 = net2.tlsconnect(String::from_str("localhost:34231"), config.clone());
+
+    while listener.getnegcount() < 1 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    // The same certificate can not be used to speak for another net.
+    let mut connector2 = net3.tlsconnect(String::from_str("localhost:34231"), config);
+
+    while listener.gethandshakefailures() < 1 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    assert!(isprotocol(listener.getlasterror()));
+    assert!(listener.getnegcount() == 1);
+    assert!(listener.getconnections()[0].sid == 4012);
+
+    listener.terminate();
+    connector1.terminate();
+    connector2.terminate();
+}
+
+#[test]
+fn tlsnotpinned() {
+    let (cert, key, _) = gencert("unpinned");
+    let (_, _, otherprint) = gencert("other");
+
+    let net1 = Net::new(4021);
+    let net2 = Net::new(4022);
+
+    let mut listener = net1.tlslisten(String::from_str("localhost:34232"), TlsConfig::with_cert(cert, key));
+    let mut config = TlsConfig::new();
+    config.pins.push(otherprint);
+    let mut connector = net2.tlsconnect(String::from_str("localhost:34232"), config);
+
+    while connector.gethandshakefailures() < 1 {
+        sleep(Duration::milliseconds(10));
+    }
+
+    assert!(isprotocol(connector.getlasterror()));
+    assert!(!connector.connected());
+    assert!(listener.getnegcount() == 0);
+
+    listener.terminate();
+    connector.terminate();
+}
@@ -0,0 +1,67 @@
+#![cfg(all(target_os = "linux", target_arch = "x86_64"))]
+#![allow(unused_imports)]
+#![allow(dead_code)]
+#![allow(unused_variables)]
+#![allow(unused_must_use)]
+
+extern crate time;
+extern crate water;
+
+use water::Net;
+use water::Endpoint;
+use water::Message;
+use water::RawMessage;
+use water::Duration;
+
+use std::os;
+use std::io::fs::PathExtensions;
+
+fn unixio(addr: String) {
+    let net1 = Net::new(234);
+    let ep1 = net1.new_endpoint();
+    let net2 = Net::new(875);
+    let ep2 = net2.new_endpoint();
+
+    let mut listener = net1.unixlisten(addr.clone());
+    let mut connector = net2.unixconnect(addr.clone());
+
+    while !connector.connected() { }
+    while listener.getnegcount() < 1 { }
+    assert!(listener.getclientcount() == 1);
+
+    let mut msg = Message::new_fromraw(RawMessage::new_fromstr("ABCD"));
+    msg.dstsid = 875;
+    msg.dsteid = ep2.geteid();
+    ep1.send(msg);
+
+    let raw = ep2.recvorblock(Duration::seconds(10)).unwrap().get_raw();
+    assert!(raw.as_slice() == b"ABCD");
+
+    // And back the other way.
+    let mut msg = Message::new_fromraw(RawMessage::new_fromstr("EFGH"));
+    msg.dstsid = 234;
+    msg.dsteid = ep1.geteid();
+    ep2.send(msg);
+
+    let raw = ep1.recvorblock(Duration::seconds(10)).unwrap().get_raw();
+    assert!(raw.as_slice() == b"EFGH");
+
+    listener.terminate();
+    connector.terminate();
+}
+
+#[test]
+fn unixpath() {
+    let path = os::tmpdir().join(format!("water-unixpath-{}.sock", os::getpid()));
+    let addr = String::from_str(path.as_str().unwrap());
+
+    unixio(addr);
+
+    // The listener removes its socket file.
+    assert!(!path.exists());
+}
+
+#[test]
+fn unixabstract() {
+    unixio(format!("@water-unixabstract-{}", os::getpid()));
+}